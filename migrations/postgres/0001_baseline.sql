@@ -0,0 +1,49 @@
+-- Baseline schema
+-- Mirrors migrations/sqlite/0001_baseline.sql. Timestamps are RFC 3339 text,
+-- as on SQLite, so that both backends share the same queries.
+
+CREATE TABLE users (
+    id TEXT PRIMARY KEY,
+    email TEXT UNIQUE NOT NULL,
+    name TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'approved', 'denied', 'suspended', 'expired')),
+    created_at TEXT NOT NULL,
+    last_login TEXT NOT NULL,
+    status_reason TEXT,
+    status_updated_at TEXT
+);
+
+CREATE TABLE user_permissions (
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users (id),
+    permission TEXT NOT NULL,
+    granted_at TEXT NOT NULL,
+    UNIQUE (user_id, permission)
+);
+
+-- Links each identity provider account (issuer + subject) to a row in users
+CREATE TABLE identities (
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users (id),
+    email TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    last_login TEXT NOT NULL,
+    PRIMARY KEY (issuer, subject)
+);
+
+CREATE INDEX idx_identities_user_id ON identities (user_id);
+
+-- No foreign key on user_id: key records are kept after their user is deleted
+CREATE TABLE auth_keys (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    tags TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    request_ip TEXT,
+    revoked_at TEXT
+);
+
+CREATE INDEX idx_auth_keys_user_id ON auth_keys (user_id);
@@ -0,0 +1,26 @@
+-- Append-only record of authentication and authorization events
+
+CREATE TABLE audit_events (
+    id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    occurred_at TEXT NOT NULL,
+    actor TEXT,
+    subject_user_id TEXT,
+    action TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    ip TEXT,
+    user_agent TEXT,
+    details TEXT NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX idx_audit_events_subject_user_id ON audit_events (subject_user_id);
+CREATE INDEX idx_audit_events_action ON audit_events (action);
+CREATE INDEX idx_audit_events_occurred_at ON audit_events (occurred_at);
+
+CREATE FUNCTION audit_events_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_no_update BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE FUNCTION audit_events_append_only();
@@ -0,0 +1,5 @@
+-- Hash-chain audit events so edits to stored rows can be detected
+-- Events recorded before this migration stay unchained
+
+ALTER TABLE audit_events ADD COLUMN prev_hash TEXT;
+ALTER TABLE audit_events ADD COLUMN hash TEXT;
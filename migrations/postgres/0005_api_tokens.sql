@@ -0,0 +1,19 @@
+-- Personal API tokens letting headless machines generate auth keys for their owner
+-- Only a SHA-256 hash of each token is stored
+
+CREATE TABLE api_tokens (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    last_used_at TEXT,
+    revoked_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX idx_api_tokens_user_id ON api_tokens (user_id);
+
+ALTER TABLE auth_keys ADD COLUMN api_token_id TEXT;
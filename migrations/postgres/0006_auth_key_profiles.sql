@@ -0,0 +1,3 @@
+-- Record the key profile each auth key was issued under
+
+ALTER TABLE auth_keys ADD COLUMN profile TEXT;
@@ -0,0 +1,7 @@
+-- Record the device each auth key was requested for, and whether it was reusable
+-- Older keys leave these NULL
+
+ALTER TABLE auth_keys ADD COLUMN reusable BIGINT;
+ALTER TABLE auth_keys ADD COLUMN hostname TEXT;
+ALTER TABLE auth_keys ADD COLUMN os TEXT;
+ALTER TABLE auth_keys ADD COLUMN purpose TEXT;
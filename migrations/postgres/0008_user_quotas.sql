@@ -0,0 +1,11 @@
+-- Per-user overrides of the configured auth key and device limits
+-- A NULL limit falls back to the configured one; 0 means no limit
+
+CREATE TABLE user_quotas (
+    user_id TEXT PRIMARY KEY,
+    keys_per_hour BIGINT,
+    live_keys BIGINT,
+    devices BIGINT,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
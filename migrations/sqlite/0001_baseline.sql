@@ -0,0 +1,57 @@
+-- Baseline schema
+-- Tables are created only if missing so that databases set up before
+-- migrations existed (brought up to this schema by db::upgrade_legacy_schema)
+-- can adopt this migration as already applied.
+
+CREATE TABLE IF NOT EXISTS users (
+    id TEXT PRIMARY KEY,
+    email TEXT UNIQUE NOT NULL,
+    name TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'approved', 'denied', 'suspended', 'expired')),
+    created_at TEXT NOT NULL,
+    last_login TEXT NOT NULL,
+    status_reason TEXT,
+    status_updated_at TEXT
+);
+
+CREATE TABLE IF NOT EXISTS user_permissions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id TEXT NOT NULL,
+    permission TEXT NOT NULL,
+    granted_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    UNIQUE (user_id, permission)
+);
+
+-- Links each identity provider account (issuer + subject) to a row in users
+CREATE TABLE IF NOT EXISTS identities (
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    email TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    last_login TEXT NOT NULL,
+    PRIMARY KEY (issuer, subject),
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_identities_user_id ON identities (user_id);
+
+-- Users created before identities were tracked are keyed by their Google subject
+INSERT OR IGNORE INTO identities (issuer, subject, user_id, email, created_at, last_login)
+SELECT 'https://accounts.google.com', id, id, email, created_at, last_login FROM users
+WHERE id NOT IN (SELECT user_id FROM identities);
+
+-- No foreign key on user_id: key records are kept after their user is deleted
+CREATE TABLE IF NOT EXISTS auth_keys (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    tags TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    request_ip TEXT,
+    revoked_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_auth_keys_user_id ON auth_keys (user_id);
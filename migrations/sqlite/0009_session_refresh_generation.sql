@@ -0,0 +1,4 @@
+-- Counts the refreshes of each session; only the newest refresh token matches it,
+-- so a replayed older one is detected and ends the session
+
+ALTER TABLE sessions ADD COLUMN refresh_generation INTEGER NOT NULL DEFAULT 0;
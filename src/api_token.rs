@@ -0,0 +1,224 @@
+use anyhow::{Result, anyhow};
+use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use tracing::warn;
+use crate::config::get_config;
+use crate::models::{User, ApiToken, CreateApiTokenRequest, API_TOKEN_SCOPES};
+use crate::store::Store;
+
+/// Prefix of every API token, which tells them apart from ID and session tokens
+pub const API_TOKEN_PREFIX: &str = "lowa_";
+
+/// Longest name an API token may be given
+const MAX_NAME_LENGTH: usize = 64;
+
+/// A request for a new API token that cannot be granted as asked
+#[derive(Debug)]
+pub struct InvalidApiTokenRequest(pub String);
+
+impl fmt::Display for InvalidApiTokenRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidApiTokenRequest {}
+
+/// Check whether a token has the form of an API token
+pub fn is_api_token(token: &str) -> bool {
+    token.starts_with(API_TOKEN_PREFIX)
+}
+
+fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Create an API token for a user, returning its record and the token itself
+///
+/// Only the token's hash is stored, so this is the one time it can be read.
+/// Fails with `InvalidApiTokenRequest` when the name, scopes or expiry are not allowed.
+pub async fn create(store: &dyn Store, user: &User, request: &CreateApiTokenRequest) -> Result<(ApiToken, String)> {
+    let config = &get_config().api_tokens;
+
+    let name = request.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(InvalidApiTokenRequest(format!("Token names must be 1 to {} characters long", MAX_NAME_LENGTH)).into());
+    }
+    if store.list_api_tokens(&user.id).await?.iter().any(|token| token.name == name) {
+        return Err(InvalidApiTokenRequest(format!("You already have a token named '{}'", name)).into());
+    }
+
+    let scopes = match &request.scopes {
+        Some(scopes) if scopes.is_empty() => {
+            return Err(InvalidApiTokenRequest("A token needs at least one scope".to_string()).into());
+        }
+        Some(scopes) => scopes.clone(),
+        None => API_TOKEN_SCOPES.map(str::to_string).to_vec(),
+    };
+    if let Some(unknown) = scopes.iter().find(|scope| !API_TOKEN_SCOPES.contains(&scope.as_str())) {
+        return Err(InvalidApiTokenRequest(format!(
+            "Unknown scope '{}', expected one of {}", unknown, API_TOKEN_SCOPES.join(", ")
+        )).into());
+    }
+
+    let days = request.expires_in_days.unwrap_or(config.default_ttl_days);
+    if days == 0 || days > config.max_ttl_days {
+        return Err(InvalidApiTokenRequest(format!("Tokens must expire within 1 to {} days", config.max_ttl_days)).into());
+    }
+
+    let secret = format!("{}{}", API_TOKEN_PREFIX, hex::encode(rand::random::<[u8; 32]>()));
+    let now = Utc::now();
+    let token = ApiToken {
+        id: format!("{:032x}", rand::random::<u128>()),
+        user_id: user.id.clone(),
+        name: name.to_string(),
+        scopes,
+        created_at: now,
+        expires_at: now + Duration::days(days as i64),
+        last_used_at: None,
+        revoked_at: None,
+    };
+
+    store.insert_api_token(&token, &hash(&secret)).await?;
+
+    Ok((token, secret))
+}
+
+/// Look up the live API token presented by a caller and check it carries a scope
+pub async fn authenticate(store: &dyn Store, secret: &str, scope: &str) -> Result<ApiToken> {
+    let token = store.find_api_token_by_hash(&hash(secret)).await?
+        .ok_or_else(|| anyhow!("Unknown API token"))?;
+
+    if token.revoked_at.is_some() {
+        return Err(anyhow!("API token '{}' has been revoked", token.name));
+    }
+    if token.expires_at <= Utc::now() {
+        return Err(anyhow!("API token '{}' has expired", token.name));
+    }
+    if !token.has_scope(scope) {
+        return Err(anyhow!("API token '{}' does not have the {} scope", token.name, scope));
+    }
+
+    if let Err(e) = store.touch_api_token(&token.id).await {
+        warn!("Failed to update last use of API token {}: {}", token.id, e);
+    }
+
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+    use crate::memory_store::MemoryStore;
+    use crate::models::{UserStatus, SCOPE_GENERATE_KEY};
+    use crate::store::ApiTokenStore;
+
+    fn user() -> User {
+        let now = Utc::now();
+        User {
+            id: "user".to_string(),
+            email: "alice@example.com".to_string(),
+            name: "Alice".to_string(),
+            status: UserStatus::Approved,
+            created_at: now,
+            last_login: now,
+            status_reason: None,
+            status_updated_at: None,
+        }
+    }
+
+    fn request(name: &str) -> CreateApiTokenRequest {
+        CreateApiTokenRequest { name: name.to_string(), scopes: None, expires_in_days: None }
+    }
+
+    async fn create_token(store: &MemoryStore, request: CreateApiTokenRequest) -> (ApiToken, String) {
+        init_test_config();
+        create(store, &user(), &request).await.unwrap()
+    }
+
+    fn error_message(result: Result<impl fmt::Debug>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[tokio::test]
+    async fn only_the_hash_of_a_token_is_stored() {
+        let store = MemoryStore::new();
+        let (token, secret) = create_token(&store, request("ci")).await;
+
+        assert!(is_api_token(&secret));
+        assert_eq!(token.scopes, vec![SCOPE_GENERATE_KEY.to_string()]);
+        assert!(store.find_api_token_by_hash(&secret).await.unwrap().is_none());
+        assert_eq!(store.find_api_token_by_hash(&hash(&secret)).await.unwrap().unwrap().id, token.id);
+        assert_eq!(hash(&secret).len(), 64);
+    }
+
+    #[tokio::test]
+    async fn a_live_token_authenticates_and_records_its_use() {
+        let store = MemoryStore::new();
+        let (token, secret) = create_token(&store, request("ci")).await;
+
+        let authenticated = authenticate(&store, &secret, SCOPE_GENERATE_KEY).await.unwrap();
+
+        assert_eq!(authenticated.id, token.id);
+        assert!(store.find_api_token(&token.id).await.unwrap().unwrap().last_used_at.is_some());
+        assert!(authenticate(&store, &format!("{}x", secret), SCOPE_GENERATE_KEY).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn tokens_need_the_requested_scope() {
+        let store = MemoryStore::new();
+        let (_, secret) = create_token(&store, request("ci")).await;
+
+        assert!(error_message(authenticate(&store, &secret, "admin").await).contains("does not have the admin scope"));
+    }
+
+    #[tokio::test]
+    async fn revoked_tokens_are_refused() {
+        let store = MemoryStore::new();
+        let (token, secret) = create_token(&store, request("ci")).await;
+
+        assert!(store.revoke_api_token(&token.id).await.unwrap());
+
+        assert!(error_message(authenticate(&store, &secret, SCOPE_GENERATE_KEY).await).contains("revoked"));
+    }
+
+    #[tokio::test]
+    async fn expired_tokens_are_refused() {
+        let store = MemoryStore::new();
+        let secret = format!("{}expired", API_TOKEN_PREFIX);
+        let now = Utc::now();
+        store.insert_api_token(&ApiToken {
+            id: "expired".to_string(),
+            user_id: "user".to_string(),
+            name: "old".to_string(),
+            scopes: vec![SCOPE_GENERATE_KEY.to_string()],
+            created_at: now - Duration::days(2),
+            expires_at: now - Duration::seconds(1),
+            last_used_at: None,
+            revoked_at: None,
+        }, &hash(&secret)).await.unwrap();
+
+        assert!(error_message(authenticate(&store, &secret, SCOPE_GENERATE_KEY).await).contains("expired"));
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_refused() {
+        init_test_config();
+        let store = MemoryStore::new();
+        create_token(&store, request("ci")).await;
+        let refused = |request: CreateApiTokenRequest| {
+            let store = &store;
+            async move { create(store, &user(), &request).await.unwrap_err().is::<InvalidApiTokenRequest>() }
+        };
+
+        assert!(refused(request("ci")).await);
+        assert!(refused(request(" ")).await);
+        assert!(refused(CreateApiTokenRequest { scopes: Some(vec![]), ..request("a") }).await);
+        assert!(refused(CreateApiTokenRequest { scopes: Some(vec!["admin".to_string()]), ..request("b") }).await);
+        assert!(refused(CreateApiTokenRequest { expires_in_days: Some(0), ..request("c") }).await);
+        let too_long = get_config().api_tokens.max_ttl_days + 1;
+        assert!(refused(CreateApiTokenRequest { expires_in_days: Some(too_long), ..request("d") }).await);
+    }
+}
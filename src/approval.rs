@@ -0,0 +1,214 @@
+use anyhow::{Result, anyhow};
+use regex::Regex;
+use std::sync::OnceLock;
+use crate::config::{get_config, ApprovalConfig, EmailMatcher};
+use crate::models::UserStatus;
+
+/// Name recorded when the denylist decides a user's status
+const DENYLIST_RULE: &str = "denylist";
+
+/// An email matcher with its patterns compiled
+struct CompiledMatcher {
+    emails: Vec<String>,
+    domains: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl CompiledMatcher {
+    fn new(matcher: &EmailMatcher, rule: &str) -> Result<Self> {
+        // Patterns must match the whole address, so "admin@example.com" cannot match "admin@example.com.evil.io"
+        let patterns = matcher.patterns.iter()
+            .map(|pattern| Regex::new(&format!("^(?:{})$", pattern))
+                .map_err(|e| anyhow!("Approval rule {} has an invalid pattern {}: {}", rule, pattern, e)))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(CompiledMatcher {
+            emails: matcher.emails.iter().map(|email| email.to_lowercase()).collect(),
+            domains: matcher.domains.iter()
+                .map(|domain| domain.trim_start_matches('.').to_lowercase())
+                .collect(),
+            patterns,
+        })
+    }
+
+    /// Check a lowercased email address against the matcher
+    fn matches(&self, email: &str) -> bool {
+        let domain = email.rsplit_once('@').map(|(_, domain)| domain).unwrap_or("");
+
+        self.emails.iter().any(|allowed| allowed == email)
+            || self.domains.iter().any(|suffix| {
+                domain == suffix || domain.strip_suffix(suffix.as_str()).is_some_and(|rest| rest.ends_with('.'))
+            })
+            || self.patterns.iter().any(|pattern| pattern.is_match(email))
+    }
+}
+
+struct CompiledRule {
+    name: String,
+    status: UserStatus,
+    matcher: CompiledMatcher,
+}
+
+struct ApprovalRules {
+    denylist: CompiledMatcher,
+    rules: Vec<CompiledRule>,
+}
+
+/// The outcome of evaluating the approval rules for a new user
+pub struct ApprovalDecision<'a> {
+    pub status: UserStatus,
+    /// Name of the rule that fired, or None when no rule matched
+    pub rule: Option<&'a str>,
+}
+
+// Approval rules, compiled once from configuration
+static RULES: OnceLock<ApprovalRules> = OnceLock::new();
+
+fn build_rules(config: &ApprovalConfig) -> Result<ApprovalRules> {
+    let rules = config.rules.iter()
+        .map(|rule| {
+            if !rule.status.is_initial() {
+                return Err(anyhow!("Approval rule {} assigns {}, which new users cannot have", rule.name, rule.status));
+            }
+
+            Ok(CompiledRule {
+                name: rule.name.clone(),
+                status: rule.status,
+                matcher: CompiledMatcher::new(&rule.matcher, &rule.name)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(ApprovalRules {
+        denylist: CompiledMatcher::new(&config.denylist, DENYLIST_RULE)?,
+        rules,
+    })
+}
+
+fn get_rules() -> &'static ApprovalRules {
+    RULES.get_or_init(|| {
+        build_rules(&get_config().approval).unwrap_or_else(|e| {
+            eprintln!("Failed to set up approval rules: {}", e);
+            std::process::exit(1);
+        })
+    })
+}
+
+/// Compile the configured rules, exiting on an invalid one
+///
+/// Called at startup so a bad pattern is reported before anyone signs in.
+/// Returns the number of rules.
+pub fn load_rules() -> usize {
+    get_rules().rules.len()
+}
+
+/// Decide the initial status of a user from their email address
+///
+/// The denylist is checked first, then the rules in order. A user no rule
+/// matches is left pending for an administrator. An email the identity
+/// provider did not verify could be anyone's, so only rules denying it apply.
+pub fn evaluate(email: &str, email_verified: bool) -> ApprovalDecision<'static> {
+    get_rules().evaluate(email, email_verified)
+}
+
+impl ApprovalRules {
+    fn evaluate(&self, email: &str, email_verified: bool) -> ApprovalDecision<'_> {
+        let email = email.to_lowercase();
+
+        if self.denylist.matches(&email) {
+            return ApprovalDecision { status: UserStatus::Denied, rule: Some(DENYLIST_RULE) };
+        }
+
+        self.rules.iter()
+            .filter(|rule| email_verified || rule.status == UserStatus::Denied)
+            .find(|rule| rule.matcher.matches(&email))
+            .map(|rule| ApprovalDecision {
+                status: rule.status,
+                rule: Some(rule.name.as_str()),
+            })
+            .unwrap_or(ApprovalDecision { status: UserStatus::Pending, rule: None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(patterns: &[&str]) -> CompiledMatcher {
+        let matcher = EmailMatcher {
+            patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            ..EmailMatcher::default()
+        };
+        CompiledMatcher::new(&matcher, "test").unwrap()
+    }
+
+    #[test]
+    fn patterns_match_the_whole_address() {
+        let matcher = matcher(&[r"[^@]+@example\.com"]);
+
+        assert!(matcher.matches("alice@example.com"));
+        assert!(!matcher.matches("alice@example.com.evil.io"));
+        assert!(!matcher.matches("alice@example.company"));
+    }
+
+    #[test]
+    fn alternatives_are_anchored_together() {
+        let matcher = matcher(&["alice@example.com|bob@example.com"]);
+
+        assert!(matcher.matches("bob@example.com"));
+        assert!(!matcher.matches("alice@example.com.evil.io"));
+        assert!(!matcher.matches("evil-bob@example.com"));
+    }
+
+    #[test]
+    fn explicit_anchors_still_work() {
+        let matcher = matcher(&[r"^[^@]+\+contractor@example\.com$"]);
+
+        assert!(matcher.matches("alice+contractor@example.com"));
+        assert!(!matcher.matches("alice@example.com"));
+    }
+
+    fn rules() -> ApprovalRules {
+        let config: ApprovalConfig = serde_json::from_value(serde_json::json!({
+            "denylist": { "emails": ["mallory@example.com"] },
+            "rules": [
+                { "name": "contractors", "status": "denied", "domains": ["contractors.example.com"] },
+                { "name": "staff", "status": "approved", "domains": ["example.com"] },
+            ],
+        })).unwrap();
+        build_rules(&config).unwrap()
+    }
+
+    #[test]
+    fn first_matching_rule_decides_verified_emails() {
+        let rules = rules();
+
+        assert_eq!(rules.evaluate("Alice@Example.com", true).status, UserStatus::Approved);
+        assert_eq!(rules.evaluate("bob@contractors.example.com", true).rule, Some("contractors"));
+        assert_eq!(rules.evaluate("mallory@example.com", true).rule, Some(DENYLIST_RULE));
+        assert_eq!(rules.evaluate("carol@elsewhere.org", true).status, UserStatus::Pending);
+    }
+
+    #[test]
+    fn unverified_emails_are_only_denied() {
+        let rules = rules();
+
+        let decision = rules.evaluate("alice@example.com", false);
+        assert_eq!(decision.status, UserStatus::Pending);
+        assert_eq!(decision.rule, None);
+        assert_eq!(rules.evaluate("bob@contractors.example.com", false).status, UserStatus::Denied);
+        assert_eq!(rules.evaluate("mallory@example.com", false).status, UserStatus::Denied);
+    }
+
+    #[test]
+    fn domains_match_subdomains_only_on_a_label_boundary() {
+        let matcher = CompiledMatcher::new(&EmailMatcher {
+            domains: vec![".Example.com".to_string()],
+            ..EmailMatcher::default()
+        }, "test").unwrap();
+
+        assert!(matcher.matches("alice@example.com"));
+        assert!(matcher.matches("alice@eng.example.com"));
+        assert!(!matcher.matches("alice@badexample.com"));
+    }
+}
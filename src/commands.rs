@@ -0,0 +1,339 @@
+// Administrative subcommands that act on the database without starting the server
+
+use anyhow::{Result, anyhow};
+use sqlx::AnyPool;
+use crate::config::{Command, UsersCommand, PermissionsCommand, DevicesCommand, DbCommand, AuditCommand};
+use serde_json::json;
+use crate::{audit, db, network, devices, oidc, quota, session};
+use crate::store::{SqlStore, Store};
+use crate::models::{User, UserStatus, QuotaRequest, VerifiedIdentity, AuditContext, AuditEvent, AuditAction, AuditOutcome};
+
+/// Run an administrative subcommand
+pub async fn run(pool: &AnyPool, command: Command) -> Result<()> {
+    // Everything but the db commands expects an up-to-date schema
+    if !matches!(command, Command::Db { .. }) {
+        db::migrate(pool).await?;
+    }
+
+    let store = SqlStore::new(pool.clone());
+
+    match command {
+        Command::Users { command } => run_users(&store, command).await,
+        Command::Permissions { command } => run_permissions(&store, command).await,
+        Command::Devices { command } => run_devices(&store, command).await,
+        Command::Db { command } => run_db(pool, command).await,
+        Command::Audit { command } => run_audit(&store, command).await,
+    }
+}
+
+async fn run_users(store: &dyn Store, command: UsersCommand) -> Result<()> {
+    match command {
+        UsersCommand::List { status } => {
+            for user in store.list_users(status).await? {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    user.id,
+                    user.email,
+                    user.status,
+                    user.last_login.to_rfc3339(),
+                    user.status_reason.as_deref().unwrap_or(""),
+                );
+            }
+        }
+        UsersCommand::Approve { email, reason } => set_status(store, &email, UserStatus::Approved, reason).await?,
+        UsersCommand::Deny { email, reason } => set_status(store, &email, UserStatus::Denied, reason).await?,
+        UsersCommand::Pend { email, reason } => set_status(store, &email, UserStatus::Pending, reason).await?,
+        UsersCommand::Suspend { email, reason } => set_status(store, &email, UserStatus::Suspended, reason).await?,
+        UsersCommand::Expire { email, reason } => set_status(store, &email, UserStatus::Expired, reason).await?,
+        UsersCommand::Delete { email } => {
+            let user = find_user(store, &email).await?;
+            // A deleted user's keys, sessions and machines go with them. Keys come first:
+            // once the user is gone, their keys can no longer be found to revoke
+            let keys_revoked = revoke_keys(store, &user).await;
+            end_sessions(store, &user).await?;
+            let devices_removed = remove_devices(store, &user).await;
+            if let Err(e) = keys_revoked {
+                audit::record(store, AuditEvent {
+                    subject_user_id: Some(user.id.clone()),
+                    details: json!({ "email": user.email, "reason": "key_revocation_failed" }),
+                    ..AuditContext::cli().event(AuditAction::DeleteUser, AuditOutcome::Failure)
+                }).await;
+                return Err(anyhow!("User {} was not deleted: {}", user.email, e));
+            }
+            store.delete_user(&user.id).await?;
+            println!("User {} deleted", user.email);
+            audit::record(store, AuditEvent {
+                subject_user_id: Some(user.id.clone()),
+                details: json!({ "email": user.email }),
+                ..AuditContext::cli().event(AuditAction::DeleteUser, AuditOutcome::Success)
+            }).await;
+            devices_removed?;
+        }
+        UsersCommand::RevokeKeys { email } => {
+            let user = find_user(store, &email).await?;
+            revoke_keys(store, &user).await?;
+        }
+        UsersCommand::EndSessions { email } => {
+            let user = find_user(store, &email).await?;
+            end_sessions(store, &user).await?;
+        }
+        UsersCommand::Quota { email } => {
+            let user = find_user(store, &email).await?;
+            print_quota(store, &user).await?;
+        }
+        UsersCommand::SetQuota { email, keys_per_hour, live_keys, devices } => {
+            let user = find_user(store, &email).await?;
+            let request = QuotaRequest { keys_per_hour, live_keys, devices };
+            quota::set(store, &user.id, &request, &AuditContext::cli()).await?;
+            println!("Updated quota of user {}", user.email);
+            print_quota(store, &user).await?;
+        }
+        UsersCommand::ClearQuota { email } => {
+            let user = find_user(store, &email).await?;
+            if !quota::clear(store, &user.id, &AuditContext::cli()).await? {
+                return Err(anyhow!("User {} has no quota overrides", user.email));
+            }
+            println!("User {} now has the configured limits", user.email);
+        }
+        UsersCommand::LinkIdentity { email, issuer, subject } => {
+            let user = find_user(store, &email).await?;
+            link_identity(store, &user, &issuer, &subject).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_permissions(store: &dyn Store, command: PermissionsCommand) -> Result<()> {
+    match command {
+        PermissionsCommand::List { email } => {
+            let user = find_user(store, &email).await?;
+            for grant in store.list_permissions(&user.id).await? {
+                println!("{}\t{}", grant.permission, grant.granted_at.to_rfc3339());
+            }
+        }
+        PermissionsCommand::Grant { email, permission } => {
+            let user = find_user(store, &email).await?;
+            if store.grant_permission(&user.id, &permission).await? {
+                println!("Granted {} to user {}", permission, user.email);
+                audit::record(store, AuditEvent {
+                    subject_user_id: Some(user.id.clone()),
+                    details: json!({ "permission": permission }),
+                    ..AuditContext::cli().event(AuditAction::GrantPermission, AuditOutcome::Success)
+                }).await;
+            } else {
+                println!("User {} already has {}", user.email, permission);
+            }
+        }
+        PermissionsCommand::Revoke { email, permission } => {
+            let user = find_user(store, &email).await?;
+            if !store.revoke_permission(&user.id, &permission).await? {
+                return Err(anyhow!("User {} does not have {}", user.email, permission));
+            }
+            println!("Revoked {} from user {}", permission, user.email);
+            audit::record(store, AuditEvent {
+                subject_user_id: Some(user.id.clone()),
+                details: json!({ "permission": permission }),
+                ..AuditContext::cli().event(AuditAction::RevokePermission, AuditOutcome::Success)
+            }).await;
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_devices(store: &dyn Store, command: DevicesCommand) -> Result<()> {
+    match command {
+        DevicesCommand::List { email } => {
+            let user = find_user(store, &email).await?;
+            let attributed = devices::list_user_devices(store, &user.id).await?;
+            let listed = attributed.devices.iter().map(|device| (device, ""))
+                .chain(attributed.ambiguous.iter().map(|device| (device, "\tambiguous owner")));
+            for (device, note) in listed {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}{}",
+                    device.id,
+                    device.name,
+                    device.tags.join(","),
+                    device.created,
+                    if device.authorized { "authorized" } else { "unauthorized" },
+                    note,
+                );
+            }
+        }
+        DevicesCommand::Remove { email } => {
+            let user = find_user(store, &email).await?;
+            remove_devices(store, &user).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_db(pool: &AnyPool, command: DbCommand) -> Result<()> {
+    match command {
+        DbCommand::Migrate => {
+            let applied = db::migrate(pool).await?;
+            if applied.is_empty() {
+                println!("Database is up to date");
+            }
+            for migration in applied {
+                println!("Applied migration {} {}", migration.version, migration.description);
+            }
+        }
+        DbCommand::Status => {
+            for migration in db::migration_status(pool).await? {
+                let state = match (&migration.installed_on, migration.checksum_matches) {
+                    (None, _) => "pending".to_string(),
+                    (Some(installed_on), true) => format!("applied {}", installed_on),
+                    (Some(installed_on), false) => format!("applied {}, but modified since", installed_on),
+                };
+                println!("{}\t{}\t{}", migration.version, migration.description, state);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_audit(store: &dyn Store, command: AuditCommand) -> Result<()> {
+    match command {
+        AuditCommand::Verify { checkpoints } => {
+            let checkpoints = match checkpoints {
+                Some(path) => audit::read_checkpoints(&path)?,
+                None => Vec::new(),
+            };
+            let report = audit::verify_chain(store, &checkpoints).await?;
+
+            if report.unchained > 0 {
+                println!("{} audit event(s) predate hash chaining and were not verified", report.unchained);
+            }
+            match report.head {
+                Some((id, hash)) => println!("Verified {} chained audit event(s) up to event {} ({})", report.events, id, hash),
+                None => println!("No chained audit events to verify"),
+            }
+            if !checkpoints.is_empty() {
+                println!("Matched {} checkpoint(s)", checkpoints.len());
+            }
+        }
+        AuditCommand::Checkpoint => println!("{}", audit::create_checkpoint(store).await?),
+    }
+
+    Ok(())
+}
+
+async fn set_status(store: &dyn Store, email: &str, status: UserStatus, reason: Option<String>) -> Result<()> {
+    let user = find_user(store, email).await?;
+    let user = store.set_user_status(&user.id, status, reason.as_deref()).await?
+        .ok_or_else(|| anyhow!("User {} disappeared while updating", email))?;
+
+    println!("User {} is now {}", user.email, user.status);
+    audit::record(store, AuditEvent {
+        subject_user_id: Some(user.id.clone()),
+        details: json!({ "status": status, "reason": reason }),
+        ..AuditContext::cli().event(AuditAction::SetStatus, AuditOutcome::Success)
+    }).await;
+
+    // Users who lose access must not keep using keys or devices enrolled while they were approved
+    let keys_revoked = if status.revokes_keys() { revoke_keys(store, &user).await } else { Ok(()) };
+    if status.ends_sessions() {
+        end_sessions(store, &user).await?;
+    }
+    if status.removes_devices() {
+        remove_devices(store, &user).await?;
+    }
+    keys_revoked?;
+
+    Ok(())
+}
+
+async fn revoke_keys(store: &dyn Store, user: &User) -> Result<()> {
+    let (revoked, failures) = network::revoke_user_keys(store, &user.id, &AuditContext::cli()).await?;
+
+    for key_id in &revoked {
+        println!("Revoked auth key {}", key_id);
+    }
+    for failure in &failures {
+        eprintln!("Failed to revoke auth key {}: {}", failure.key_id, failure.error);
+    }
+
+    if !failures.is_empty() {
+        return Err(anyhow!("{} auth key(s) of user {} could not be revoked; run `users revoke-keys {}` to retry", failures.len(), user.email, user.email));
+    }
+
+    Ok(())
+}
+
+async fn end_sessions(store: &dyn Store, user: &User) -> Result<()> {
+    let ended = session::end_user_sessions(store, &user.id, &AuditContext::cli()).await?;
+    println!("Ended {} session(s) of user {}", ended.len(), user.email);
+    Ok(())
+}
+
+async fn print_quota(store: &dyn Store, user: &User) -> Result<()> {
+    let overrides = store.find_user_quota(&user.id).await?;
+    let limits = quota::effective_limits(overrides.as_ref());
+    let usage = quota::usage(store, &user.id).await?;
+    let limit = |value: u32, overridden: Option<u32>| {
+        let value = if value == 0 { "unlimited".to_string() } else { value.to_string() };
+        if overridden.is_some() { format!("{} (override)", value) } else { value }
+    };
+
+    println!("keys_per_hour\t{}\tused {}", limit(limits.keys_per_hour, overrides.as_ref().and_then(|quota| quota.keys_per_hour)), usage.keys_last_hour);
+    println!("live_keys\t{}\tused {}", limit(limits.live_keys, overrides.as_ref().and_then(|quota| quota.live_keys)), usage.live_keys);
+    println!("devices\t{}", limit(limits.devices, overrides.as_ref().and_then(|quota| quota.devices)));
+    Ok(())
+}
+
+async fn remove_devices(store: &dyn Store, user: &User) -> Result<()> {
+    let (removed, failures) = devices::remove_user_devices(store, &user.id, &AuditContext::cli()).await?;
+
+    for device in &removed {
+        println!("Removed device {} ({})", device.id, device.name);
+    }
+    for failure in &failures {
+        eprintln!("Failed to remove device {}: {}", failure.device_id, failure.error);
+    }
+
+    if !failures.is_empty() {
+        return Err(anyhow!("{} device(s) of user {} could not be removed; run `devices remove {}` to retry", failures.len(), user.email, user.email));
+    }
+
+    Ok(())
+}
+
+async fn link_identity(store: &dyn Store, user: &User, issuer: &str, subject: &str) -> Result<()> {
+    let provider = oidc::find_provider(issuer)
+        .ok_or_else(|| anyhow!("No identity provider with issuer {}", issuer))?;
+
+    if let Some(linked) = store.find_user_by_identity(provider.issuer(), subject).await? {
+        if linked.id == user.id {
+            println!("Account {} of {} is already linked to user {}", subject, provider.name(), user.email);
+            return Ok(());
+        }
+        return Err(anyhow!("Account {} of {} is already linked to user {}", subject, provider.name(), linked.email));
+    }
+
+    let identity = VerifiedIdentity {
+        issuer: provider.issuer().to_string(),
+        subject: subject.to_string(),
+        email: user.email.clone(),
+        name: None,
+        email_verified: provider.verifies_email(),
+    };
+    store.link_identity(&user.id, &identity).await?;
+    println!("Linked account {} of {} to user {}", subject, provider.name(), user.email);
+
+    audit::record(store, AuditEvent {
+        subject_user_id: Some(user.id.clone()),
+        details: json!({ "issuer": identity.issuer, "subject": identity.subject }),
+        ..AuditContext::cli().event(AuditAction::LinkIdentity, AuditOutcome::Success)
+    }).await;
+
+    Ok(())
+}
+
+async fn find_user(store: &dyn Store, email: &str) -> Result<User> {
+    store.find_user_by_email(email).await?
+        .ok_or_else(|| anyhow!("No user with email {}", email))
+}
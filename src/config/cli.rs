@@ -3,7 +3,7 @@
 use clap::Parser;
 use config::{ConfigBuilder, ConfigError, Map, Source, Value, ValueKind};
 use config::builder::DefaultState;
-use crate::config::models::CliArgs;
+use crate::config::models::{CliArgs, Command};
 
 /// CLI Source that implements the config::Source trait
 #[derive(Debug, Clone)]
@@ -27,9 +27,33 @@ impl Source for CliSource {
         if let Some(log_level) = &self.cli_args.log_level {
             map.insert("server.log_level".to_string(), Value::new(None, ValueKind::String(log_level.clone())));
         }
+        if !self.cli_args.trusted_proxies.is_empty() {
+            let array_values: Vec<Value> = self.cli_args.trusted_proxies
+                .iter()
+                .map(|s| Value::new(None, ValueKind::String(s.clone())))
+                .collect();
+            map.insert("server.trusted_proxies".to_string(), Value::new(None, ValueKind::Array(array_values)));
+        }
+        if !self.cli_args.cors_allowed_origins.is_empty() {
+            let array_values: Vec<Value> = self.cli_args.cors_allowed_origins
+                .iter()
+                .map(|s| Value::new(None, ValueKind::String(s.clone())))
+                .collect();
+            map.insert("server.cors.allowed_origins".to_string(), Value::new(None, ValueKind::Array(array_values)));
+        }
+        if let Some(allow_credentials) = self.cli_args.cors_allow_credentials {
+            map.insert("server.cors.allow_credentials".to_string(), Value::new(None, ValueKind::Boolean(allow_credentials)));
+        }
         if let Some(client_id) = &self.cli_args.google_client_id {
             map.insert("google.client_id".to_string(), Value::new(None, ValueKind::String(client_id.clone())));
         }
+        if !self.cli_args.google_allowed_hosted_domains.is_empty() {
+            let array_values: Vec<Value> = self.cli_args.google_allowed_hosted_domains
+                .iter()
+                .map(|s| Value::new(None, ValueKind::String(s.clone())))
+                .collect();
+            map.insert("google.allowed_hosted_domains".to_string(), Value::new(None, ValueKind::Array(array_values)));
+        }
         if let Some(oauth_secret_path) = &self.cli_args.tailscale_oauth_secret_path {
             map.insert("tailscale.oauth_secret_path".to_string(), Value::new(None, ValueKind::String(oauth_secret_path.clone())));
         }
@@ -46,6 +70,16 @@ impl Source for CliSource {
         if let Some(db_path) = &self.cli_args.database_path {
             map.insert("database.path".to_string(), Value::new(None, ValueKind::String(db_path.clone())));
         }
+        if let Some(db_url) = &self.cli_args.database_url {
+            map.insert("database.url".to_string(), Value::new(None, ValueKind::String(db_url.clone())));
+        }
+        if !self.cli_args.admin_emails.is_empty() {
+            let array_values: Vec<Value> = self.cli_args.admin_emails
+                .iter()
+                .map(|s| Value::new(None, ValueKind::String(s.clone())))
+                .collect();
+            map.insert("admin.emails".to_string(), Value::new(None, ValueKind::Array(array_values)));
+        }
 
         Ok(map)
     }
@@ -57,6 +91,12 @@ pub fn get_config_file_path() -> String {
     cli_args.config
 }
 
+/// Get the administrative subcommand from CLI arguments, if one was given
+pub fn get_command() -> Option<Command> {
+    let cli_args = CliArgs::parse();
+    cli_args.command
+}
+
 /// Load configuration from CLI arguments
 pub fn load_from_cli(builder: ConfigBuilder<DefaultState>) -> ConfigBuilder<DefaultState> {
     let cli_args = CliArgs::parse();
@@ -8,10 +8,16 @@ use config::builder::DefaultState;
 pub fn set_defaults(builder: ConfigBuilder<DefaultState>)
     -> Result<ConfigBuilder<DefaultState>, ConfigError>
 {
-    Ok(builder
+    builder
         .set_default("server.bind_address", "0.0.0.0:3000")?
         .set_default("server.log_level", "info")?
+        .set_default("server.cors.allowed_origins", Vec::<String>::new())?
+        .set_default("server.cors.allowed_methods", vec!["GET", "POST", "PUT", "DELETE"])?
+        .set_default("server.cors.allowed_headers", vec!["authorization", "content-type"])?
+        .set_default("server.cors.max_age_seconds", 3600)?
+        .set_default("server.cors.allow_credentials", false)?
         .set_default("tailscale.api_url", "https://api.tailscale.com/api/v2")?
-        .set_default("database.path", "sso.db")?)
-    // Note: google.client_id and tailscale.oauth_secret_path are REQUIRED (no defaults)
+        .set_default("database.path", "sso.db")
+    // Note: tailscale.oauth_secret_path is REQUIRED (no default), as is at least one
+    // identity provider (google.client_id or an [[identity_providers]] entry)
 }
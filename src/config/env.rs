@@ -12,6 +12,9 @@ use config::builder::DefaultState;
 /// - LOW_ACCESS_GOOGLE__CLIENT_ID="your-client-id.apps.googleusercontent.com"
 /// - LOW_ACCESS_TAILSCALE__API_URL="https://api.tailscale.com/api/v2"
 /// - LOW_ACCESS_DATABASE__PATH="/var/lib/sso/sso.db"
+/// - LOW_ACCESS_SERVER__CORS__ALLOWED_ORIGINS="https://a.example.com,https://b.example.com"
+///
+/// The CORS lists are split on commas; other values are taken whole.
 pub fn load_from_env(builder: ConfigBuilder<DefaultState>)
     -> ConfigBuilder<DefaultState>
 {
@@ -20,5 +23,9 @@ pub fn load_from_env(builder: ConfigBuilder<DefaultState>)
             .prefix_separator("_")
             .separator("__")
             .try_parsing(true)
+            .list_separator(",")
+            .with_list_parse_key("server.cors.allowed_origins")
+            .with_list_parse_key("server.cors.allowed_methods")
+            .with_list_parse_key("server.cors.allowed_headers")
     )
 }
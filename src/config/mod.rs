@@ -11,28 +11,44 @@ mod cli;
 use serde::Deserialize;
 use std::sync::OnceLock;
 
-pub use models::{ServerConfig, GoogleConfig, TailscaleConfig, DatabaseConfig};
+pub use models::{ServerConfig, RateLimitConfig, CorsConfig, GoogleConfig, IdentityProviderConfig, TailscaleConfig, KeyProfileConfig, DatabaseConfig, AdminConfig, ApprovalConfig, EmailMatcher, AuditConfig, SessionConfig, ApiTokenConfig, QuotaConfig, DeviceRemoval, NetworkProviderKind};
+pub use models::{Command, UsersCommand, PermissionsCommand, DevicesCommand, DbCommand, AuditCommand};
+pub use cli::get_command;
 
 /// Main configuration struct containing all application settings
 #[derive(Debug, Clone, Deserialize)]
 pub struct SsoConfig {
     pub server: ServerConfig,
-    pub google: GoogleConfig,
+    pub google: Option<GoogleConfig>,
+    #[serde(default)]
+    pub identity_providers: Vec<IdentityProviderConfig>,
     pub tailscale: TailscaleConfig,
     pub database: DatabaseConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub approval: ApprovalConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub api_tokens: ApiTokenConfig,
+    #[serde(default)]
+    pub quotas: QuotaConfig,
 }
 
 impl SsoConfig {
-    /// Get the Google OAuth audience for JWT validation
-    pub fn audience(&self) -> &str {
-        &self.google.client_id
-    }
-
     /// Read Tailscale OAuth secret from file
     pub fn read_tailscale_secret(&self) -> Result<String, std::io::Error> {
         std::fs::read_to_string(&self.tailscale.oauth_secret_path)
             .map(|s| s.trim().to_string())
     }
+
+    /// Check whether an email belongs to a configured administrator
+    pub fn is_admin_email(&self, email: &str) -> bool {
+        self.admin.emails.iter().any(|admin| admin.eq_ignore_ascii_case(email))
+    }
 }
 
 /// Load configuration from all sources
@@ -61,6 +77,32 @@ pub fn load_config() -> Result<SsoConfig, config::ConfigError> {
 // Global config instance
 static CONFIG: OnceLock<SsoConfig> = OnceLock::new();
 
+/// Install the built-in defaults as the global configuration, for tests
+///
+/// Tests cannot load the configuration normally, as the test runner's
+/// arguments are not ours. Only the required values are filled in, with an
+/// approval rule approving `approved.example.com` for the sign-up tests.
+#[cfg(test)]
+pub fn init_test_config() -> &'static SsoConfig {
+    const TEST_CONFIG: &str = r#"
+        [[approval.rules]]
+        name = "staff"
+        status = "approved"
+        domains = ["approved.example.com"]
+    "#;
+
+    CONFIG.get_or_init(|| {
+        defaults::set_defaults(config::Config::builder())
+            .and_then(|builder| builder
+                .add_source(config::File::from_str(TEST_CONFIG, config::FileFormat::Toml))
+                .set_override("tailscale.oauth_secret_path", "/nonexistent")?
+                .set_override("google.client_id", "test-client")?
+                .build())
+            .and_then(|config| config.try_deserialize())
+            .expect("built-in defaults are a valid configuration")
+    })
+}
+
 /// Get the global configuration singleton
 pub fn get_config() -> &'static SsoConfig {
     CONFIG.get_or_init(|| {
@@ -0,0 +1,8 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AdminConfig {
+    /// Emails granted the admin permission when they first use the admin API
+    #[serde(default)]
+    pub emails: Vec<String>,
+}
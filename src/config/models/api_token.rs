@@ -0,0 +1,29 @@
+use serde::Deserialize;
+
+/// Personal API tokens that headless machines use instead of signing in
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiTokenConfig {
+    /// Lifetime in days of a token created without an explicit expiry
+    #[serde(default = "default_ttl_days")]
+    pub default_ttl_days: u32,
+    /// Longest lifetime in days a token may be created with
+    #[serde(default = "default_max_ttl_days")]
+    pub max_ttl_days: u32,
+}
+
+impl Default for ApiTokenConfig {
+    fn default() -> Self {
+        ApiTokenConfig {
+            default_ttl_days: default_ttl_days(),
+            max_ttl_days: default_max_ttl_days(),
+        }
+    }
+}
+
+fn default_ttl_days() -> u32 {
+    30
+}
+
+fn default_max_ttl_days() -> u32 {
+    365
+}
@@ -0,0 +1,38 @@
+use serde::Deserialize;
+use crate::models::UserStatus;
+
+/// Rules that decide the status of a user when they first sign in
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ApprovalConfig {
+    /// Users matching the denylist are denied before any rule is consulted
+    #[serde(default)]
+    pub denylist: EmailMatcher,
+    /// Evaluated in order; the first matching rule decides the status
+    #[serde(default)]
+    pub rules: Vec<ApprovalRule>,
+}
+
+/// A named rule assigning a status to the emails it matches
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApprovalRule {
+    /// Short name recorded as the reason for the assigned status
+    pub name: String,
+    /// Status to assign: "approved", "denied" or "pending"
+    pub status: UserStatus,
+    #[serde(flatten)]
+    pub matcher: EmailMatcher,
+}
+
+/// Ways of matching an email address; any one match is enough
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EmailMatcher {
+    /// Exact addresses, compared case-insensitively
+    #[serde(default)]
+    pub emails: Vec<String>,
+    /// Domain suffixes; "example.com" also matches "eng.example.com"
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Regular expressions matched against the whole lowercased address
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
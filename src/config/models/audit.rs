@@ -0,0 +1,9 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditConfig {
+    /// Ed25519 private key (PKCS#8 PEM) used to sign exported checkpoints
+    pub checkpoint_key_path: Option<String>,
+    /// Matching public key (PEM), used to verify checkpoints
+    pub checkpoint_public_key_path: Option<String>,
+}
@@ -1,6 +1,7 @@
 // CLI argument data structure
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use crate::models::UserStatus;
 
 /// SSO Backend Server CLI Arguments
 #[derive(Parser, Debug, Clone)]
@@ -19,6 +20,10 @@ pub struct CliArgs {
     #[arg(long)]
     pub google_client_id: Option<String>,
 
+    /// Google Workspace domain allowed to sign in (can be specified multiple times)
+    #[arg(long = "google-allowed-hosted-domain")]
+    pub google_allowed_hosted_domains: Vec<String>,
+
     /// Path to Tailscale OAuth secret file
     #[arg(long)]
     pub tailscale_oauth_secret_path: Option<String>,
@@ -35,7 +40,198 @@ pub struct CliArgs {
     #[arg(long)]
     pub database_path: Option<String>,
 
+    /// Database URL (sqlite: or postgres://), overriding the file path
+    #[arg(long)]
+    pub database_url: Option<String>,
+
     /// Log level (trace, debug, info, warn, error)
     #[arg(long)]
     pub log_level: Option<String>,
+
+    /// Reverse proxy address or CIDR range whose X-Forwarded-For is trusted (can be specified multiple times)
+    #[arg(long = "trusted-proxy")]
+    pub trusted_proxies: Vec<String>,
+
+    /// Browser origin allowed to call the API, or * for any (can be specified multiple times)
+    #[arg(long = "cors-allowed-origin")]
+    pub cors_allowed_origins: Vec<String>,
+
+    /// Allow credentialed cross-origin requests (true or false)
+    #[arg(long)]
+    pub cors_allow_credentials: Option<bool>,
+
+    /// Admin email allowed to use the admin API (can be specified multiple times)
+    #[arg(long = "admin-email")]
+    pub admin_emails: Vec<String>,
+
+    /// Run an administrative command against the database instead of starting the server
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Manage users
+    Users {
+        #[command(subcommand)]
+        command: UsersCommand,
+    },
+    /// Manage user permissions
+    Permissions {
+        #[command(subcommand)]
+        command: PermissionsCommand,
+    },
+    /// Manage users' devices on the tailnet
+    Devices {
+        #[command(subcommand)]
+        command: DevicesCommand,
+    },
+    /// Manage the database schema
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+    /// Check the integrity of the audit log
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum UsersCommand {
+    /// List users, optionally filtered by status
+    List {
+        /// Only list users with this status (pending, approved, denied, suspended, expired)
+        #[arg(long)]
+        status: Option<UserStatus>,
+    },
+    /// Approve a user
+    Approve {
+        email: String,
+        /// Reason stored with the decision
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Deny a user
+    Deny {
+        email: String,
+        /// Reason stored with the decision
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Return a user to pending
+    Pend {
+        email: String,
+        /// Reason stored with the decision
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Suspend an approved user, revoking their auth keys but keeping their devices
+    Suspend {
+        email: String,
+        /// Reason stored with the decision
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Mark an approved user's access as expired, revoking their auth keys
+    Expire {
+        email: String,
+        /// Reason stored with the decision
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Delete a user and their permissions
+    Delete {
+        email: String,
+    },
+    /// Revoke a user's live Tailscale auth keys, retrying earlier failures
+    RevokeKeys {
+        email: String,
+    },
+    /// End every live session of a user, signing them out everywhere
+    EndSessions {
+        email: String,
+    },
+    /// Show the auth key and device limits that apply to a user and their usage
+    Quota {
+        email: String,
+    },
+    /// Override the configured limits for a user; omitted limits keep the configured value
+    SetQuota {
+        email: String,
+        /// Auth keys the user may be issued in any hour, 0 for no limit
+        #[arg(long)]
+        keys_per_hour: Option<u32>,
+        /// Unexpired, unrevoked auth keys the user may hold, 0 for no limit
+        #[arg(long)]
+        live_keys: Option<u32>,
+        /// Tailnet devices the user may have, 0 for no limit
+        #[arg(long)]
+        devices: Option<u32>,
+    },
+    /// Remove a user's overrides so the configured limits apply again
+    ClearQuota {
+        email: String,
+    },
+    /// Link an identity provider account to a user, for providers that do not verify emails
+    LinkIdentity {
+        email: String,
+        /// Issuer of the provider the account belongs to
+        #[arg(long)]
+        issuer: String,
+        /// The account's subject, as recorded in the refused sign_in audit event
+        #[arg(long)]
+        subject: String,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum PermissionsCommand {
+    /// List a user's permissions
+    List {
+        email: String,
+    },
+    /// Grant a permission to a user
+    Grant {
+        email: String,
+        permission: String,
+    },
+    /// Revoke a permission from a user
+    Revoke {
+        email: String,
+        permission: String,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum DevicesCommand {
+    /// List the tailnet devices attributed to a user
+    List {
+        email: String,
+    },
+    /// Delete or de-authorize the tailnet devices attributed to a user
+    Remove {
+        email: String,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum DbCommand {
+    /// Apply pending schema migrations
+    Migrate,
+    /// List schema migrations and whether each has been applied
+    Status,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum AuditCommand {
+    /// Walk the audit hash chain and report the first broken link
+    Verify {
+        /// File of exported checkpoints, one per line, that the chain must still match
+        #[arg(long)]
+        checkpoints: Option<String>,
+    },
+    /// Verify the chain and print a signed checkpoint of its head
+    Checkpoint,
 }
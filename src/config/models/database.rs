@@ -2,5 +2,15 @@ use serde::Deserialize;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct DatabaseConfig {
+    /// SQLite database file, used when no URL is set
     pub path: String,
+    /// Database URL, e.g. postgres://user@host/low_access or sqlite:sso.db
+    pub url: Option<String>,
+}
+
+impl DatabaseConfig {
+    /// The URL to connect to, falling back to the SQLite file at `path`
+    pub fn url(&self) -> String {
+        self.url.clone().unwrap_or_else(|| format!("sqlite:{}", self.path))
+    }
 }
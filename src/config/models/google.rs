@@ -3,4 +3,7 @@ use serde::Deserialize;
 #[derive(Debug, Clone, Deserialize)]
 pub struct GoogleConfig {
     pub client_id: String,
+    /// Google Workspace domains allowed to sign in; empty allows any Google account
+    #[serde(default)]
+    pub allowed_hosted_domains: Vec<String>,
 }
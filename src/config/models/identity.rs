@@ -0,0 +1,37 @@
+use serde::Deserialize;
+use jsonwebtoken::Algorithm;
+
+/// An OpenID Connect identity provider whose ID tokens we accept
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdentityProviderConfig {
+    /// Short name used in logs
+    pub name: String,
+    /// Issuer URL, matched against the token's `iss` claim
+    pub issuer: String,
+    /// Other `iss` values this provider uses (Google also issues "accounts.google.com")
+    #[serde(default)]
+    pub additional_issuers: Vec<String>,
+    /// Client IDs accepted in the token's `aud` claim
+    pub audiences: Vec<String>,
+    /// Signing algorithms accepted for ID tokens
+    #[serde(default = "default_algorithms")]
+    pub algorithms: Vec<Algorithm>,
+    /// Discovery document URL (default: issuer + /.well-known/openid-configuration)
+    pub discovery_url: Option<String>,
+    /// JWKS URL, skipping discovery when set
+    pub jwks_uri: Option<String>,
+    /// Reject tokens whose `email_verified` claim is not true
+    #[serde(default = "default_require_verified_email")]
+    pub require_verified_email: bool,
+    /// Accepted values of the `hd` claim; empty allows any (Google Workspace only)
+    #[serde(default)]
+    pub allowed_hosted_domains: Vec<String>,
+}
+
+fn default_algorithms() -> Vec<Algorithm> {
+    vec![Algorithm::RS256]
+}
+
+fn default_require_verified_email() -> bool {
+    true
+}
@@ -4,10 +4,24 @@ pub mod server;
 pub mod google;
 pub mod tailscale;
 pub mod database;
+pub mod admin;
+pub mod identity;
+pub mod approval;
+pub mod audit;
+pub mod session;
+pub mod api_token;
+pub mod quota;
 pub mod cli;
 
-pub use server::ServerConfig;
+pub use server::{ServerConfig, RateLimitConfig, CorsConfig};
 pub use google::GoogleConfig;
-pub use tailscale::TailscaleConfig;
+pub use tailscale::{TailscaleConfig, KeyProfileConfig, DeviceRemoval, NetworkProviderKind};
 pub use database::DatabaseConfig;
-pub use cli::CliArgs;
+pub use admin::AdminConfig;
+pub use identity::IdentityProviderConfig;
+pub use approval::{ApprovalConfig, EmailMatcher};
+pub use audit::AuditConfig;
+pub use session::SessionConfig;
+pub use api_token::ApiTokenConfig;
+pub use quota::QuotaConfig;
+pub use cli::{CliArgs, Command, UsersCommand, PermissionsCommand, DevicesCommand, DbCommand, AuditCommand};
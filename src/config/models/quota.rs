@@ -0,0 +1,35 @@
+use serde::Deserialize;
+
+/// Limits on the auth keys and devices each user can have; 0 means no limit
+///
+/// Administrators can override any of them for a single user.
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuotaConfig {
+    /// Auth keys a user may be issued in any hour
+    #[serde(default = "default_keys_per_hour")]
+    pub keys_per_hour: u32,
+    /// Auth keys a user may hold that have neither expired nor been revoked
+    #[serde(default = "default_live_keys")]
+    pub live_keys: u32,
+    /// Tailnet devices attributed to a user; checking this lists the tailnet's devices
+    #[serde(default)]
+    pub devices: u32,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        QuotaConfig {
+            keys_per_hour: default_keys_per_hour(),
+            live_keys: default_live_keys(),
+            devices: 0,
+        }
+    }
+}
+
+fn default_keys_per_hour() -> u32 {
+    10
+}
+
+fn default_live_keys() -> u32 {
+    5
+}
@@ -1,7 +1,83 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ServerConfig {
     pub bind_address: String,
     pub log_level: String,
+    /// Reverse proxies whose X-Forwarded-For header is believed, as addresses or CIDR ranges
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    pub cors: CorsConfig,
+}
+
+/// Which browser origins may call the API, answered in CORS headers
+///
+/// Defaults are set in `config::defaults`, like the rest of `[server]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CorsConfig {
+    /// Origins such as "https://access.example.com", or "*" for any; empty allows none
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache a preflight response, in seconds
+    pub max_age_seconds: u64,
+    /// Let browsers send cookies and read responses to credentialed requests
+    pub allow_credentials: bool,
+}
+
+/// Token buckets limiting how often each client address may call each route
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Requests a client may make in a burst, the size of each bucket
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+    /// Requests a client may make per minute once their burst is spent
+    #[serde(default = "default_per_minute")]
+    pub per_minute: u32,
+    /// Most buckets kept at once; when all are in use the least recently seen clients are forgotten
+    #[serde(default = "default_max_clients")]
+    pub max_clients: usize,
+    /// Budgets for single routes, keyed by their path such as "/auth/validate"; unknown paths are refused
+    #[serde(default)]
+    pub routes: BTreeMap<String, RouteRateLimitConfig>,
+}
+
+/// A route's own budget; unset values fall back to the global ones
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteRateLimitConfig {
+    pub burst: Option<u32>,
+    pub per_minute: Option<u32>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: default_enabled(),
+            burst: default_burst(),
+            per_minute: default_per_minute(),
+            max_clients: default_max_clients(),
+            routes: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_burst() -> u32 {
+    20
+}
+
+fn default_per_minute() -> u32 {
+    60
+}
+
+fn default_max_clients() -> usize {
+    100_000
 }
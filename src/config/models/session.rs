@@ -0,0 +1,42 @@
+use serde::Deserialize;
+
+/// Sessions issued after a successful ID token sign-in
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionConfig {
+    /// File holding the secret that signs session tokens, at least 32 bytes
+    /// When unset a random secret is generated at startup, so sessions end on
+    /// restart and are not shared between replicas; required with PostgreSQL
+    pub secret_path: Option<String>,
+    /// Lifetime of a session token in seconds
+    #[serde(default = "default_ttl_seconds")]
+    pub ttl_seconds: u64,
+    /// Lifetime of a refresh token in seconds; sessions cannot be refreshed beyond it
+    #[serde(default = "default_refresh_ttl_seconds")]
+    pub refresh_ttl_seconds: u64,
+    /// Mark session cookies Secure; disable only for local development over plain HTTP
+    #[serde(default = "default_cookie_secure")]
+    pub cookie_secure: bool,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            secret_path: None,
+            ttl_seconds: default_ttl_seconds(),
+            refresh_ttl_seconds: default_refresh_ttl_seconds(),
+            cookie_secure: default_cookie_secure(),
+        }
+    }
+}
+
+fn default_ttl_seconds() -> u64 {
+    900
+}
+
+fn default_refresh_ttl_seconds() -> u64 {
+    43200
+}
+
+fn default_cookie_secure() -> bool {
+    true
+}
@@ -1,9 +1,79 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct TailscaleConfig {
+    /// Control plane that issues keys and manages devices
+    #[serde(default)]
+    pub provider: NetworkProviderKind,
     pub oauth_secret_path: String,
     pub api_url: String,
     #[serde(default)]
     pub auth_key_tags: Vec<String>,
+    /// Named kinds of auth key users can request, gated by permissions
+    /// When empty, every key is issued with auth_key_tags as a reusable two-hour key
+    #[serde(default)]
+    pub key_profiles: BTreeMap<String, KeyProfileConfig>,
+    /// Profile used when a request names none; open to every approved user
+    pub default_key_profile: Option<String>,
+    /// What to do with a denied or deleted user's devices
+    #[serde(default)]
+    pub device_removal: DeviceRemoval,
+    /// Headscale user that owns issued pre-auth keys (Headscale only)
+    pub headscale_user: Option<String>,
+}
+
+/// Settings for the auth keys issued under one profile
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeyProfileConfig {
+    /// ACL tags applied to the keys; defaults to auth_key_tags
+    pub tags: Option<Vec<String>>,
+    /// Lifetime of the keys in seconds
+    #[serde(default = "default_expiry_seconds")]
+    pub expiry_seconds: u64,
+    /// Whether a key can enroll more than one device
+    #[serde(default = "default_reusable")]
+    pub reusable: bool,
+    /// Whether devices enrolled with a key are removed once they go offline
+    #[serde(default)]
+    pub ephemeral: bool,
+}
+
+impl Default for KeyProfileConfig {
+    fn default() -> Self {
+        KeyProfileConfig {
+            tags: None,
+            expiry_seconds: default_expiry_seconds(),
+            reusable: default_reusable(),
+            ephemeral: false,
+        }
+    }
+}
+
+fn default_expiry_seconds() -> u64 {
+    7200
+}
+
+fn default_reusable() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkProviderKind {
+    /// Tailscale's hosted control plane, authenticated with an OAuth client
+    #[default]
+    Tailscale,
+    /// A self-hosted Headscale server, authenticated with an API key
+    Headscale,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceRemoval {
+    /// Delete the device from the tailnet
+    #[default]
+    Delete,
+    /// Keep the device but revoke its authorization
+    Deauthorize,
 }
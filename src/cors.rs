@@ -0,0 +1,154 @@
+use anyhow::{Result, anyhow};
+use axum::http::{HeaderName, HeaderValue, Method, header::RETRY_AFTER};
+use std::time::Duration;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+use crate::config::CorsConfig;
+
+/// Allows any origin, method or header when it is the only entry of its list
+const WILDCARD: &str = "*";
+
+/// Build the CORS layer answering browsers for the configured origins
+///
+/// Fails when an entry is malformed, or when a wildcard is combined with
+/// credentials, which would let any website act with a signed-in user's
+/// session. Retry-After is always exposed so pages can back off from 429s.
+pub fn layer(config: &CorsConfig) -> Result<CorsLayer> {
+    if config.allow_credentials {
+        for (list, entries) in [
+            ("allowed_origins", &config.allowed_origins),
+            ("allowed_methods", &config.allowed_methods),
+            ("allowed_headers", &config.allowed_headers),
+        ] {
+            if entries.iter().any(|entry| entry == WILDCARD) {
+                return Err(anyhow!("{} cannot be \"*\" while allow_credentials is set; list them instead", list));
+            }
+        }
+    }
+
+    let origins = if is_wildcard("allowed_origins", &config.allowed_origins)? {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(config.allowed_origins.iter().map(|origin| parse_origin(origin)).collect::<Result<Vec<_>>>()?)
+    };
+
+    let methods = if is_wildcard("allowed_methods", &config.allowed_methods)? {
+        AllowMethods::any()
+    } else {
+        AllowMethods::list(config.allowed_methods.iter()
+            .map(|method| Method::from_bytes(method.trim().to_uppercase().as_bytes())
+                .map_err(|_| anyhow!("'{}' is not an HTTP method", method)))
+            .collect::<Result<Vec<_>>>()?)
+    };
+
+    let headers = if is_wildcard("allowed_headers", &config.allowed_headers)? {
+        AllowHeaders::any()
+    } else {
+        AllowHeaders::list(config.allowed_headers.iter()
+            .map(|header| HeaderName::from_bytes(header.trim().as_bytes())
+                .map_err(|_| anyhow!("'{}' is not a header name", header)))
+            .collect::<Result<Vec<_>>>()?)
+    };
+
+    Ok(CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers([RETRY_AFTER])
+        .max_age(Duration::from_secs(config.max_age_seconds))
+        .allow_credentials(config.allow_credentials))
+}
+
+/// Whether a list is the wildcard, which must then be its only entry
+fn is_wildcard(list: &str, entries: &[String]) -> Result<bool> {
+    match entries {
+        [entry] if entry == WILDCARD => Ok(true),
+        _ if entries.iter().any(|entry| entry == WILDCARD) => {
+            Err(anyhow!("{} cannot mix \"*\" with other entries", list))
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Check an origin is a bare scheme and host, as browsers send it
+fn parse_origin(origin: &str) -> Result<HeaderValue> {
+    let origin = origin.trim();
+    let host = origin.strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .ok_or_else(|| anyhow!("Origin '{}' must start with http:// or https://", origin))?;
+
+    if host.is_empty() || host.contains('/') {
+        return Err(anyhow!("Origin '{}' must be a scheme and host only, without a path or trailing slash", origin));
+    }
+
+    HeaderValue::from_str(origin).map_err(|_| anyhow!("Origin '{}' is not a valid header value", origin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::{Request, header};
+    use axum::routing::get;
+    use tower::Service;
+
+    fn config(origins: &[&str], allow_credentials: bool) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["authorization".to_string(), "content-type".to_string()],
+            max_age_seconds: 3600,
+            allow_credentials,
+        }
+    }
+
+    /// The Access-Control-Allow-Origin a preflight from an origin is answered with
+    async fn preflight(config: &CorsConfig, origin: &str) -> Option<String> {
+        let mut app = Router::new().route("/", get(|| async { "ok" })).layer(layer(config).unwrap());
+        let response = app.call(Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap()).await.unwrap();
+
+        response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn wildcards_are_refused_with_credentials() {
+        assert!(layer(&config(&["*"], true)).is_err());
+
+        let mut methods = config(&["https://access.example.com"], true);
+        methods.allowed_methods = vec!["*".to_string()];
+        assert!(layer(&methods).is_err());
+
+        let mut headers = config(&["https://access.example.com"], true);
+        headers.allowed_headers = vec!["*".to_string()];
+        assert!(layer(&headers).is_err());
+    }
+
+    #[test]
+    fn wildcards_are_allowed_alone_without_credentials() {
+        assert!(layer(&config(&["*"], false)).is_ok());
+        assert!(layer(&config(&["*", "https://access.example.com"], false)).is_err());
+    }
+
+    #[test]
+    fn origins_must_be_a_scheme_and_host() {
+        assert!(layer(&config(&["https://access.example.com:8443"], true)).is_ok());
+        assert!(layer(&config(&["https://access.example.com/"], true)).is_err());
+        assert!(layer(&config(&["access.example.com"], true)).is_err());
+        assert!(layer(&config(&["https://"], true)).is_err());
+    }
+
+    #[tokio::test]
+    async fn only_listed_origins_are_answered() {
+        let config = config(&["https://access.example.com"], true);
+
+        assert_eq!(preflight(&config, "https://access.example.com").await.as_deref(), Some("https://access.example.com"));
+        assert_eq!(preflight(&config, "https://evil.example.com").await, None);
+    }
+}
@@ -1,82 +1,1063 @@
-use sqlx::{SqlitePool, migrate::MigrateDatabase, Sqlite};
-use crate::models::User;
+use sqlx::{AnyPool, Any, Row, FromRow, TypeInfo, ValueRef, any::AnyRow, migrate::{MigrateDatabase, Migrator}};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use crate::models::{User, UserStatus, IllegalTransition, UserPermission, Identity, VerifiedIdentity, AuthKey, DeviceInfo, MigrationStatus, AuditEvent, AuditQuery, Session, ApiToken, UserQuota};
 use crate::config::get_config;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
 
-pub async fn init_db() -> Result<SqlitePool> {
-    let db_path = &get_config().database.path;
-    let database_url = format!("sqlite:{}", db_path);
+// Schema migrations embedded from the migrations/ directory, one set per backend
+// Both sets carry the same versions and describe the same schema
+static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("migrations/postgres");
 
-    // Create database if it doesn't exist
-    if !Sqlite::database_exists(&database_url).await.unwrap_or(false) {
-        Sqlite::create_database(&database_url).await?;
+/// Database engines the configured URL can point at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+/// Whether the configured database is PostgreSQL, which several replicas may share
+pub fn is_postgres() -> Result<bool> {
+    Ok(backend()? == Backend::Postgres)
+}
+
+/// Work out the backend from the scheme of the configured database URL
+fn backend() -> Result<Backend> {
+    let url = get_config().database.url();
+
+    match url.split_once(':').map(|(scheme, _)| scheme) {
+        Some("sqlite") => Ok(Backend::Sqlite),
+        Some("postgres" | "postgresql") => Ok(Backend::Postgres),
+        _ => Err(anyhow!("Unsupported database URL {}; expected sqlite: or postgres://", url)),
     }
+}
 
-    // Connect to database
-    let pool = SqlitePool::connect(&database_url).await?;
+fn migrator() -> Result<&'static Migrator> {
+    Ok(match backend()? {
+        Backend::Sqlite => &SQLITE_MIGRATOR,
+        Backend::Postgres => &POSTGRES_MIGRATOR,
+    })
+}
 
-    // Run migrations
-    create_tables(&pool).await?;
+/// Connect to the database and bring its schema up to date
+pub async fn init_db() -> Result<AnyPool> {
+    let pool = connect().await?;
+    migrate(&pool).await?;
 
     Ok(pool)
 }
 
-async fn create_tables(pool: &SqlitePool) -> Result<()> {
-    // Create users table
+/// Connect to the database, creating it if needed, without migrating it
+pub async fn connect() -> Result<AnyPool> {
+    sqlx::any::install_default_drivers();
+
+    let database_url = get_config().database.url();
+    backend()?;
+
+    // Create database if it doesn't exist
+    if !Any::database_exists(&database_url).await.unwrap_or(false) {
+        Any::create_database(&database_url).await?;
+    }
+
+    Ok(AnyPool::connect(&database_url).await?)
+}
+
+/// Apply every pending migration
+///
+/// Returns the migrations that were pending beforehand.
+pub async fn migrate(pool: &AnyPool) -> Result<Vec<MigrationStatus>> {
+    if backend()? == Backend::Sqlite {
+        upgrade_legacy_schema(pool).await?;
+    }
+
+    let pending: Vec<MigrationStatus> = migration_status(pool).await?
+        .into_iter()
+        .filter(|migration| migration.installed_on.is_none())
+        .collect();
+
+    migrator()?.run(pool).await?;
+
+    Ok(pending)
+}
+
+/// List the embedded migrations and whether each has been applied
+pub async fn migration_status(pool: &AnyPool) -> Result<Vec<MigrationStatus>> {
+    let applied: Vec<(i64, String, Vec<u8>)> = if table_exists(pool, "_sqlx_migrations").await? {
+        sqlx::query_as("SELECT version, CAST(installed_on AS TEXT), checksum FROM _sqlx_migrations WHERE success = TRUE")
+            .fetch_all(pool)
+            .await?
+    } else {
+        Vec::new()
+    };
+
+    Ok(migrator()?.iter()
+        .map(|migration| {
+            let installed = applied.iter().find(|(version, _, _)| *version == migration.version);
+
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                installed_on: installed.map(|(_, installed_on, _)| installed_on.clone()),
+                checksum_matches: installed.is_none_or(|(_, _, checksum)| *checksum == *migration.checksum),
+            }
+        })
+        .collect())
+}
+
+async fn table_exists(pool: &AnyPool, table: &str) -> Result<bool> {
+    let query = match backend()? {
+        Backend::Sqlite => "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = $1",
+        Backend::Postgres => "SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = current_schema() AND table_name = $1",
+    };
+
+    let count: i64 = sqlx::query_scalar(query)
+        .bind(table)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(count > 0)
+}
+
+/// Bring a SQLite database created before migrations existed up to the baseline schema
+///
+/// Such databases were kept current by ad-hoc column additions at startup.
+/// Once they match the baseline, the baseline migration is applied over them
+/// as a no-op and later migrations run as usual.
+async fn upgrade_legacy_schema(pool: &AnyPool) -> Result<()> {
+    if table_exists(pool, "_sqlx_migrations").await? || !table_exists(pool, "users").await? {
+        return Ok(());
+    }
+
+    // Columns added after the users table was first deployed
+    ensure_column(pool, "users", "status_reason", "TEXT").await?;
+    ensure_column(pool, "users", "status_updated_at", "TEXT").await?;
+
+    add_status_check(pool).await?;
+
+    if table_exists(pool, "auth_keys").await? {
+        ensure_column(pool, "auth_keys", "revoked_at", "TEXT").await?;
+    }
+
+    Ok(())
+}
+
+/// Add a column to an existing table if it is not there yet
+async fn ensure_column(pool: &AnyPool, table: &str, column: &str, definition: &str) -> Result<()> {
+    let columns: Vec<String> = sqlx::query_scalar(&format!("SELECT name FROM pragma_table_info('{}')", table))
+        .fetch_all(pool)
+        .await?;
+
+    if !columns.iter().any(|name| name == column) {
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+            .execute(pool)
+            .await?;
+    }
+
+    Ok(())
+}
+
+// The users table as created by the baseline migration
+const BASELINE_USERS_TABLE: &str = r#"
+    CREATE TABLE users_new (
+        id TEXT PRIMARY KEY,
+        email TEXT UNIQUE NOT NULL,
+        name TEXT NOT NULL,
+        status TEXT NOT NULL DEFAULT 'pending'
+            CHECK (status IN ('pending', 'approved', 'denied', 'suspended', 'expired')),
+        created_at TEXT NOT NULL,
+        last_login TEXT NOT NULL,
+        status_reason TEXT,
+        status_updated_at TEXT
+    )
+"#;
+
+/// Rebuild the users table with the status CHECK constraint if it lacks one
+///
+/// SQLite cannot add a constraint to an existing table, so the rows are
+/// copied into a new table. Foreign keys are switched off on the connection
+/// doing the copy, or dropping the old table would trip them.
+async fn add_status_check(pool: &AnyPool) -> Result<()> {
+    let table_sql: String = sqlx::query_scalar("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'users'")
+        .fetch_one(pool)
+        .await?;
+
+    if table_sql.contains("CHECK") {
+        return Ok(());
+    }
+
+    let mut conn = pool.acquire().await?;
+    sqlx::query("PRAGMA foreign_keys = OFF").execute(&mut *conn).await?;
+
+    let rebuilt = async {
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+
+        sqlx::query(BASELINE_USERS_TABLE)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!("INSERT INTO users_new ({0}) SELECT {0} FROM users", USER_COLUMNS))
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DROP TABLE users").execute(&mut *tx).await?;
+        sqlx::query("ALTER TABLE users_new RENAME TO users").execute(&mut *tx).await?;
+
+        tx.commit().await?;
+        Ok::<_, anyhow::Error>(())
+    }.await;
+
+    sqlx::query("PRAGMA foreign_keys = ON").execute(&mut *conn).await?;
+
+    rebuilt.map_err(|e| anyhow!("Failed to add status constraint to users table: {}", e))
+}
+
+pub async fn upsert_user(pool: &AnyPool, user: &User) -> Result<()> {
     sqlx::query(
         r#"
-        CREATE TABLE IF NOT EXISTS users (
-            id TEXT PRIMARY KEY,
-            email TEXT UNIQUE NOT NULL,
-            name TEXT NOT NULL,
-            status TEXT NOT NULL DEFAULT 'pending',  -- 'pending', 'approved', 'denied'
-            created_at TEXT NOT NULL,
-            last_login TEXT NOT NULL
-        )
+        INSERT INTO users (id, email, name, status, created_at, last_login, status_reason, status_updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT(id) DO UPDATE SET
+            email = excluded.email,
+            name = excluded.name,
+            last_login = excluded.last_login
+            -- Note: Don't update status on conflict, preserve admin decisions
         "#,
     )
+    .bind(&user.id)
+    .bind(&user.email)
+    .bind(&user.name)
+    .bind(user.status.as_str())
+    .bind(user.created_at.to_rfc3339())
+    .bind(user.last_login.to_rfc3339())
+    .bind(&user.status_reason)
+    .bind(user.status_updated_at.map(|at| at.to_rfc3339()))
     .execute(pool)
     .await?;
 
-    // Create user_permissions table
+    Ok(())
+}
+
+const USER_COLUMNS: &str = "id, email, name, status, created_at, last_login, status_reason, status_updated_at";
+
+pub async fn find_user_by_id(pool: &AnyPool, id: &str) -> Result<Option<User>> {
+    let user = sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(user)
+}
+
+pub async fn find_user_by_email(pool: &AnyPool, email: &str) -> Result<Option<User>> {
+    let user = sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE email = $1", USER_COLUMNS))
+        .bind(email)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(user)
+}
+
+/// Find the user an identity provider account is linked to
+pub async fn find_user_by_identity(pool: &AnyPool, issuer: &str, subject: &str) -> Result<Option<User>> {
+    let user = sqlx::query_as::<_, User>(&format!(
+        "SELECT {} FROM users WHERE id = (SELECT user_id FROM identities WHERE issuer = $1 AND subject = $2)",
+        USER_COLUMNS
+    ))
+    .bind(issuer)
+    .bind(subject)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user)
+}
+
+/// Link an identity provider account to a user, or record a new login if it is already linked
+///
+/// An identity stays linked to the user it was first linked to.
+pub async fn upsert_identity(pool: &AnyPool, user_id: &str, identity: &VerifiedIdentity) -> Result<()> {
+    let now = chrono::Utc::now().to_rfc3339();
+
     sqlx::query(
         r#"
-        CREATE TABLE IF NOT EXISTS user_permissions (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            user_id TEXT NOT NULL,
-            permission TEXT NOT NULL,
-            granted_at TEXT NOT NULL,
-            FOREIGN KEY (user_id) REFERENCES users (id),
-            UNIQUE (user_id, permission)
-        )
+        INSERT INTO identities (issuer, subject, user_id, email, created_at, last_login)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT(issuer, subject) DO UPDATE SET
+            email = excluded.email,
+            last_login = excluded.last_login
         "#,
     )
+    .bind(&identity.issuer)
+    .bind(&identity.subject)
+    .bind(user_id)
+    .bind(&identity.email)
+    .bind(&now)
+    .bind(&now)
     .execute(pool)
     .await?;
 
     Ok(())
 }
 
-pub async fn upsert_user(pool: &SqlitePool, user: &User) -> Result<()> {
+/// List the identity provider accounts linked to a user, oldest first
+pub async fn list_identities(pool: &AnyPool, user_id: &str) -> Result<Vec<Identity>> {
+    let identities = sqlx::query_as::<_, Identity>(
+        "SELECT issuer, subject, user_id, email, created_at, last_login FROM identities WHERE user_id = $1 ORDER BY created_at"
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(identities)
+}
+
+/// List users, optionally restricted to a single status, oldest first
+pub async fn list_users(pool: &AnyPool, status: Option<UserStatus>) -> Result<Vec<User>> {
+    let users = match status {
+        Some(status) => {
+            sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE status = $1 ORDER BY created_at", USER_COLUMNS))
+                .bind(status.as_str())
+                .fetch_all(pool)
+                .await?
+        }
+        None => {
+            sqlx::query_as::<_, User>(&format!("SELECT {} FROM users ORDER BY created_at", USER_COLUMNS))
+                .fetch_all(pool)
+                .await?
+        }
+    };
+
+    Ok(users)
+}
+
+/// Record an admin decision on a user
+///
+/// This is the only place a user's status changes after creation;
+/// `upsert_user` deliberately leaves it alone. Fails with `IllegalTransition`
+/// if the user's current status cannot move to the new one. Returns the
+/// updated user, or `None` if no user has the given id.
+pub async fn set_user_status(pool: &AnyPool, id: &str, status: UserStatus, reason: Option<&str>) -> Result<Option<User>> {
+    let Some(user) = find_user_by_id(pool, id).await? else {
+        return Ok(None);
+    };
+
+    if !user.status.can_transition_to(status) {
+        return Err(IllegalTransition { from: user.status, to: status }.into());
+    }
+
+    // Only update from the status that was checked, in case it changed meanwhile
+    let result = sqlx::query(
+        "UPDATE users SET status = $1, status_reason = $2, status_updated_at = $3 WHERE id = $4 AND status = $5"
+    )
+    .bind(status.as_str())
+    .bind(reason)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(id)
+    .bind(user.status.as_str())
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(anyhow!("Status of user {} changed while updating it", user.email));
+    }
+
+    find_user_by_id(pool, id).await
+}
+
+/// Delete a user together with their permission grants, linked identities, sessions, API tokens and quota
+///
+/// Returns false if no user has the given id. A deleted user who signs in
+/// again is recreated with whatever status the approval rules assign.
+pub async fn delete_user(pool: &AnyPool, id: &str) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM user_permissions WHERE user_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM identities WHERE user_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM sessions WHERE user_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM api_tokens WHERE user_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM user_quotas WHERE user_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    let result = sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// List the permissions granted to a user, in the order they were granted
+pub async fn list_permissions(pool: &AnyPool, user_id: &str) -> Result<Vec<UserPermission>> {
+    let permissions = sqlx::query_as::<_, UserPermission>(
+        "SELECT permission, granted_at FROM user_permissions WHERE user_id = $1 ORDER BY granted_at"
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(permissions)
+}
+
+/// Grant a permission to a user
+///
+/// Returns false if the user already held the permission.
+pub async fn grant_permission(pool: &AnyPool, user_id: &str, permission: &str) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO user_permissions (user_id, permission, granted_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT(user_id, permission) DO NOTHING
+        "#,
+    )
+    .bind(user_id)
+    .bind(permission)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Revoke a permission from a user
+///
+/// Returns false if the user did not hold the permission.
+pub async fn revoke_permission(pool: &AnyPool, user_id: &str, permission: &str) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM user_permissions WHERE user_id = $1 AND permission = $2")
+        .bind(user_id)
+        .bind(permission)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+const AUTH_KEY_COLUMNS: &str = "id, user_id, tags, created_at, expires_at, request_ip, revoked_at, api_token_id, profile, reusable, hostname, os, purpose";
+
+/// Record a Tailscale auth key issued to a user
+pub async fn insert_auth_key(pool: &AnyPool, key: &AuthKey) -> Result<()> {
     sqlx::query(
         r#"
-        INSERT INTO users (id, email, name, status, created_at, last_login)
-        VALUES (?, ?, ?, ?, ?, ?)
-        ON CONFLICT(id) DO UPDATE SET
-            email = excluded.email,
-            name = excluded.name,
-            last_login = excluded.last_login
-            -- Note: Don't update status on conflict, preserve admin decisions
+        INSERT INTO auth_keys (id, user_id, tags, created_at, expires_at, request_ip, api_token_id, profile, reusable, hostname, os, purpose)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
         "#,
     )
-    .bind(&user.id)
-    .bind(&user.email)
-    .bind(&user.name)
-    .bind(&user.status)
-    .bind(user.created_at.to_rfc3339())
-    .bind(user.last_login.to_rfc3339())
+    .bind(&key.id)
+    .bind(&key.user_id)
+    .bind(&key.tags)
+    .bind(key.created_at.to_rfc3339())
+    .bind(key.expires_at.to_rfc3339())
+    .bind(&key.request_ip)
+    .bind(&key.api_token_id)
+    .bind(&key.profile)
+    .bind(key.reusable.map(i64::from))
+    .bind(&key.device.hostname)
+    .bind(&key.device.os)
+    .bind(&key.device.purpose)
     .execute(pool)
     .await?;
 
     Ok(())
 }
+
+/// List every recorded auth key, oldest first
+pub async fn list_auth_keys(pool: &AnyPool) -> Result<Vec<AuthKey>> {
+    let keys = sqlx::query_as::<_, AuthKey>(&format!("SELECT {} FROM auth_keys ORDER BY created_at", AUTH_KEY_COLUMNS))
+        .fetch_all(pool)
+        .await?;
+
+    Ok(keys)
+}
+
+/// List a user's auth keys that have neither expired nor been revoked
+pub async fn list_live_auth_keys(pool: &AnyPool, user_id: &str) -> Result<Vec<AuthKey>> {
+    let keys = sqlx::query_as::<_, AuthKey>(&format!(
+        "SELECT {} FROM auth_keys WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > $2 ORDER BY created_at",
+        AUTH_KEY_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(keys)
+}
+
+/// List the auth keys issued to a user at or after a time, oldest first
+pub async fn list_auth_keys_since(pool: &AnyPool, user_id: &str, since: DateTime<Utc>) -> Result<Vec<AuthKey>> {
+    let keys = sqlx::query_as::<_, AuthKey>(&format!(
+        "SELECT {} FROM auth_keys WHERE user_id = $1 AND created_at >= $2 ORDER BY created_at",
+        AUTH_KEY_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(since.to_rfc3339())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(keys)
+}
+
+/// Mark an auth key as revoked
+pub async fn mark_auth_key_revoked(pool: &AnyPool, id: &str) -> Result<()> {
+    sqlx::query("UPDATE auth_keys SET revoked_at = $1 WHERE id = $2")
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+const SESSION_COLUMNS: &str = "id, user_id, created_at, last_seen, expires_at, ip, user_agent, ended_at, refresh_generation";
+
+/// Record a session started by a sign-in
+pub async fn insert_session(pool: &AnyPool, session: &Session) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO sessions (id, user_id, created_at, last_seen, expires_at, ip, user_agent, refresh_generation)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+    )
+    .bind(&session.id)
+    .bind(&session.user_id)
+    .bind(session.created_at.to_rfc3339())
+    .bind(session.last_seen.to_rfc3339())
+    .bind(session.expires_at.to_rfc3339())
+    .bind(&session.ip)
+    .bind(&session.user_agent)
+    .bind(session.refresh_generation)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn find_session(pool: &AnyPool, id: &str) -> Result<Option<Session>> {
+    let session = sqlx::query_as::<_, Session>(&format!("SELECT {} FROM sessions WHERE id = $1", SESSION_COLUMNS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(session)
+}
+
+/// Record that a session was just used
+pub async fn touch_session(pool: &AnyPool, id: &str) -> Result<()> {
+    sqlx::query("UPDATE sessions SET last_seen = $1 WHERE id = $2")
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Move a live session on to its next refresh generation
+///
+/// Only succeeds if the session is still at the given generation, so of two
+/// refreshes with the same refresh token at most one goes through.
+pub async fn advance_refresh_generation(pool: &AnyPool, id: &str, generation: i64) -> Result<bool> {
+    let result = sqlx::query(
+        "UPDATE sessions SET refresh_generation = $1 WHERE id = $2 AND refresh_generation = $3 AND ended_at IS NULL",
+    )
+    .bind(generation + 1)
+    .bind(id)
+    .bind(generation)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// List a user's sessions that have neither expired nor ended, oldest first
+pub async fn list_live_sessions(pool: &AnyPool, user_id: &str) -> Result<Vec<Session>> {
+    let sessions = sqlx::query_as::<_, Session>(&format!(
+        "SELECT {} FROM sessions WHERE user_id = $1 AND ended_at IS NULL AND expires_at > $2 ORDER BY created_at",
+        SESSION_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(sessions)
+}
+
+/// End a session, returning false if it had already ended
+pub async fn end_session(pool: &AnyPool, id: &str) -> Result<bool> {
+    let result = sqlx::query("UPDATE sessions SET ended_at = $1 WHERE id = $2 AND ended_at IS NULL")
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// End every live session of a user, returning the ids of the sessions ended
+pub async fn end_user_sessions(pool: &AnyPool, user_id: &str) -> Result<Vec<String>> {
+    let mut ended = Vec::new();
+
+    for session in list_live_sessions(pool, user_id).await? {
+        if end_session(pool, &session.id).await? {
+            ended.push(session.id);
+        }
+    }
+
+    Ok(ended)
+}
+
+const API_TOKEN_COLUMNS: &str = "id, user_id, name, scopes, created_at, expires_at, last_used_at, revoked_at";
+
+/// Record a personal API token by the hash of its secret
+pub async fn insert_api_token(pool: &AnyPool, token: &ApiToken, token_hash: &str) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(&token.id)
+    .bind(&token.user_id)
+    .bind(&token.name)
+    .bind(token_hash)
+    .bind(token.scopes.join(","))
+    .bind(token.created_at.to_rfc3339())
+    .bind(token.expires_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn find_api_token(pool: &AnyPool, id: &str) -> Result<Option<ApiToken>> {
+    let token = sqlx::query_as::<_, ApiToken>(&format!("SELECT {} FROM api_tokens WHERE id = $1", API_TOKEN_COLUMNS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(token)
+}
+
+pub async fn find_api_token_by_hash(pool: &AnyPool, token_hash: &str) -> Result<Option<ApiToken>> {
+    let token = sqlx::query_as::<_, ApiToken>(&format!("SELECT {} FROM api_tokens WHERE token_hash = $1", API_TOKEN_COLUMNS))
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(token)
+}
+
+/// List a user's API tokens that have not been revoked, expired ones included, oldest first
+pub async fn list_api_tokens(pool: &AnyPool, user_id: &str) -> Result<Vec<ApiToken>> {
+    let tokens = sqlx::query_as::<_, ApiToken>(&format!(
+        "SELECT {} FROM api_tokens WHERE user_id = $1 AND revoked_at IS NULL ORDER BY created_at",
+        API_TOKEN_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tokens)
+}
+
+/// Record that an API token was just used
+pub async fn touch_api_token(pool: &AnyPool, id: &str) -> Result<()> {
+    sqlx::query("UPDATE api_tokens SET last_used_at = $1 WHERE id = $2")
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Revoke an API token, returning false if it was already revoked
+pub async fn revoke_api_token(pool: &AnyPool, id: &str) -> Result<bool> {
+    let result = sqlx::query("UPDATE api_tokens SET revoked_at = $1 WHERE id = $2 AND revoked_at IS NULL")
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Look up the quota overrides an administrator set for a user
+pub async fn find_user_quota(pool: &AnyPool, user_id: &str) -> Result<Option<UserQuota>> {
+    let quota = sqlx::query_as::<_, UserQuota>(
+        "SELECT user_id, keys_per_hour, live_keys, devices, updated_at FROM user_quotas WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(quota)
+}
+
+/// Store a user's quota overrides, replacing any they had
+pub async fn set_user_quota(pool: &AnyPool, quota: &UserQuota) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO user_quotas (user_id, keys_per_hour, live_keys, devices, updated_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (user_id) DO UPDATE SET
+            keys_per_hour = excluded.keys_per_hour,
+            live_keys = excluded.live_keys,
+            devices = excluded.devices,
+            updated_at = excluded.updated_at
+        "#,
+    )
+    .bind(&quota.user_id)
+    .bind(quota.keys_per_hour.map(i64::from))
+    .bind(quota.live_keys.map(i64::from))
+    .bind(quota.devices.map(i64::from))
+    .bind(quota.updated_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Remove a user's quota overrides, returning false if they had none
+pub async fn clear_user_quota(pool: &AnyPool, user_id: &str) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM user_quotas WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Take a lock on a user's quota, held until the returned transaction ends
+///
+/// Replicas sharing a PostgreSQL database check quotas concurrently; a
+/// transaction-scoped advisory lock makes them take turns, and is released
+/// when the transaction is dropped. A SQLite database is served by a single
+/// process, whose own lock is enough, so no transaction is opened.
+pub async fn lock_user_quota(pool: &AnyPool, user_id: &str) -> Result<Option<sqlx::Transaction<'static, Any>>> {
+    if backend()? != Backend::Postgres {
+        return Ok(None);
+    }
+
+    // Advisory locks are keyed by a number, so derive one from the user id
+    let digest = Sha256::digest(format!("user_quota:{}", user_id).as_bytes());
+    let key = i64::from_be_bytes(digest[..8].try_into().expect("SHA-256 digests are 32 bytes"));
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(key)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(Some(tx))
+}
+
+const AUDIT_EVENT_COLUMNS: &str = "id, occurred_at, actor, subject_user_id, action, outcome, ip, user_agent, details, prev_hash, hash";
+
+// Serializes appends within this process, so two events never link to the same predecessor
+static AUDIT_APPEND: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Largest number of audit events a single query returns
+pub const AUDIT_QUERY_MAX_LIMIT: u32 = 1000;
+
+/// Append an event to the audit log, chained to the last event recorded
+pub async fn insert_audit_event(pool: &AnyPool, event: &AuditEvent) -> Result<()> {
+    let _append = AUDIT_APPEND.lock().await;
+    let mut tx = pool.begin().await?;
+
+    // Replicas sharing a PostgreSQL database append concurrently; the lock
+    // makes them take turns until this event is linked in
+    if backend()? == Backend::Postgres {
+        sqlx::query("LOCK TABLE audit_events IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let last = sqlx::query("SELECT hash FROM audit_events ORDER BY id DESC LIMIT 1")
+        .fetch_optional(&mut *tx)
+        .await?;
+    let prev_hash = match last {
+        Some(row) => optional_text(&row, "hash")?.unwrap_or_default(),
+        None => String::new(),
+    };
+    let hash = event.chain_hash(&prev_hash);
+
+    sqlx::query(
+        r#"
+        INSERT INTO audit_events (occurred_at, actor, subject_user_id, action, outcome, ip, user_agent, details, prev_hash, hash)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "#,
+    )
+    .bind(event.occurred_at.to_rfc3339())
+    .bind(&event.actor)
+    .bind(&event.subject_user_id)
+    .bind(event.action.as_str())
+    .bind(event.outcome.as_str())
+    .bind(&event.ip)
+    .bind(&event.user_agent)
+    .bind(event.details.to_string())
+    .bind(prev_hash)
+    .bind(hash)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// List audit events in chain order, starting after the given id
+pub async fn list_audit_chain(pool: &AnyPool, after_id: i64, limit: u32) -> Result<Vec<AuditEvent>> {
+    let events = sqlx::query_as::<_, AuditEvent>(&format!(
+        "SELECT {} FROM audit_events WHERE id > $1 ORDER BY id LIMIT {}",
+        AUDIT_EVENT_COLUMNS, limit
+    ))
+    .bind(after_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(events)
+}
+
+/// List audit events matching a query, newest first
+///
+/// At most `limit` events are returned, 100 by default and never more than
+/// `AUDIT_QUERY_MAX_LIMIT`.
+pub async fn list_audit_events(pool: &AnyPool, query: &AuditQuery) -> Result<Vec<AuditEvent>> {
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+
+    if let Some(user_id) = &query.user_id {
+        values.push(user_id.clone());
+        conditions.push(format!("subject_user_id = ${}", values.len()));
+    }
+    if let Some(action) = query.action {
+        values.push(action.as_str().to_string());
+        conditions.push(format!("action = ${}", values.len()));
+    }
+    if let Some(since) = query.since {
+        values.push(since.to_rfc3339());
+        conditions.push(format!("occurred_at >= ${}", values.len()));
+    }
+    if let Some(until) = query.until {
+        values.push(until.to_rfc3339());
+        conditions.push(format!("occurred_at < ${}", values.len()));
+    }
+
+    let filter = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", conditions.join(" AND "))
+    };
+    let limit = query.limit.unwrap_or(100).min(AUDIT_QUERY_MAX_LIMIT);
+
+    let sql = format!("SELECT {} FROM audit_events{} ORDER BY id DESC LIMIT {}", AUDIT_EVENT_COLUMNS, filter, limit);
+    let mut events = sqlx::query_as::<_, AuditEvent>(&sql);
+    for value in values {
+        events = events.bind(value);
+    }
+
+    Ok(events.fetch_all(pool).await?)
+}
+
+// Timestamps are stored as RFC 3339 text so the same queries work on every
+// backend; sqlx's Any driver has no date types of its own.
+fn timestamp(row: &AnyRow, column: &str) -> sqlx::Result<DateTime<Utc>> {
+    let value: String = row.try_get(column)?;
+    parse_timestamp(&value).map_err(|e| sqlx::Error::ColumnDecode { index: column.to_string(), source: e.into() })
+}
+
+// sqlx's Any driver never reports a value as NULL, so Option<String> cannot be
+// decoded directly; the NULL shows up in the value's type instead.
+fn optional_text(row: &AnyRow, column: &str) -> sqlx::Result<Option<String>> {
+    if row.try_get_raw(column)?.type_info().name() == "NULL" {
+        return Ok(None);
+    }
+
+    row.try_get(column).map(Some)
+}
+
+fn optional_integer(row: &AnyRow, column: &str) -> sqlx::Result<Option<i64>> {
+    if row.try_get_raw(column)?.type_info().name() == "NULL" {
+        return Ok(None);
+    }
+
+    row.try_get(column).map(Some)
+}
+
+// Flags are stored as 0 or 1, which both backends read back as integers
+fn optional_flag(row: &AnyRow, column: &str) -> sqlx::Result<Option<bool>> {
+    Ok(optional_integer(row, column)?.map(|value| value != 0))
+}
+
+fn optional_limit(row: &AnyRow, column: &str) -> sqlx::Result<Option<u32>> {
+    optional_integer(row, column)?
+        .map(u32::try_from)
+        .transpose()
+        .map_err(|e| sqlx::Error::ColumnDecode { index: column.to_string(), source: e.into() })
+}
+
+fn optional_timestamp(row: &AnyRow, column: &str) -> sqlx::Result<Option<DateTime<Utc>>> {
+    optional_text(row, column)?
+        .map(|value| parse_timestamp(&value))
+        .transpose()
+        .map_err(|e| sqlx::Error::ColumnDecode { index: column.to_string(), source: e.into() })
+}
+
+// Rows written by older versions may use SQLite's "YYYY-MM-DD HH:MM:SS" form
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f").map(|naive| naive.and_utc()))
+        .map_err(|_| anyhow!("Invalid timestamp '{}'", value))
+}
+
+impl FromRow<'_, AnyRow> for User {
+    fn from_row(row: &AnyRow) -> sqlx::Result<Self> {
+        let status: String = row.try_get("status")?;
+
+        Ok(User {
+            id: row.try_get("id")?,
+            email: row.try_get("email")?,
+            name: row.try_get("name")?,
+            status: status.parse()
+                .map_err(|e: anyhow::Error| sqlx::Error::ColumnDecode { index: "status".to_string(), source: e.into() })?,
+            created_at: timestamp(row, "created_at")?,
+            last_login: timestamp(row, "last_login")?,
+            status_reason: optional_text(row, "status_reason")?,
+            status_updated_at: optional_timestamp(row, "status_updated_at")?,
+        })
+    }
+}
+
+impl FromRow<'_, AnyRow> for UserPermission {
+    fn from_row(row: &AnyRow) -> sqlx::Result<Self> {
+        Ok(UserPermission {
+            permission: row.try_get("permission")?,
+            granted_at: timestamp(row, "granted_at")?,
+        })
+    }
+}
+
+impl FromRow<'_, AnyRow> for Identity {
+    fn from_row(row: &AnyRow) -> sqlx::Result<Self> {
+        Ok(Identity {
+            issuer: row.try_get("issuer")?,
+            subject: row.try_get("subject")?,
+            user_id: row.try_get("user_id")?,
+            email: row.try_get("email")?,
+            created_at: timestamp(row, "created_at")?,
+            last_login: timestamp(row, "last_login")?,
+        })
+    }
+}
+
+impl FromRow<'_, AnyRow> for AuthKey {
+    fn from_row(row: &AnyRow) -> sqlx::Result<Self> {
+        Ok(AuthKey {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            tags: row.try_get("tags")?,
+            created_at: timestamp(row, "created_at")?,
+            expires_at: timestamp(row, "expires_at")?,
+            request_ip: optional_text(row, "request_ip")?,
+            revoked_at: optional_timestamp(row, "revoked_at")?,
+            api_token_id: optional_text(row, "api_token_id")?,
+            profile: optional_text(row, "profile")?,
+            reusable: optional_flag(row, "reusable")?,
+            device: DeviceInfo {
+                hostname: optional_text(row, "hostname")?,
+                os: optional_text(row, "os")?,
+                purpose: optional_text(row, "purpose")?,
+            },
+        })
+    }
+}
+
+impl FromRow<'_, AnyRow> for AuditEvent {
+    fn from_row(row: &AnyRow) -> sqlx::Result<Self> {
+        let action: String = row.try_get("action")?;
+        let outcome: String = row.try_get("outcome")?;
+        let details: String = row.try_get("details")?;
+
+        Ok(AuditEvent {
+            id: row.try_get("id")?,
+            occurred_at: timestamp(row, "occurred_at")?,
+            actor: optional_text(row, "actor")?,
+            subject_user_id: optional_text(row, "subject_user_id")?,
+            action: action.parse()
+                .map_err(|e: anyhow::Error| sqlx::Error::ColumnDecode { index: "action".to_string(), source: e.into() })?,
+            outcome: outcome.parse()
+                .map_err(|e: anyhow::Error| sqlx::Error::ColumnDecode { index: "outcome".to_string(), source: e.into() })?,
+            ip: optional_text(row, "ip")?,
+            user_agent: optional_text(row, "user_agent")?,
+            details: serde_json::from_str(&details)
+                .map_err(|e| sqlx::Error::ColumnDecode { index: "details".to_string(), source: e.into() })?,
+            prev_hash: optional_text(row, "prev_hash")?,
+            hash: optional_text(row, "hash")?,
+        })
+    }
+}
+
+impl FromRow<'_, AnyRow> for Session {
+    fn from_row(row: &AnyRow) -> sqlx::Result<Self> {
+        Ok(Session {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            created_at: timestamp(row, "created_at")?,
+            last_seen: timestamp(row, "last_seen")?,
+            expires_at: timestamp(row, "expires_at")?,
+            ip: optional_text(row, "ip")?,
+            user_agent: optional_text(row, "user_agent")?,
+            ended_at: optional_timestamp(row, "ended_at")?,
+            refresh_generation: row.try_get("refresh_generation")?,
+        })
+    }
+}
+
+impl FromRow<'_, AnyRow> for ApiToken {
+    fn from_row(row: &AnyRow) -> sqlx::Result<Self> {
+        let scopes: String = row.try_get("scopes")?;
+
+        Ok(ApiToken {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            name: row.try_get("name")?,
+            scopes: scopes.split(',').filter(|scope| !scope.is_empty()).map(str::to_string).collect(),
+            created_at: timestamp(row, "created_at")?,
+            expires_at: timestamp(row, "expires_at")?,
+            last_used_at: optional_timestamp(row, "last_used_at")?,
+            revoked_at: optional_timestamp(row, "revoked_at")?,
+        })
+    }
+}
+
+impl FromRow<'_, AnyRow> for UserQuota {
+    fn from_row(row: &AnyRow) -> sqlx::Result<Self> {
+        Ok(UserQuota {
+            user_id: row.try_get("user_id")?,
+            keys_per_hour: optional_limit(row, "keys_per_hour")?,
+            live_keys: optional_limit(row, "live_keys")?,
+            devices: optional_limit(row, "devices")?,
+            updated_at: timestamp(row, "updated_at")?,
+        })
+    }
+}
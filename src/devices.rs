@@ -0,0 +1,277 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeSet;
+use tracing::warn;
+use crate::config::{get_config, DeviceRemoval};
+use crate::audit;
+use crate::models::{AuthKey, Device, UserDevices, DeviceRemovalFailure, AuditContext, AuditEvent, AuditAction, AuditOutcome};
+use crate::network::get_provider;
+use crate::store::Store;
+
+/// How a device was tied to an auth key, weakest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Evidence {
+    /// Created while the key was live and carrying its tags
+    Window,
+    /// As for `Window`, and named as the hostname the key was requested for
+    Hostname,
+    /// The control plane reports the device joined with the key
+    Key,
+}
+
+/// List the tailnet devices that were enrolled with a user's auth keys
+///
+/// Headscale reports the key each node joined with, which settles its owner.
+/// Tailscale does not, so a device is tied to a key when it carries the key's
+/// tags, was created while the key was live and, if the key was requested for
+/// a hostname, has that hostname. Only the strongest kind of match counts.
+/// A device matching keys of several users that way, or matching only by
+/// when it was created, is ambiguous and reported apart from the user's own
+/// devices.
+pub async fn list_user_devices(store: &dyn Store, user_id: &str) -> Result<UserDevices> {
+    let keys = store.list_auth_keys().await?;
+    let devices = get_provider().list_devices().await?;
+    let mut attributed = UserDevices::default();
+
+    for device in devices.into_iter().filter(|device| !device.is_external) {
+        match attribute(&keys, &device, user_id) {
+            Some(Attribution::Certain) => attributed.devices.push(device),
+            Some(Attribution::Ambiguous) => attributed.ambiguous.push(device),
+            None => {}
+        }
+    }
+
+    Ok(attributed)
+}
+
+/// How surely a device belongs to a user
+#[derive(Debug, PartialEq, Eq)]
+enum Attribution {
+    Certain,
+    /// The device may be the user's, but may as well be someone else's
+    Ambiguous,
+}
+
+/// Decide whether a device belongs to a user, given every recorded auth key
+fn attribute(keys: &[AuthKey], device: &Device, user_id: &str) -> Option<Attribution> {
+    let matches = keys.iter()
+        .filter_map(|key| evidence(key, device).map(|evidence| (evidence, key.user_id.as_str())))
+        .collect::<Vec<_>>();
+    let strongest = matches.iter().map(|(evidence, _)| *evidence).max()?;
+    let owners = matches.iter()
+        .filter(|(evidence, _)| *evidence == strongest)
+        .map(|(_, owner)| *owner)
+        .collect::<BTreeSet<_>>();
+
+    if !owners.contains(user_id) {
+        return None;
+    }
+    // Any device created with matching tags while the key was live matches by window,
+    // including ones enrolled with keys issued elsewhere, so that alone settles nothing
+    if owners.len() == 1 && strongest > Evidence::Window {
+        Some(Attribution::Certain)
+    } else {
+        Some(Attribution::Ambiguous)
+    }
+}
+
+/// Check whether a device could have joined the tailnet with a key, and how surely
+fn evidence(key: &AuthKey, device: &Device) -> Option<Evidence> {
+    if let Some(key_id) = &device.auth_key_id {
+        return (*key_id == key.id).then_some(Evidence::Key);
+    }
+    if !key_enrolled(key, device) {
+        return None;
+    }
+
+    match &key.device.hostname {
+        Some(hostname) if hostname.eq_ignore_ascii_case(&device.hostname) => Some(Evidence::Hostname),
+        // The key was meant for another machine
+        Some(_) => None,
+        None => Some(Evidence::Window),
+    }
+}
+
+/// Check whether a device was created with a key's tags while the key was live
+fn key_enrolled(key: &AuthKey, device: &Device) -> bool {
+    let Ok(created) = DateTime::parse_from_rfc3339(&device.created) else {
+        return false;
+    };
+    let created = created.with_timezone(&Utc);
+
+    let live_until = match key.revoked_at {
+        Some(revoked_at) if revoked_at < key.expires_at => revoked_at,
+        _ => key.expires_at,
+    };
+
+    let tags_match = key.tags.split(',')
+        .filter(|tag| !tag.is_empty())
+        .all(|tag| device.tags.iter().any(|device_tag| device_tag == tag));
+
+    tags_match && created >= key.created_at && created <= live_until
+}
+
+/// Remove every device attributed to a user
+///
+/// Each device is attempted independently. Returns the devices that were
+/// removed and the ones that could not be. Devices that are already
+/// de-authorized are skipped when that is the configured policy. Devices
+/// that cannot be tied to the user with certainty are never touched, and are
+/// reported as failures for an admin to resolve. Every attempt is audited in the given
+/// context.
+pub async fn remove_user_devices(store: &dyn Store, user_id: &str, context: &AuditContext) -> Result<(Vec<Device>, Vec<DeviceRemovalFailure>)> {
+    let mut removed = Vec::new();
+    let mut failures = Vec::new();
+    let removal = get_config().tailscale.device_removal;
+
+    let attributed = list_user_devices(store, user_id).await?;
+
+    for device in attributed.ambiguous {
+        warn!("Not removing device {} of user {}: it may belong to someone else", device.id, user_id);
+        audit::record(store, AuditEvent {
+            subject_user_id: Some(user_id.to_string()),
+            details: serde_json::json!({ "device_id": device.id, "name": device.name, "reason": "ambiguous_owner" }),
+            ..context.event(AuditAction::RemoveDevice, AuditOutcome::Denied)
+        }).await;
+        failures.push(DeviceRemovalFailure {
+            device_id: device.id,
+            error: "Device cannot be tied to this user with certainty; not removed".to_string(),
+        });
+    }
+
+    for device in attributed.devices {
+        if removal == DeviceRemoval::Deauthorize && !device.authorized {
+            continue;
+        }
+
+        let event = AuditEvent {
+            subject_user_id: Some(user_id.to_string()),
+            ..context.event(AuditAction::RemoveDevice, AuditOutcome::Success)
+        };
+
+        match get_provider().remove_device(&device.id, removal).await {
+            Ok(()) => {
+                audit::record(store, AuditEvent {
+                    details: serde_json::json!({ "device_id": device.id, "name": device.name }),
+                    ..event
+                }).await;
+                removed.push(device);
+            }
+            Err(e) => {
+                warn!("Failed to remove device {} of user {}: {}", device.id, user_id, e);
+                audit::record(store, AuditEvent {
+                    outcome: AuditOutcome::Failure,
+                    details: serde_json::json!({ "device_id": device.id, "name": device.name, "error": e.to_string() }),
+                    ..event
+                }).await;
+                failures.push(DeviceRemovalFailure {
+                    device_id: device.id,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok((removed, failures))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use crate::models::DeviceInfo;
+
+    fn key(id: &str, user_id: &str, tags: &str, hostname: Option<&str>) -> AuthKey {
+        let created = "2026-01-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        AuthKey {
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            tags: tags.to_string(),
+            created_at: created,
+            expires_at: created + Duration::hours(2),
+            request_ip: None,
+            revoked_at: None,
+            api_token_id: None,
+            profile: None,
+            reusable: Some(true),
+            device: DeviceInfo { hostname: hostname.map(str::to_string), ..DeviceInfo::default() },
+        }
+    }
+
+    fn device(hostname: &str, tags: &[&str], created: &str) -> Device {
+        Device {
+            id: format!("device-{}", hostname),
+            name: format!("{}.tailnet.ts.net", hostname),
+            hostname: hostname.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            created: created.to_string(),
+            authorized: true,
+            is_external: false,
+            auth_key_id: None,
+        }
+    }
+
+    #[test]
+    fn devices_are_enrolled_only_with_the_key_tags_while_it_was_live() {
+        let key = key("k1", "alice", "tag:user,tag:laptop", None);
+
+        assert!(key_enrolled(&key, &device("a", &["tag:user", "tag:laptop", "tag:extra"], "2026-01-01T12:30:00Z")));
+        assert!(!key_enrolled(&key, &device("a", &["tag:user"], "2026-01-01T12:30:00Z")));
+        assert!(!key_enrolled(&key, &device("a", &["tag:user", "tag:laptop"], "2026-01-01T11:59:59Z")));
+        assert!(!key_enrolled(&key, &device("a", &["tag:user", "tag:laptop"], "2026-01-01T14:00:01Z")));
+        assert!(!key_enrolled(&key, &device("a", &["tag:user", "tag:laptop"], "")));
+    }
+
+    #[test]
+    fn revocation_ends_the_enrolment_window() {
+        let mut key = key("k1", "alice", "tag:user", None);
+        key.revoked_at = Some(key.created_at + Duration::minutes(10));
+
+        assert!(key_enrolled(&key, &device("a", &["tag:user"], "2026-01-01T12:05:00Z")));
+        assert!(!key_enrolled(&key, &device("a", &["tag:user"], "2026-01-01T12:15:00Z")));
+    }
+
+    #[test]
+    fn keys_without_tags_match_any_device_in_their_window() {
+        let key = key("k1", "alice", "", None);
+
+        assert!(key_enrolled(&key, &device("a", &[], "2026-01-01T12:30:00Z")));
+        assert_eq!(evidence(&key, &device("a", &["tag:server"], "2026-01-01T12:30:00Z")), Some(Evidence::Window));
+    }
+
+    #[test]
+    fn evidence_prefers_reported_keys_then_hostnames() {
+        let named = key("k1", "alice", "tag:user", Some("Build-01"));
+        let mut joined = device("build-01", &["tag:user"], "2026-01-01T12:30:00Z");
+
+        assert_eq!(evidence(&named, &joined), Some(Evidence::Hostname));
+        assert_eq!(evidence(&named, &device("build-02", &["tag:user"], "2026-01-01T12:30:00Z")), None);
+
+        joined.auth_key_id = Some("k1".to_string());
+        assert_eq!(evidence(&named, &joined), Some(Evidence::Key));
+        joined.auth_key_id = Some("k2".to_string());
+        assert_eq!(evidence(&named, &joined), None);
+    }
+
+    #[test]
+    fn window_matches_alone_are_ambiguous() {
+        let keys = [key("k1", "alice", "", None)];
+        let device = device("laptop", &[], "2026-01-01T12:30:00Z");
+
+        assert_eq!(attribute(&keys, &device, "alice"), Some(Attribution::Ambiguous));
+        assert_eq!(attribute(&keys, &device, "bob"), None);
+    }
+
+    #[test]
+    fn only_a_single_strong_match_is_certain() {
+        let device = device("build-01", &["tag:user"], "2026-01-01T12:30:00Z");
+        let keys = [key("k1", "alice", "tag:user", Some("build-01")), key("k2", "bob", "tag:user", None)];
+
+        assert_eq!(attribute(&keys, &device, "alice"), Some(Attribution::Certain));
+        // Bob's key only matches by window, which the hostname match outranks
+        assert_eq!(attribute(&keys, &device, "bob"), None);
+
+        let shared = [key("k1", "alice", "tag:user", Some("build-01")), key("k2", "bob", "tag:user", Some("build-01"))];
+        assert_eq!(attribute(&shared, &device, "alice"), Some(Attribution::Ambiguous));
+    }
+}
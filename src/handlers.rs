@@ -1,118 +1,535 @@
 use axum::{
-    extract::State,
-    http::{StatusCode, HeaderMap},
-    response::Json,
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, Path, Query, State},
+    http::{StatusCode, HeaderMap, HeaderValue, header::{AUTHORIZATION, RETRY_AFTER, USER_AGENT}, request::Parts},
+    response::{IntoResponse, Json, Response},
 };
-use sqlx::SqlitePool;
+use serde_json::json;
+use std::convert::Infallible;
+use std::net::SocketAddr;
 use tracing::{info, warn, error};
-use crate::models::{User, GenerateTokenRequest, ValidateTokenResponse, GenerateTokenResponse};
-use crate::{google, db, tailscale};
+use crate::models::{
+    User, UserPermission, VerifiedIdentity, AuthKey, KeyRevocationFailure, DeviceRemovalFailure,
+    GenerateTokenRequest, ValidateTokenResponse, GenerateTokenResponse, KeyProfile, KeyProfilesResponse,
+    ListUsersQuery, UserStatusRequest, AdminUsersResponse, AdminUserResponse,
+    PermissionRequest, PermissionsResponse, IdentitiesResponse, RevokeKeysResponse,
+    DevicesResponse, RemoveDevicesResponse, UserStatus, IllegalTransition, PERMISSION_ADMIN,
+    AuditContext, AuditEvent, AuditAction, AuditOutcome, AuditQuery, AuditEventsResponse,
+    Session, SessionTokenKind, SessionClaims, SessionResponse, SessionsResponse, EndSessionsResponse,
+    ApiToken, CreateApiTokenRequest, ApiTokenResponse, ApiTokensResponse, SCOPE_GENERATE_KEY,
+    QuotaRequest, QuotaResponse,
+};
+use crate::config::get_config;
+use crate::{api_token, approval, audit, oidc, devices, proxy, quota, session};
+use crate::session::{SESSION_COOKIE, REFRESH_COOKIE};
+use crate::state::AppState;
+use crate::store::UserStore;
+use crate::oidc::TokenRejection;
+use crate::quota::QuotaError;
+use crate::network::{self, get_provider};
 
 pub async fn health_check() -> &'static str {
     "LoW Access API is running!"
 }
 
 pub async fn validate_token(
-    State(pool): State<SqlitePool>,
+    State(state): State<AppState>,
+    context: AuditContext,
     headers: HeaderMap,
-) -> Result<Json<ValidateTokenResponse>, StatusCode> {
+) -> Result<(HeaderMap, Json<ValidateTokenResponse>), StatusCode> {
     info!("Received token validation request");
 
-    // Extract token from Authorization header
-    let auth_header = headers.get("authorization")
-        .and_then(|value| value.to_str().ok())
-        .ok_or_else(|| {
-            info!("Missing or invalid Authorization header");
-            StatusCode::UNAUTHORIZED
-        })?;
+    // Extract the ID token or session token from the request
+    let token = request_token(&headers)?;
 
-    let token = if auth_header.starts_with("Bearer ") {
-        &auth_header[7..] // Remove "Bearer " prefix
-    } else {
-        info!("Authorization header doesn't start with 'Bearer '");
-        return Err(StatusCode::UNAUTHORIZED);
-    };
+    // A session is already signed in; report who it belongs to
+    if session::is_session_token(token) {
+        let response = match session_user(&state, token).await {
+            Ok((user, _)) => ValidateTokenResponse {
+                success: true,
+                user: Some(user),
+                message: "Session is valid".to_string(),
+                reason: None,
+                session: None,
+            },
+            Err(e) => {
+                info!("Session validation failed: {}", e);
+                let (reason, message) = session_rejection(&e);
+                ValidateTokenResponse {
+                    success: false,
+                    user: None,
+                    message,
+                    reason: Some(reason),
+                    session: None,
+                }
+            }
+        };
+        return Ok((HeaderMap::new(), Json(response)));
+    }
 
-    // Step 1: Validate the Google token
-    let user = match google::validate_google_id_token(token).await {
-        Ok(user) => user,
+    // Step 1: Validate the ID token with the provider that issued it
+    let identity = match verify_id_token(token).await {
+        Ok(identity) => identity,
         Err(e) => {
             info!("Token validation failed: {}", e);
-            return Ok(Json(ValidateTokenResponse {
+            let (reason, message) = token_rejection(&e);
+            audit::record(state.store.as_ref(), rejected_token_event(&context, AuditAction::SignIn, reason, &e)).await;
+            return Ok((HeaderMap::new(), Json(ValidateTokenResponse {
                 success: false,
                 user: None,
-                message: format!("Invalid token: {}", e),
-            }));
+                message,
+                reason: Some(reason),
+                session: None,
+            })));
         }
     };
 
     // Step 2: Check if user is authorized in our database
-    match check_user_authorization(&pool, &user).await {
+    let context = context.with_actor(&identity.email);
+    match check_user_authorization(state.store.as_ref(), &identity).await {
         Ok(authorized_user) => {
             info!("User {} is authorized and logged in", authorized_user.email);
-            Ok(Json(ValidateTokenResponse {
-                success: true,
-                user: Some(authorized_user),
-                message: "Authentication and authorization successful".to_string(),
-            }))
+            audit::record(state.store.as_ref(), AuditEvent {
+                subject_user_id: Some(authorized_user.id.clone()),
+                details: json!({ "issuer": identity.issuer, "status": authorized_user.status }),
+                ..context.event(AuditAction::SignIn, AuditOutcome::Success)
+            }).await;
+
+            // Step 3: Start a session so later requests need not resend the ID token
+            let session = session::issue(state.store.as_ref(), &authorized_user, &context).await.inspect_err(|e| {
+                error!("Failed to issue a session to {}: {}", authorized_user.email, e);
+            }).ok();
+
+            Ok((
+                session.as_ref().map(session::cookies).unwrap_or_default(),
+                Json(ValidateTokenResponse {
+                    success: true,
+                    user: Some(authorized_user),
+                    message: "Authentication and authorization successful".to_string(),
+                    reason: None,
+                    session,
+                }),
+            ))
         }
         Err(e) => {
-            info!("User {} authorization failed: {}", user.email, e);
-            Ok(Json(ValidateTokenResponse {
+            info!("User {} authorization failed: {}", identity.email, e);
+            audit::record(state.store.as_ref(), AuditEvent {
+                details: json!({ "issuer": identity.issuer, "subject": identity.subject, "error": e }),
+                ..context.event(AuditAction::SignIn, AuditOutcome::Failure)
+            }).await;
+            Ok((HeaderMap::new(), Json(ValidateTokenResponse {
                 success: false,
                 user: None,
                 message: format!("Access denied: {}", e),
-            }))
+                reason: None,
+                session: None,
+            })))
         }
     }
 }
 
-async fn check_user_authorization(pool: &SqlitePool, user: &User) -> Result<User, String> {
-    // Check if user exists in our database
-    let existing_user = sqlx::query_as::<_, User>(
-        "SELECT id, email, name, status, created_at, last_login FROM users WHERE email = ?"
-    )
-    .bind(&user.email)
-    .fetch_optional(pool)
-    .await
-    .map_err(|e| {
-        // Provide more specific error messages based on the error type
-        match e {
-            sqlx::Error::Io(_) => "Database connection failed. Please try again later.".to_string(),
-            sqlx::Error::Database(_) => "Database query failed. The service may be temporarily unavailable.".to_string(),
-            sqlx::Error::Tls(_) => "Database connection security error. Please contact support.".to_string(),
-            sqlx::Error::Protocol(_) => "Database communication error. Please try again.".to_string(),
-            sqlx::Error::PoolTimedOut => "Database is overloaded. Please try again in a moment.".to_string(),
-            sqlx::Error::PoolClosed => "Database service is currently unavailable. Please try again later.".to_string(),
-            _ => format!("Database service error: {}. Please contact support if this persists.", e),
+/// Exchange a refresh token for a new session
+///
+/// The refresh token is read from the Authorization header or the refresh
+/// cookie, and is replaced along with the session token.
+pub async fn refresh_session(
+    State(state): State<AppState>,
+    context: AuditContext,
+    headers: HeaderMap,
+) -> Result<(HeaderMap, Json<SessionResponse>), StatusCode> {
+    let token = if headers.contains_key(AUTHORIZATION) {
+        bearer_token(&headers)?
+    } else {
+        session::cookie(&headers, REFRESH_COOKIE).ok_or(StatusCode::UNAUTHORIZED)?
+    };
+
+    let claims = session::authenticate(state.store.as_ref(), token, SessionTokenKind::Refresh).await.map_err(|e| {
+        info!("Session refresh failed: {}", e);
+        StatusCode::UNAUTHORIZED
+    })?;
+
+    let user = state.store.find_user_by_id(&claims.sub).await
+        .map_err(|e| {
+            error!("Failed to look up user {}: {}", claims.sub, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let tokens = match session::refresh(state.store.as_ref(), &user, &claims).await {
+        Ok(tokens) => tokens,
+        Err(e) if e.is::<session::RefreshTokenReused>() => {
+            warn!("{} of user {}", e, user.email);
+            audit::record(state.store.as_ref(), AuditEvent {
+                subject_user_id: Some(user.id.clone()),
+                details: json!({ "session": claims.sid, "reason": "refresh_token_reused" }),
+                ..context.with_actor(&user.email).event(AuditAction::RefreshSession, AuditOutcome::Denied)
+            }).await;
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Err(e) if e.is::<session::SessionEnded>() => {
+            info!("Session refresh failed: {}", e);
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Err(e) => {
+            error!("Failed to refresh the session of {}: {}", user.email, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    audit::record(state.store.as_ref(), AuditEvent {
+        subject_user_id: Some(user.id.clone()),
+        details: json!({ "expires_at": tokens.expires_at }),
+        ..context.with_actor(&user.email).event(AuditAction::RefreshSession, AuditOutcome::Success)
+    }).await;
+
+    Ok((session::cookies(&tokens), Json(SessionResponse {
+        success: true,
+        session: Some(tokens),
+        message: "Session refreshed".to_string(),
+    })))
+}
+
+/// End the caller's session and clear its cookies
+///
+/// The session is found from the session token, or from the refresh cookie
+/// once the session token has expired. Both tokens stop working at once.
+pub async fn logout(
+    State(state): State<AppState>,
+    context: AuditContext,
+    headers: HeaderMap,
+) -> (HeaderMap, Json<SessionResponse>) {
+    let claims = request_token(&headers).ok()
+        .filter(|token| session::is_session_token(token))
+        .and_then(|token| session::verify(token, SessionTokenKind::Session).ok())
+        .or_else(|| {
+            session::cookie(&headers, REFRESH_COOKIE)
+                .and_then(|token| session::verify(token, SessionTokenKind::Refresh).ok())
+        });
+
+    if let Some(claims) = claims {
+        match state.store.end_session(&claims.sid).await {
+            Ok(true) => audit::record(state.store.as_ref(), AuditEvent {
+                subject_user_id: Some(claims.sub),
+                details: json!({ "session": claims.sid }),
+                ..context.with_actor(&claims.email).event(AuditAction::SignOut, AuditOutcome::Success)
+            }).await,
+            Ok(false) => {}
+            Err(e) => error!("Failed to end session {} of {}: {}", claims.sid, claims.email, e),
         }
+    }
+
+    (session::clear_cookies(), Json(SessionResponse {
+        success: true,
+        session: None,
+        message: "Signed out".to_string(),
+    }))
+}
+
+/// List the caller's live sessions, marking the one making the request
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    caller: AuthenticatedUser,
+) -> Result<Json<SessionsResponse>, StatusCode> {
+    let sessions = load_sessions(&state, &caller.user).await?;
+
+    Ok(Json(SessionsResponse {
+        success: true,
+        message: format!("Found {} live session(s)", sessions.len()),
+        sessions,
+        current: caller.session_id,
+    }))
+}
+
+/// End one of the caller's own sessions, clearing its cookies if it is the current one
+pub async fn end_session(
+    State(state): State<AppState>,
+    caller: AuthenticatedUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+) -> Result<(HeaderMap, Json<EndSessionsResponse>), StatusCode> {
+    // Sessions of other users are reported as missing rather than forbidden
+    let session = state.store.find_session(&id).await
+        .map_err(|e| {
+            error!("Failed to look up session {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .filter(|session| session.user_id == caller.user.id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let ended = state.store.end_session(&session.id).await.map_err(|e| {
+        error!("Failed to end session {}: {}", session.id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    if !ended {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    info!("User {} ended session {}", caller.user.email, session.id);
+    audit::record(state.store.as_ref(), AuditEvent {
+        subject_user_id: Some(caller.user.id.clone()),
+        details: json!({ "sessions": [session.id] }),
+        ..context.with_actor(&caller.user.email).event(AuditAction::EndSessions, AuditOutcome::Success)
+    }).await;
+
+    let headers = if caller.session_id.as_deref() == Some(session.id.as_str()) {
+        session::clear_cookies()
+    } else {
+        HeaderMap::new()
+    };
+
+    Ok((headers, Json(EndSessionsResponse {
+        success: true,
+        message: format!("Session {} ended", session.id),
+        ended: vec![session.id],
+    })))
+}
+
+/// Load the user an API token belongs to, checking it may generate auth keys
+async fn api_token_user(state: &AppState, token: &str) -> anyhow::Result<(User, ApiToken)> {
+    let api_token = api_token::authenticate(state.store.as_ref(), token, SCOPE_GENERATE_KEY).await?;
+
+    let user = state.store.find_user_by_id(&api_token.user_id).await?
+        .ok_or_else(|| anyhow::anyhow!("The user this API token belongs to no longer exists"))?;
+
+    Ok((user, api_token))
+}
+
+/// List the caller's API tokens that have not been revoked
+pub async fn list_api_tokens(
+    State(state): State<AppState>,
+    caller: AuthenticatedUser,
+) -> Result<Json<ApiTokensResponse>, StatusCode> {
+    let api_tokens = load_api_tokens(&state, &caller.user).await?;
+
+    Ok(Json(ApiTokensResponse {
+        success: true,
+        message: format!("Found {} API token(s)", api_tokens.len()),
+        api_tokens,
+    }))
+}
+
+/// Create a personal API token for a headless machine
+///
+/// Only approved users may create tokens. The token is returned once and
+/// cannot be read again.
+pub async fn create_api_token(
+    State(state): State<AppState>,
+    caller: AuthenticatedUser,
+    context: AuditContext,
+    Json(request): Json<CreateApiTokenRequest>,
+) -> Result<Json<ApiTokenResponse>, StatusCode> {
+    let user = caller.user;
+    if user.status != UserStatus::Approved {
+        info!("User {} with status {} tried to create an API token", user.email, user.status);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let (api_token, token) = match api_token::create(state.store.as_ref(), &user, &request).await {
+        Ok(created) => created,
+        Err(e) => {
+            if let Some(invalid) = e.downcast_ref::<api_token::InvalidApiTokenRequest>() {
+                return Ok(Json(ApiTokenResponse {
+                    success: false,
+                    api_token: None,
+                    token: None,
+                    message: invalid.to_string(),
+                }));
+            }
+            error!("Failed to create an API token for {}: {}", user.email, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    info!("User {} created API token {} ({})", user.email, api_token.id, api_token.name);
+    audit::record(state.store.as_ref(), AuditEvent {
+        subject_user_id: Some(user.id.clone()),
+        details: json!({ "api_token": api_token.id, "name": api_token.name, "scopes": api_token.scopes, "expires_at": api_token.expires_at }),
+        ..context.with_actor(&user.email).event(AuditAction::CreateApiToken, AuditOutcome::Success)
+    }).await;
+
+    Ok(Json(ApiTokenResponse {
+        success: true,
+        message: format!("API token {} created; store it now, it will not be shown again", api_token.name),
+        api_token: Some(api_token),
+        token: Some(token),
+    }))
+}
+
+/// Revoke one of the caller's API tokens
+pub async fn revoke_api_token(
+    State(state): State<AppState>,
+    caller: AuthenticatedUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+) -> Result<Json<ApiTokenResponse>, StatusCode> {
+    let context = context.with_actor(&caller.user.email);
+    revoke_token(&state, &context, &caller.user, &id).await
+}
+
+/// Extract the caller's token: the bearer token, or else the session cookie
+fn request_token(headers: &HeaderMap) -> Result<&str, StatusCode> {
+    if headers.contains_key(AUTHORIZATION) {
+        return bearer_token(headers);
+    }
+
+    session::cookie(headers, SESSION_COOKIE).ok_or_else(|| {
+        info!("Missing Authorization header and session cookie");
+        StatusCode::UNAUTHORIZED
+    })
+}
+
+/// Load the user a session token belongs to, checking the session is still live
+///
+/// The user is read afresh on every request, so status changes apply at once.
+async fn session_user(state: &AppState, token: &str) -> anyhow::Result<(User, SessionClaims)> {
+    let claims = session::authenticate(state.store.as_ref(), token, SessionTokenKind::Session).await?;
+
+    let user = state.store.find_user_by_id(&claims.sub).await?
+        .ok_or_else(|| anyhow::anyhow!("The user this session belongs to no longer exists"))?;
+
+    Ok((user, claims))
+}
+
+/// Classify a rejected session token for the response
+fn session_rejection(e: &anyhow::Error) -> (&'static str, String) {
+    if session::is_expired(e) {
+        ("session_expired", "Session expired; refresh it or sign in again".to_string())
+    } else if e.is::<session::SessionEnded>() {
+        ("session_ended", "Session has been ended; sign in again".to_string())
+    } else {
+        ("invalid_session", format!("Invalid session: {}", e))
+    }
+}
+
+/// Extract the bearer token from the Authorization header
+fn bearer_token(headers: &HeaderMap) -> Result<&str, StatusCode> {
+    let auth_header = headers.get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| {
+            info!("Missing or invalid Authorization header");
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    auth_header.strip_prefix("Bearer ").ok_or_else(|| {
+        info!("Authorization header doesn't start with 'Bearer '");
+        StatusCode::UNAUTHORIZED
+    })
+}
+
+/// Validate an ID token with the identity provider that issued it
+///
+/// The token is routed by its `iss` claim before anything is verified; the
+/// chosen provider then checks the signature, issuer and audience itself.
+async fn verify_id_token(token: &str) -> anyhow::Result<VerifiedIdentity> {
+    let issuer = oidc::unverified_issuer(token)?;
+
+    let provider = oidc::find_provider(&issuer)
+        .ok_or_else(|| anyhow::anyhow!("Untrusted token issuer {}", issuer))?;
+
+    provider.validate_id_token(token).await
+}
+
+/// Classify a rejected ID token for the response
+///
+/// Tokens that verified but failed an account policy check get their own
+/// reason, so the frontend can tell the user what to do about it.
+fn token_rejection(e: &anyhow::Error) -> (&'static str, String) {
+    match e.downcast_ref::<TokenRejection>() {
+        Some(TokenRejection::EmailNotVerified) => ("email_not_verified", format!("Access denied: {}", e)),
+        Some(TokenRejection::HostedDomainNotAllowed(_)) => ("hosted_domain_not_allowed", format!("Access denied: {}", e)),
+        None => ("invalid_token", format!("Invalid token: {}", e)),
+    }
+}
+
+/// Audit event for an ID or session token that was rejected before its user was looked up
+///
+/// Tokens refused by account policy are denials; anything else is a failure.
+fn rejected_token_event(context: &AuditContext, action: AuditAction, reason: &str, e: &anyhow::Error) -> AuditEvent {
+    let outcome = match reason {
+        "email_not_verified" | "hosted_domain_not_allowed" => AuditOutcome::Denied,
+        _ => AuditOutcome::Failure,
+    };
+
+    AuditEvent {
+        details: json!({ "reason": reason, "error": e.to_string() }),
+        ..context.event(action, outcome)
+    }
+}
+
+/// Describe a database failure in terms a signing-in user can act on
+fn database_error_message(e: &anyhow::Error) -> String {
+    // Provide more specific error messages based on the error type
+    match e.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Io(_)) => "Database connection failed. Please try again later.".to_string(),
+        Some(sqlx::Error::Database(_)) => "Database query failed. The service may be temporarily unavailable.".to_string(),
+        Some(sqlx::Error::Tls(_)) => "Database connection security error. Please contact support.".to_string(),
+        Some(sqlx::Error::Protocol(_)) => "Database communication error. Please try again.".to_string(),
+        Some(sqlx::Error::PoolTimedOut) => "Database is overloaded. Please try again in a moment.".to_string(),
+        Some(sqlx::Error::PoolClosed) => "Database service is currently unavailable. Please try again later.".to_string(),
+        _ => format!("Database service error: {}. Please contact support if this persists.", e),
+    }
+}
+
+/// Find the user an identity is linked to
+///
+/// An identity seen for the first time is linked to the user with the same
+/// email only if its provider verifies emails. Otherwise any provider able to
+/// assert an address could take over that user's account.
+async fn find_user_for_identity(users: &dyn UserStore, identity: &VerifiedIdentity) -> anyhow::Result<Option<User>> {
+    if let Some(user) = users.find_user_by_identity(&identity.issuer, &identity.subject).await? {
+        return Ok(Some(user));
+    }
+
+    if !identity.email_verified {
+        return Ok(None);
+    }
+
+    users.find_user_by_email(&identity.email).await
+}
+
+async fn check_user_authorization(users: &dyn UserStore, identity: &VerifiedIdentity) -> Result<User, String> {
+    // Check if user exists in our database
+    let existing_user = find_user_for_identity(users, identity).await
+        .map_err(|e| database_error_message(&e))?;
 
     match existing_user {
         Some(mut db_user) => {
             // User exists - update login time and return with their current status
             db_user.last_login = chrono::Utc::now();
-            
-            if let Err(e) = db::upsert_user(pool, &db_user).await {
-                warn!("Failed to update user login time: {}", e);
+
+            if let Err(e) = users.record_login(&db_user, identity).await {
+                warn!("Failed to record login of user {} with identity {} {}: {}", db_user.email, identity.issuer, identity.subject, e);
             }
 
             Ok(db_user)
         }
         None => {
-            // User doesn't exist - CREATE THEM with 'pending' status
+            // The email belongs to an account this unverified identity may not claim
+            let taken = users.find_user_by_email(&identity.email).await
+                .map_err(|e| database_error_message(&e))?;
+            if taken.is_some() {
+                return Err(
+                    "An account with this email already exists, and this sign-in provider does not verify email addresses. \
+                    Sign in with the provider you used before, or ask an administrator to link this account.".to_string()
+                );
+            }
+
+            // User doesn't exist - create them with the status the approval rules assign
+            let decision = approval::evaluate(&identity.email, identity.email_verified);
             let now = chrono::Utc::now();
             let new_user = User {
-                id: user.id.clone(),
-                email: user.email.clone(),
-                name: user.name.clone(),
-                status: "pending".to_string(),  // Default to pending approval
+                id: format!("{:032x}", rand::random::<u128>()),
+                email: identity.email.clone(),
+                name: identity.name.clone().unwrap_or_else(|| "Unknown".to_string()),
+                status: decision.status,
                 created_at: now,
                 last_login: now,
+                status_reason: decision.rule.map(|rule| format!("Matched approval rule '{}'", rule)),
+                status_updated_at: decision.rule.map(|_| now),
             };
 
             // Insert the new user into database
-            if let Err(e) = db::upsert_user(pool, &new_user).await {
+            if let Err(e) = users.create_user(&new_user, identity).await {
                 return Err(match e.downcast_ref::<sqlx::Error>() {
                     Some(sqlx::Error::Database(_)) => "Unable to create user account due to database constraints. Please contact support.".to_string(),
                     Some(sqlx::Error::Io(_)) => "Database connection lost while creating account. Please try signing in again.".to_string(),
@@ -122,7 +539,10 @@ async fn check_user_authorization(pool: &SqlitePool, user: &User) -> Result<User
                 });
             }
 
-            info!("New user {} created with pending status", new_user.email);
+            match decision.rule {
+                Some(rule) => info!("New user {} created with {} status by approval rule {}", new_user.email, new_user.status, rule),
+                None => info!("New user {} created with {} status", new_user.email, new_user.status),
+            }
             // Return the new user so frontend can show pending page
             Ok(new_user)
         }
@@ -130,65 +550,1410 @@ async fn check_user_authorization(pool: &SqlitePool, user: &User) -> Result<User
 }
 
 pub async fn generate_tailscale_token(
-    State(pool): State<SqlitePool>,
-    Json(payload): Json<GenerateTokenRequest>,
-) -> Result<Json<GenerateTokenResponse>, StatusCode> {
-    // Validate the Google ID token and get user info
-    let user = match google::validate_google_id_token(&payload.id_token).await {
-        Ok(user) => user,
-        Err(e) => {
-            info!("Token validation failed: {}", e);
-            return Ok(Json(GenerateTokenResponse {
-                success: false,
-                tailscale_token: None,
-                message: format!("Invalid token: {}", e),
-            }));
-        }
+    State(state): State<AppState>,
+    context: AuditContext,
+    headers: HeaderMap,
+    payload: Option<Json<GenerateTokenRequest>>,
+) -> Result<Response, StatusCode> {
+    // An ID token in the body signs the user in again; otherwise their session or API token is used
+    let request = payload.map(|Json(request)| request).unwrap_or_default();
+    let token = match &request.id_token {
+        Some(id_token) => id_token.as_str(),
+        None => request_token(&headers)?,
     };
 
-    // Check user authorization (this also validates their current status)
-    let authorized_user = match check_user_authorization(&pool, &user).await {
-        Ok(user) => user,
-        Err(e) => {
-            return Ok(Json(GenerateTokenResponse {
-                success: false,
-                tailscale_token: None,
-                message: e,
-            }));
+    let (authorized_user, context, api_token_id) = if api_token::is_api_token(token) {
+        match api_token_user(&state, token).await {
+            Ok((user, api_token)) => {
+                let context = context.with_actor(&user.email);
+                (user, context, Some(api_token.id))
+            }
+            Err(e) => {
+                info!("API token validation failed: {}", e);
+                audit::record(state.store.as_ref(), rejected_token_event(&context, AuditAction::GenerateKey, "invalid_api_token", &e)).await;
+                return Ok(Json(GenerateTokenResponse {
+                    success: false,
+                    tailscale_token: None,
+                    message: format!("Invalid API token: {}", e),
+                    reason: Some("invalid_api_token"),
+                    retry_after: None,
+                }).into_response());
+            }
+        }
+    } else if session::is_session_token(token) {
+        match session_user(&state, token).await {
+            Ok((user, _)) => {
+                let context = context.with_actor(&user.email);
+                (user, context, None)
+            }
+            Err(e) => {
+                info!("Session validation failed: {}", e);
+                let (reason, message) = session_rejection(&e);
+                audit::record(state.store.as_ref(), rejected_token_event(&context, AuditAction::GenerateKey, reason, &e)).await;
+                return Ok(Json(GenerateTokenResponse {
+                    success: false,
+                    tailscale_token: None,
+                    message,
+                    reason: Some(reason),
+                    retry_after: None,
+                }).into_response());
+            }
+        }
+    } else {
+        // Validate the ID token and get user info
+        let identity = match verify_id_token(token).await {
+            Ok(identity) => identity,
+            Err(e) => {
+                info!("Token validation failed: {}", e);
+                let (reason, message) = token_rejection(&e);
+                audit::record(state.store.as_ref(), rejected_token_event(&context, AuditAction::GenerateKey, reason, &e)).await;
+                return Ok(Json(GenerateTokenResponse {
+                    success: false,
+                    tailscale_token: None,
+                    message,
+                    reason: Some(reason),
+                    retry_after: None,
+                }).into_response());
+            }
+        };
+
+        // Check user authorization (this also validates their current status)
+        let context = context.with_actor(&identity.email);
+        match check_user_authorization(state.store.as_ref(), &identity).await {
+            Ok(user) => (user, context, None),
+            Err(e) => {
+                audit::record(state.store.as_ref(), AuditEvent {
+                    details: json!({ "issuer": identity.issuer, "error": e }),
+                    ..context.event(AuditAction::GenerateKey, AuditOutcome::Failure)
+                }).await;
+                return Ok(Json(GenerateTokenResponse {
+                    success: false,
+                    tailscale_token: None,
+                    message: e,
+                    reason: None,
+                    retry_after: None,
+                }).into_response());
+            }
         }
     };
 
     // Only approved users can generate tokens
-    if authorized_user.status != "approved" {
-        let message = match authorized_user.status.as_str() {
-            "pending" => "Your account is pending approval. Cannot generate tokens yet.".to_string(),
-            "denied" => "Your account has been denied access. Cannot generate tokens.".to_string(),
-            _ => "Account status does not allow token generation.".to_string(),
-        };
+    if authorized_user.status != UserStatus::Approved {
+        let message = match authorized_user.status {
+            UserStatus::Pending => "Your account is pending approval. Cannot generate tokens yet.",
+            UserStatus::Denied => "Your account has been denied access. Cannot generate tokens.",
+            UserStatus::Suspended => "Your account has been suspended. Cannot generate tokens.",
+            UserStatus::Expired => "Your access has expired and must be renewed by an administrator. Cannot generate tokens.",
+            UserStatus::Approved => unreachable!(),
+        }.to_string();
+
+        audit::record(state.store.as_ref(), AuditEvent {
+            subject_user_id: Some(authorized_user.id.clone()),
+            details: json!({ "status": authorized_user.status }),
+            ..context.event(AuditAction::GenerateKey, AuditOutcome::Denied)
+        }).await;
 
         return Ok(Json(GenerateTokenResponse {
             success: false,
             tailscale_token: None,
             message,
-        }));
+            reason: None,
+            retry_after: None,
+        }).into_response());
+    }
+
+    if let Err(message) = request.device.validate() {
+        info!("User {} sent invalid device details: {}", authorized_user.email, message);
+        audit::record(state.store.as_ref(), AuditEvent {
+            subject_user_id: Some(authorized_user.id.clone()),
+            details: json!({ "reason": "invalid_device", "error": message }),
+            ..context.event(AuditAction::GenerateKey, AuditOutcome::Failure)
+        }).await;
+        return Ok(Json(GenerateTokenResponse {
+            success: false,
+            tailscale_token: None,
+            message,
+            reason: Some("invalid_device"),
+            retry_after: None,
+        }).into_response());
+    }
+
+    // Pick the key profile, which may need a permission the user was granted
+    let permissions = load_permissions(&state, &authorized_user).await?
+        .into_iter()
+        .map(|grant| grant.permission)
+        .collect::<Vec<_>>();
+    let mut profile = match select_key_profile(request.profile.as_deref(), &permissions) {
+        Ok(profile) => profile.clone(),
+        Err((reason, message)) => {
+            info!("User {} cannot use key profile {:?}: {}", authorized_user.email, request.profile, message);
+            audit::record(state.store.as_ref(), AuditEvent {
+                subject_user_id: Some(authorized_user.id.clone()),
+                details: json!({ "profile": request.profile, "reason": reason }),
+                ..context.event(AuditAction::GenerateKey, AuditOutcome::Denied)
+            }).await;
+            return Ok(Json(GenerateTokenResponse {
+                success: false,
+                tailscale_token: None,
+                message,
+                reason: Some(reason),
+                retry_after: None,
+            }).into_response());
+        }
+    };
+
+    if request.single_use {
+        profile.reusable = false;
     }
 
+    // Keys are counted from our own records, so check quotas before asking the provider,
+    // holding the user's place until the new key is recorded
+    let _reservation = match quota::reserve(state.store.as_ref(), &authorized_user.id).await {
+        Ok(reservation) => reservation,
+        Err(QuotaError::Exceeded(exceeded)) => {
+            info!("User {} reached their {} quota", authorized_user.email, exceeded.limit);
+            audit::record(state.store.as_ref(), AuditEvent {
+                subject_user_id: Some(authorized_user.id.clone()),
+                details: json!({ "reason": "quota_exceeded", "limit": exceeded.limit, "retry_after": exceeded.retry_after }),
+                ..context.event(AuditAction::GenerateKey, AuditOutcome::Denied)
+            }).await;
+
+            let mut headers = HeaderMap::new();
+            if let Some(seconds) = exceeded.retry_after {
+                headers.insert(RETRY_AFTER, HeaderValue::from(seconds));
+            }
+            return Ok((StatusCode::TOO_MANY_REQUESTS, headers, Json(GenerateTokenResponse {
+                success: false,
+                tailscale_token: None,
+                message: exceeded.message,
+                reason: Some("quota_exceeded"),
+                retry_after: exceeded.retry_after,
+            })).into_response());
+        }
+        Err(QuotaError::Failed(e)) => {
+            error!("Failed to check quotas of {}: {}", authorized_user.email, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
     // Generate Tailscale auth key
-    match tailscale::generate_auth_key(&authorized_user.email).await {
+    match get_provider().generate_auth_key(&authorized_user.email, &profile, &request.device).await {
         Ok(auth_key) => {
+            let record = AuthKey {
+                id: auth_key.id,
+                user_id: authorized_user.id.clone(),
+                tags: profile.tags.join(","),
+                created_at: auth_key.created,
+                expires_at: auth_key.expires,
+                request_ip: context.ip.clone(),
+                revoked_at: None,
+                api_token_id,
+                profile: Some(profile.name.clone()),
+                reusable: Some(profile.reusable),
+                device: request.device,
+            };
+
+            // An unrecorded key would escape the quotas and key revocation, so take it back
+            if let Err(e) = state.store.insert_auth_key(&record).await {
+                error!("Failed to record auth key {} for {}: {}", record.id, authorized_user.email, e);
+                if let Err(e) = get_provider().revoke_auth_key(&record.id).await {
+                    error!("Failed to revoke unrecorded auth key {}: {}", record.id, e);
+                }
+                audit::record(state.store.as_ref(), AuditEvent {
+                    subject_user_id: Some(authorized_user.id.clone()),
+                    details: json!({ "key_id": record.id, "error": e.to_string() }),
+                    ..context.event(AuditAction::GenerateKey, AuditOutcome::Failure)
+                }).await;
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+
+            audit::record(state.store.as_ref(), AuditEvent {
+                subject_user_id: Some(authorized_user.id.clone()),
+                details: json!({
+                    "key_id": record.id,
+                    "profile": record.profile,
+                    "reusable": record.reusable,
+                    "device": record.device,
+                    "tags": record.tags,
+                    "expires_at": record.expires_at,
+                    "api_token": record.api_token_id,
+                }),
+                ..context.event(AuditAction::GenerateKey, AuditOutcome::Success)
+            }).await;
+
             Ok(Json(GenerateTokenResponse {
                 success: true,
-                tailscale_token: Some(auth_key),
+                tailscale_token: Some(auth_key.key),
                 message: "Tailscale auth key generated successfully".to_string(),
-            }))
+                reason: None,
+                retry_after: None,
+            }).into_response())
         }
         Err(e) => {
             error!("Failed to generate Tailscale auth key for {}: {}", authorized_user.email, e);
+            audit::record(state.store.as_ref(), AuditEvent {
+                subject_user_id: Some(authorized_user.id.clone()),
+                details: json!({ "error": e.to_string() }),
+                ..context.event(AuditAction::GenerateKey, AuditOutcome::Failure)
+            }).await;
             Ok(Json(GenerateTokenResponse {
                 success: false,
                 tailscale_token: None,
                 message: "Unable to generate network access token. Please try again later or contact support if this persists.".to_string(),
-            }))
+                reason: None,
+                retry_after: None,
+            }).into_response())
+        }
+    }
+}
+
+/// Choose the key profile for a request, checking the user may use it
+///
+/// Returns the machine-readable reason and a message when the request names an
+/// unknown or forbidden profile, or names none and there is no default.
+fn select_key_profile(requested: Option<&str>, permissions: &[String]) -> Result<&'static KeyProfile, (&'static str, String)> {
+    let profile = match requested {
+        Some(name) => network::find_key_profile(name)
+            .ok_or_else(|| ("unknown_profile", format!("There is no key profile named {}", name)))?,
+        None => network::default_key_profile()
+            .ok_or_else(|| ("profile_required", "Choose a key profile; there is no default".to_string()))?,
+    };
+
+    if !network::may_use_key_profile(profile, permissions) {
+        return Err(("profile_not_allowed", format!("You are not allowed to request {} keys", profile.name)));
+    }
+
+    Ok(profile)
+}
+
+/// List the key profiles the caller may request keys with
+pub async fn list_key_profiles(caller: AuthenticatedUser) -> Json<KeyProfilesResponse> {
+    let profiles = network::allowed_key_profiles(&caller.permissions);
+
+    Json(KeyProfilesResponse {
+        success: true,
+        message: format!("You may use {} key profile(s)", profiles.len()),
+        default_profile: network::default_key_profile().map(|profile| profile.name.clone()),
+        profiles,
+    })
+}
+
+/// The client address and user agent of a request, for the audit log
+///
+/// The address is the client's own when it arrived through trusted proxies.
+/// The actor is left unset until the caller has been identified.
+#[async_trait]
+impl FromRequestParts<AppState> for AuditContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &AppState) -> Result<Self, Self::Rejection> {
+        Ok(AuditContext {
+            actor: None,
+            ip: parts.extensions.get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| proxy::client_ip(&parts.headers, addr.ip()).to_string()),
+            user_agent: parts.headers.get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        })
+    }
+}
+
+/// A caller authenticated by an ID token or session, with their permissions loaded
+///
+/// The bearer token or session cookie must be a valid session token, or a
+/// valid ID token belonging to a user who has signed in at least once.
+/// Emails listed in the admin configuration are bootstrap administrators and
+/// are granted the admin permission the first time they are seen here.
+pub struct AuthenticatedUser {
+    pub user: User,
+    pub permissions: Vec<String>,
+    pub session_id: Option<String>,  // Set when the caller used a session token
+}
+
+impl AuthenticatedUser {
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|granted| granted == permission)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthenticatedUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let token = request_token(&parts.headers)?;
+
+        let (user, session_id) = if session::is_session_token(token) {
+            let (user, claims) = session_user(state, token).await.map_err(|e| {
+                info!("Session validation failed: {}", e);
+                StatusCode::UNAUTHORIZED
+            })?;
+            (user, Some(claims.sid))
+        } else {
+            let caller = verify_id_token(token).await.map_err(|e| {
+                info!("Token validation failed: {}", e);
+                StatusCode::UNAUTHORIZED
+            })?;
+
+            let user = find_user_for_identity(state.store.as_ref(), &caller).await
+                .map_err(|e| {
+                    error!("Failed to look up user {}: {}", caller.email, e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?
+                .ok_or(StatusCode::FORBIDDEN)?;
+            (user, None)
+        };
+
+        let mut permissions: Vec<String> = state.store.list_permissions(&user.id).await
+            .map_err(|e| {
+                error!("Failed to load permissions of {}: {}", user.email, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .into_iter()
+            .map(|grant| grant.permission)
+            .collect();
+
+        if get_config().is_admin_email(&user.email) && !permissions.iter().any(|permission| permission == PERMISSION_ADMIN) {
+            let verified = email_verified(state.store.as_ref(), &user).await.map_err(|e| {
+                error!("Failed to load identities of {}: {}", user.email, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+            if !verified {
+                warn!("Not granting {} permission to configured admin {}: no linked provider verifies the email", PERMISSION_ADMIN, user.email);
+            } else {
+                match state.store.grant_permission(&user.id, PERMISSION_ADMIN).await {
+                    Ok(granted) => {
+                        if granted {
+                            info!("Granted {} permission to configured admin {}", PERMISSION_ADMIN, user.email);
+                            let context = AuditContext::from_request_parts(parts, state).await.unwrap_or_default();
+                            audit::record(state.store.as_ref(), AuditEvent {
+                                subject_user_id: Some(user.id.clone()),
+                                details: json!({ "permission": PERMISSION_ADMIN, "source": "admin.emails" }),
+                                ..context.event(AuditAction::GrantPermission, AuditOutcome::Success)
+                            }).await;
+                        }
+                        permissions.push(PERMISSION_ADMIN.to_string());
+                    }
+                    Err(e) => {
+                        error!("Failed to grant {} permission to {}: {}", PERMISSION_ADMIN, user.email, e);
+                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                    }
+                }
+            }
+        }
+
+        Ok(AuthenticatedUser { user, permissions, session_id })
+    }
+}
+
+/// Check whether a user signed in through a provider that verifies emails
+///
+/// Configured admin emails are only trusted from such providers.
+async fn email_verified(users: &dyn UserStore, user: &User) -> anyhow::Result<bool> {
+    let identities = users.list_identities(&user.id).await?;
+
+    Ok(identities.iter().any(|identity| {
+        oidc::find_provider(&identity.issuer).is_some_and(|provider| provider.verifies_email())
+    }))
+}
+
+/// A caller allowed to use the admin API
+///
+/// Admins may still be pending (nobody else can approve the first admin),
+/// but a denied, suspended or expired admin is locked out.
+pub struct AdminUser(pub User);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let caller = AuthenticatedUser::from_request_parts(parts, state).await?;
+
+        let locked_out = matches!(caller.user.status, UserStatus::Denied | UserStatus::Suspended | UserStatus::Expired);
+        if locked_out || !caller.has_permission(PERMISSION_ADMIN) {
+            warn!("User {} attempted to use the admin API", caller.user.email);
+            let context = AuditContext::from_request_parts(parts, state).await.unwrap_or_default();
+            audit::record(state.store.as_ref(), AuditEvent {
+                subject_user_id: Some(caller.user.id.clone()),
+                details: json!({ "method": parts.method.as_str(), "path": parts.uri.path(), "status": caller.user.status }),
+                ..context.with_actor(&caller.user.email).event(AuditAction::AdminAccess, AuditOutcome::Denied)
+            }).await;
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        Ok(AdminUser(caller.user))
+    }
+}
+
+pub async fn list_users(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Query(query): Query<ListUsersQuery>,
+) -> Result<Json<AdminUsersResponse>, StatusCode> {
+    let users = state.store.list_users(query.status).await.map_err(|e| {
+        error!("Failed to list users: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(AdminUsersResponse {
+        success: true,
+        message: format!("Found {} user(s)", users.len()),
+        users,
+    }))
+}
+
+pub async fn approve_user(
+    State(state): State<AppState>,
+    admin: AdminUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+    body: Option<Json<UserStatusRequest>>,
+) -> Result<Json<AdminUserResponse>, StatusCode> {
+    change_user_status(&state, admin, context, &id, UserStatus::Approved, body).await
+}
+
+pub async fn deny_user(
+    State(state): State<AppState>,
+    admin: AdminUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+    body: Option<Json<UserStatusRequest>>,
+) -> Result<Json<AdminUserResponse>, StatusCode> {
+    change_user_status(&state, admin, context, &id, UserStatus::Denied, body).await
+}
+
+pub async fn pend_user(
+    State(state): State<AppState>,
+    admin: AdminUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+    body: Option<Json<UserStatusRequest>>,
+) -> Result<Json<AdminUserResponse>, StatusCode> {
+    change_user_status(&state, admin, context, &id, UserStatus::Pending, body).await
+}
+
+pub async fn suspend_user(
+    State(state): State<AppState>,
+    admin: AdminUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+    body: Option<Json<UserStatusRequest>>,
+) -> Result<Json<AdminUserResponse>, StatusCode> {
+    change_user_status(&state, admin, context, &id, UserStatus::Suspended, body).await
+}
+
+pub async fn expire_user(
+    State(state): State<AppState>,
+    admin: AdminUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+    body: Option<Json<UserStatusRequest>>,
+) -> Result<Json<AdminUserResponse>, StatusCode> {
+    change_user_status(&state, admin, context, &id, UserStatus::Expired, body).await
+}
+
+async fn change_user_status(
+    state: &AppState,
+    AdminUser(admin): AdminUser,
+    context: AuditContext,
+    id: &str,
+    status: UserStatus,
+    body: Option<Json<UserStatusRequest>>,
+) -> Result<Json<AdminUserResponse>, StatusCode> {
+    let reason = body.and_then(|Json(request)| request.reason);
+    let context = context.with_actor(&admin.email);
+    let event = AuditEvent {
+        subject_user_id: Some(id.to_string()),
+        ..context.event(AuditAction::SetStatus, AuditOutcome::Success)
+    };
+
+    let user = match state.store.set_user_status(id, status, reason.as_deref()).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            if let Some(transition) = e.downcast_ref::<IllegalTransition>() {
+                info!("Admin {} tried to change user {}: {}", admin.email, id, transition);
+                audit::record(state.store.as_ref(), AuditEvent {
+                    outcome: AuditOutcome::Denied,
+                    details: json!({ "from": transition.from, "status": status, "reason": reason }),
+                    ..event
+                }).await;
+                return Err(StatusCode::CONFLICT);
+            }
+            error!("Failed to set status of user {} to {}: {}", id, status, e);
+            audit::record(state.store.as_ref(), AuditEvent {
+                outcome: AuditOutcome::Failure,
+                details: json!({ "status": status, "reason": reason, "error": e.to_string() }),
+                ..event
+            }).await;
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    info!("Admin {} set user {} to {}", admin.email, user.email, status);
+    audit::record(state.store.as_ref(), AuditEvent {
+        details: json!({ "status": status, "reason": reason }),
+        ..event
+    }).await;
+
+    // Users who lose access must not keep using keys or devices enrolled while they were approved
+    let revocation_failures = if status.revokes_keys() {
+        revoke_keys(state, &context, &user).await?.1
+    } else {
+        Vec::new()
+    };
+    let device_failures = if status.removes_devices() {
+        remove_devices(state, &context, &user).await
+    } else {
+        Vec::new()
+    };
+    if status.ends_sessions() {
+        end_sessions(state, &context, &user).await?;
+    }
+
+    let message = if revocation_failures.is_empty() && device_failures.is_empty() {
+        format!("User {} is now {}", user.email, status)
+    } else {
+        format!(
+            "User {} is now {}, but {} auth key(s) could not be revoked and {} device removal(s) failed",
+            user.email, status, revocation_failures.len(), device_failures.len()
+        )
+    };
+
+    Ok(Json(AdminUserResponse {
+        success: true,
+        message,
+        user: Some(user),
+        revocation_failures,
+        device_failures,
+    }))
+}
+
+pub async fn delete_user(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+) -> Result<Json<AdminUserResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+    let context = context.with_actor(&admin.email);
+
+    // A deleted user's keys, sessions and machines go with them. Keys come first:
+    // once the user is gone, their keys can no longer be found to revoke
+    let (_, revocation_failures) = revoke_keys(&state, &context, &user).await?;
+    end_sessions(&state, &context, &user).await?;
+    let device_failures = remove_devices(&state, &context, &user).await;
+
+    if !revocation_failures.is_empty() {
+        warn!("Not deleting user {}: {} auth key(s) could not be revoked", user.email, revocation_failures.len());
+        audit::record(state.store.as_ref(), AuditEvent {
+            subject_user_id: Some(user.id.clone()),
+            details: json!({ "email": user.email, "reason": "key_revocation_failed", "failed_keys": revocation_failures.len() }),
+            ..context.event(AuditAction::DeleteUser, AuditOutcome::Failure)
+        }).await;
+        return Ok(Json(AdminUserResponse {
+            success: false,
+            message: format!(
+                "User {} was not deleted, as {} auth key(s) could not be revoked; retry the deletion",
+                user.email, revocation_failures.len()
+            ),
+            user: Some(user),
+            revocation_failures,
+            device_failures,
+        }));
+    }
+
+    let deleted = state.store.delete_user(&id).await.map_err(|e| {
+        error!("Failed to delete user {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if !deleted {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    info!("Admin {} deleted user {}", admin.email, id);
+    audit::record(state.store.as_ref(), AuditEvent {
+        subject_user_id: Some(user.id.clone()),
+        details: json!({ "email": user.email }),
+        ..context.event(AuditAction::DeleteUser, AuditOutcome::Success)
+    }).await;
+
+    Ok(Json(AdminUserResponse {
+        success: true,
+        user: None,
+        message: format!("User {} deleted", id),
+        revocation_failures: Vec::new(),
+        device_failures,
+    }))
+}
+
+/// Revoke a user's live auth keys, retrying any earlier failed revocations
+pub async fn revoke_user_keys(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+) -> Result<Json<RevokeKeysResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+    let (revoked, failures) = revoke_keys(&state, &context.with_actor(&admin.email), &user).await?;
+
+    info!("Admin {} revoked {} auth key(s) of user {}", admin.email, revoked.len(), user.email);
+
+    Ok(Json(RevokeKeysResponse {
+        success: failures.is_empty(),
+        message: format!("Revoked {} auth key(s) of user {}, {} failed", revoked.len(), user.email, failures.len()),
+        revoked,
+        failures,
+    }))
+}
+
+/// List the tailnet devices attributed to a user
+pub async fn list_user_devices(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<String>,
+) -> Result<Json<DevicesResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+
+    let attributed = devices::list_user_devices(state.store.as_ref(), &user.id).await.map_err(|e| {
+        error!("Failed to list devices of {}: {}", user.email, e);
+        StatusCode::BAD_GATEWAY
+    })?;
+
+    Ok(Json(DevicesResponse {
+        success: true,
+        message: format!(
+            "Found {} device(s) for user {}, {} that may also belong to another user",
+            attributed.devices.len(), user.email, attributed.ambiguous.len()
+        ),
+        devices: attributed.devices,
+        ambiguous: attributed.ambiguous,
+    }))
+}
+
+/// Remove the tailnet devices attributed to a user
+pub async fn remove_user_devices(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+) -> Result<Json<RemoveDevicesResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+
+    let (removed, failures) = devices::remove_user_devices(state.store.as_ref(), &user.id, &context.with_actor(&admin.email)).await.map_err(|e| {
+        error!("Failed to list devices of {}: {}", user.email, e);
+        StatusCode::BAD_GATEWAY
+    })?;
+
+    info!("Admin {} removed {} device(s) of user {}", admin.email, removed.len(), user.email);
+
+    Ok(Json(RemoveDevicesResponse {
+        success: failures.is_empty(),
+        message: format!("Removed {} device(s) of user {}, {} failed", removed.len(), user.email, failures.len()),
+        removed,
+        failures,
+    }))
+}
+
+async fn remove_devices(state: &AppState, context: &AuditContext, user: &User) -> Vec<DeviceRemovalFailure> {
+    match devices::remove_user_devices(state.store.as_ref(), &user.id, context).await {
+        Ok((_, failures)) => failures,
+        Err(e) => {
+            error!("Failed to list devices of {}: {}", user.email, e);
+            vec![DeviceRemovalFailure {
+                device_id: String::new(),
+                error: e.to_string(),
+            }]
+        }
+    }
+}
+
+async fn revoke_keys(state: &AppState, context: &AuditContext, user: &User) -> Result<(Vec<String>, Vec<KeyRevocationFailure>), StatusCode> {
+    network::revoke_user_keys(state.store.as_ref(), &user.id, context).await.map_err(|e| {
+        error!("Failed to load auth keys of {}: {}", user.email, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+pub async fn list_user_permissions(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<String>,
+) -> Result<Json<PermissionsResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+    let permissions = load_permissions(&state, &user).await?;
+
+    Ok(Json(PermissionsResponse {
+        success: true,
+        message: format!("User {} has {} permission(s)", user.email, permissions.len()),
+        permissions,
+    }))
+}
+
+pub async fn grant_user_permission(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+    Json(request): Json<PermissionRequest>,
+) -> Result<Json<PermissionsResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+
+    let permission = request.permission.trim();
+    if permission.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let granted = state.store.grant_permission(&user.id, permission).await.map_err(|e| {
+        error!("Failed to grant {} to user {}: {}", permission, user.email, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let message = if granted {
+        info!("Admin {} granted {} to user {}", admin.email, permission, user.email);
+        audit::record(state.store.as_ref(), AuditEvent {
+            subject_user_id: Some(user.id.clone()),
+            details: json!({ "permission": permission }),
+            ..context.with_actor(&admin.email).event(AuditAction::GrantPermission, AuditOutcome::Success)
+        }).await;
+        format!("Granted {} to user {}", permission, user.email)
+    } else {
+        format!("User {} already has {}", user.email, permission)
+    };
+
+    Ok(Json(PermissionsResponse {
+        success: true,
+        permissions: load_permissions(&state, &user).await?,
+        message,
+    }))
+}
+
+pub async fn revoke_user_permission(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    context: AuditContext,
+    Path((id, permission)): Path<(String, String)>,
+) -> Result<Json<PermissionsResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+
+    let revoked = state.store.revoke_permission(&user.id, &permission).await.map_err(|e| {
+        error!("Failed to revoke {} from user {}: {}", permission, user.email, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if !revoked {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    info!("Admin {} revoked {} from user {}", admin.email, permission, user.email);
+    audit::record(state.store.as_ref(), AuditEvent {
+        subject_user_id: Some(user.id.clone()),
+        details: json!({ "permission": permission }),
+        ..context.with_actor(&admin.email).event(AuditAction::RevokePermission, AuditOutcome::Success)
+    }).await;
+
+    Ok(Json(PermissionsResponse {
+        success: true,
+        permissions: load_permissions(&state, &user).await?,
+        message: format!("Revoked {} from user {}", permission, user.email),
+    }))
+}
+
+/// List the identity provider accounts linked to a user
+pub async fn list_user_identities(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<String>,
+) -> Result<Json<IdentitiesResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+
+    let identities = state.store.list_identities(&user.id).await.map_err(|e| {
+        error!("Failed to load identities of {}: {}", user.email, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(IdentitiesResponse {
+        success: true,
+        message: format!("User {} has {} linked identities", user.email, identities.len()),
+        identities,
+    }))
+}
+
+/// List a user's live sessions
+pub async fn list_user_sessions(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<String>,
+) -> Result<Json<SessionsResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+    let sessions = load_sessions(&state, &user).await?;
+
+    Ok(Json(SessionsResponse {
+        success: true,
+        message: format!("User {} has {} live session(s)", user.email, sessions.len()),
+        sessions,
+        current: None,
+    }))
+}
+
+/// End every live session of a user, signing them out everywhere
+pub async fn end_user_sessions(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+) -> Result<Json<EndSessionsResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+    let ended = end_sessions(&state, &context.with_actor(&admin.email), &user).await?;
+
+    info!("Admin {} ended {} session(s) of user {}", admin.email, ended.len(), user.email);
+
+    Ok(Json(EndSessionsResponse {
+        success: true,
+        message: format!("Ended {} session(s) of user {}", ended.len(), user.email),
+        ended,
+    }))
+}
+
+async fn load_sessions(state: &AppState, user: &User) -> Result<Vec<Session>, StatusCode> {
+    state.store.list_live_sessions(&user.id).await.map_err(|e| {
+        error!("Failed to load sessions of {}: {}", user.email, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn end_sessions(state: &AppState, context: &AuditContext, user: &User) -> Result<Vec<String>, StatusCode> {
+    session::end_user_sessions(state.store.as_ref(), &user.id, context).await.map_err(|e| {
+        error!("Failed to end sessions of {}: {}", user.email, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// List a user's API tokens that have not been revoked
+pub async fn list_user_api_tokens(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<String>,
+) -> Result<Json<ApiTokensResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+    let api_tokens = load_api_tokens(&state, &user).await?;
+
+    Ok(Json(ApiTokensResponse {
+        success: true,
+        message: format!("User {} has {} API token(s)", user.email, api_tokens.len()),
+        api_tokens,
+    }))
+}
+
+/// Revoke one of a user's API tokens
+pub async fn revoke_user_api_token(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    context: AuditContext,
+    Path((id, token_id)): Path<(String, String)>,
+) -> Result<Json<ApiTokenResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+    revoke_token(&state, &context.with_actor(&admin.email), &user, &token_id).await
+}
+
+async fn load_api_tokens(state: &AppState, user: &User) -> Result<Vec<ApiToken>, StatusCode> {
+    state.store.list_api_tokens(&user.id).await.map_err(|e| {
+        error!("Failed to load API tokens of {}: {}", user.email, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// Revoke an API token of a user; tokens of other users are reported as missing
+async fn revoke_token(state: &AppState, context: &AuditContext, user: &User, id: &str) -> Result<Json<ApiTokenResponse>, StatusCode> {
+    let api_token = state.store.find_api_token(id).await
+        .map_err(|e| {
+            error!("Failed to look up API token {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .filter(|api_token| api_token.user_id == user.id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let revoked = state.store.revoke_api_token(&api_token.id).await.map_err(|e| {
+        error!("Failed to revoke API token {}: {}", api_token.id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if !revoked {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    info!("API token {} ({}) of user {} revoked", api_token.id, api_token.name, user.email);
+    audit::record(state.store.as_ref(), AuditEvent {
+        subject_user_id: Some(user.id.clone()),
+        details: json!({ "api_token": api_token.id, "name": api_token.name }),
+        ..context.event(AuditAction::RevokeApiToken, AuditOutcome::Success)
+    }).await;
+
+    Ok(Json(ApiTokenResponse {
+        success: true,
+        message: format!("API token {} revoked", api_token.name),
+        api_token: None,
+        token: None,
+    }))
+}
+
+/// Show the limits that apply to a user, their overrides and how much they have used
+pub async fn get_user_quota(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<String>,
+) -> Result<Json<QuotaResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+    let message = format!("Quota of user {}", user.email);
+    quota_response(&state, &user, message).await
+}
+
+/// Override some or all of the configured limits for a user
+pub async fn set_user_quota(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+    Json(request): Json<QuotaRequest>,
+) -> Result<Json<QuotaResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+
+    quota::set(state.store.as_ref(), &user.id, &request, &context.with_actor(&admin.email)).await.map_err(|e| {
+        error!("Failed to set quota of {}: {}", user.email, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    info!("Quota of user {} set by {}", user.email, admin.email);
+    let message = format!("Quota of user {} updated", user.email);
+    quota_response(&state, &user, message).await
+}
+
+/// Remove a user's overrides so the configured limits apply again
+pub async fn clear_user_quota(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    context: AuditContext,
+    Path(id): Path<String>,
+) -> Result<Json<QuotaResponse>, StatusCode> {
+    let user = find_user(&state, &id).await?;
+
+    let cleared = quota::clear(state.store.as_ref(), &user.id, &context.with_actor(&admin.email)).await.map_err(|e| {
+        error!("Failed to clear quota of {}: {}", user.email, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if !cleared {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    info!("Quota of user {} cleared by {}", user.email, admin.email);
+    let message = format!("User {} now has the configured limits", user.email);
+    quota_response(&state, &user, message).await
+}
+
+async fn quota_response(state: &AppState, user: &User, message: String) -> Result<Json<QuotaResponse>, StatusCode> {
+    let load = async {
+        let overrides = state.store.find_user_quota(&user.id).await?;
+        let usage = quota::usage(state.store.as_ref(), &user.id).await?;
+        anyhow::Ok((overrides, usage))
+    };
+    let (overrides, usage) = load.await.map_err(|e| {
+        error!("Failed to load quota of {}: {}", user.email, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(QuotaResponse {
+        success: true,
+        limits: quota::effective_limits(overrides.as_ref()),
+        overrides,
+        usage,
+        message,
+    }))
+}
+
+/// Query the audit log, newest events first
+pub async fn list_audit_events(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<AuditEventsResponse>, StatusCode> {
+    let events = state.store.list_audit_events(&query).await.map_err(|e| {
+        error!("Failed to query audit events: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(AuditEventsResponse {
+        success: true,
+        message: format!("Found {} audit event(s)", events.len()),
+        events,
+    }))
+}
+
+async fn find_user(state: &AppState, id: &str) -> Result<User, StatusCode> {
+    state.store.find_user_by_id(id).await
+        .map_err(|e| {
+            error!("Failed to look up user {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn load_permissions(state: &AppState, user: &User) -> Result<Vec<UserPermission>, StatusCode> {
+    state.store.list_permissions(&user.id).await.map_err(|e| {
+        error!("Failed to load permissions of {}: {}", user.email, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use std::sync::Arc;
+    use crate::config::init_test_config;
+    use crate::memory_store::MemoryStore;
+    use crate::models::DeviceInfo;
+    use crate::store::{AuditStore, AuthKeyStore, SessionStore};
+
+    fn test_state() -> (AppState, Arc<MemoryStore>) {
+        init_test_config();
+        let store = Arc::new(MemoryStore::new());
+        (AppState::with_store(store.clone()), store)
+    }
+
+    fn identity(issuer: &str, subject: &str, email: &str, email_verified: bool) -> VerifiedIdentity {
+        VerifiedIdentity {
+            issuer: issuer.to_string(),
+            subject: subject.to_string(),
+            email: email.to_string(),
+            name: Some("Test User".to_string()),
+            email_verified,
+        }
+    }
+
+    async fn add_user(store: &MemoryStore, email: &str, status: UserStatus) -> User {
+        let now = Utc::now();
+        let user = User {
+            id: format!("user-{}", email),
+            email: email.to_string(),
+            name: "Test User".to_string(),
+            status,
+            created_at: now,
+            last_login: now,
+            status_reason: None,
+            status_updated_at: None,
+        };
+        store.create_user(&user, &identity("https://accounts.google.com", &user.id, email, true)).await.unwrap();
+        user
+    }
+
+    async fn audit_events(store: &MemoryStore, action: AuditAction) -> Vec<AuditEvent> {
+        let query = AuditQuery { user_id: None, action: Some(action), since: None, until: None, limit: None };
+        store.list_audit_events(&query).await.unwrap()
+    }
+
+    fn admin() -> AdminUser {
+        let now = Utc::now();
+        AdminUser(User {
+            id: "admin".to_string(),
+            email: "admin@example.com".to_string(),
+            name: "Admin".to_string(),
+            status: UserStatus::Approved,
+            created_at: now,
+            last_login: now,
+            status_reason: None,
+            status_updated_at: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn new_identity_creates_a_pending_user() {
+        let (_, store) = test_state();
+        let caller = identity("https://sso.example.com", "abc", "new@example.com", true);
+
+        let user = check_user_authorization(store.as_ref(), &caller).await.unwrap();
+
+        assert_eq!(user.status, UserStatus::Pending);
+        assert_eq!(user.email, "new@example.com");
+        let linked = store.find_user_by_identity("https://sso.example.com", "abc").await.unwrap().unwrap();
+        assert_eq!(linked.id, user.id);
+    }
+
+    #[tokio::test]
+    async fn approval_rules_approve_only_verified_emails() {
+        let (_, store) = test_state();
+        let verified = identity("https://accounts.google.com", "alice", "alice@approved.example.com", true);
+        let unverified = identity("https://sso.example.com", "mallory", "mallory@approved.example.com", false);
+
+        let approved = check_user_authorization(store.as_ref(), &verified).await.unwrap();
+        let pending = check_user_authorization(store.as_ref(), &unverified).await.unwrap();
+
+        assert_eq!(approved.status, UserStatus::Approved);
+        assert_eq!(pending.status, UserStatus::Pending);
+        assert_eq!(pending.status_reason, None);
+    }
+
+    #[test]
+    fn key_requests_name_a_known_profile() {
+        init_test_config();
+
+        assert_eq!(select_key_profile(None, &[]).unwrap().name, network::DEFAULT_KEY_PROFILE);
+        assert_eq!(select_key_profile(Some(network::DEFAULT_KEY_PROFILE), &[]).unwrap().name, network::DEFAULT_KEY_PROFILE);
+        assert_eq!(select_key_profile(Some("desktop"), &[]).unwrap_err().0, "unknown_profile");
+    }
+
+    #[tokio::test]
+    async fn linked_identity_signs_in_its_user() {
+        let (_, store) = test_state();
+        let existing = add_user(&store, "alice@example.com", UserStatus::Approved).await;
+        let caller = identity("https://accounts.google.com", &existing.id, "alice@example.com", true);
+
+        let user = check_user_authorization(store.as_ref(), &caller).await.unwrap();
+
+        assert_eq!(user.id, existing.id);
+        assert_eq!(user.status, UserStatus::Approved);
+        assert!(user.last_login >= existing.last_login);
+    }
+
+    #[tokio::test]
+    async fn verified_email_links_a_new_identity_to_the_existing_user() {
+        let (_, store) = test_state();
+        let existing = add_user(&store, "alice@example.com", UserStatus::Approved).await;
+        let caller = identity("https://sso.example.com", "alice-corp", "alice@example.com", true);
+
+        let user = check_user_authorization(store.as_ref(), &caller).await.unwrap();
+
+        assert_eq!(user.id, existing.id);
+        assert_eq!(store.list_identities(&existing.id).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn unverified_email_cannot_take_over_an_existing_user() {
+        let (_, store) = test_state();
+        let existing = add_user(&store, "alice@example.com", UserStatus::Approved).await;
+        let caller = identity("https://sso.example.com", "mallory", "alice@example.com", false);
+
+        assert!(check_user_authorization(store.as_ref(), &caller).await.is_err());
+        assert!(store.find_user_by_identity("https://sso.example.com", "mallory").await.unwrap().is_none());
+        assert_eq!(store.list_identities(&existing.id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn approving_a_pending_user_is_audited() {
+        let (state, store) = test_state();
+        let user = add_user(&store, "bob@example.com", UserStatus::Pending).await;
+        let body = Some(Json(UserStatusRequest { reason: Some("On the team".to_string()) }));
+
+        let Json(response) = change_user_status(&state, admin(), AuditContext::default(), &user.id, UserStatus::Approved, body)
+            .await
+            .unwrap();
+
+        let updated = response.user.unwrap();
+        assert_eq!(updated.status, UserStatus::Approved);
+        assert_eq!(updated.status_reason.as_deref(), Some("On the team"));
+
+        let events = audit_events(&store, AuditAction::SetStatus).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].outcome, AuditOutcome::Success);
+        assert_eq!(events[0].actor.as_deref(), Some("admin@example.com"));
+        assert_eq!(events[0].subject_user_id.as_deref(), Some(user.id.as_str()));
+    }
+
+    #[tokio::test]
+    async fn illegal_transition_is_refused_and_audited() {
+        let (state, store) = test_state();
+        let user = add_user(&store, "bob@example.com", UserStatus::Pending).await;
+
+        let result = change_user_status(&state, admin(), AuditContext::default(), &user.id, UserStatus::Suspended, None).await;
+
+        assert_eq!(result.err(), Some(StatusCode::CONFLICT));
+        assert_eq!(store.find_user_by_id(&user.id).await.unwrap().unwrap().status, UserStatus::Pending);
+        let events = audit_events(&store, AuditAction::SetStatus).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].outcome, AuditOutcome::Denied);
+    }
+
+    #[tokio::test]
+    async fn unknown_user_is_not_found() {
+        let (state, _) = test_state();
+
+        let result = change_user_status(&state, admin(), AuditContext::default(), "missing", UserStatus::Approved, None).await;
+
+        assert_eq!(result.err(), Some(StatusCode::NOT_FOUND));
+    }
+
+    fn live_session(user: &User) -> Session {
+        let now = Utc::now();
+        Session {
+            id: "session".to_string(),
+            user_id: user.id.clone(),
+            created_at: now,
+            last_seen: now,
+            expires_at: now + Duration::days(1),
+            ip: None,
+            user_agent: None,
+            ended_at: None,
+            refresh_generation: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn suspending_a_user_ends_their_sessions() {
+        let (state, store) = test_state();
+        let user = add_user(&store, "bob@example.com", UserStatus::Approved).await;
+        store.insert_session(&live_session(&user)).await.unwrap();
+
+        let Json(response) = change_user_status(&state, admin(), AuditContext::default(), &user.id, UserStatus::Suspended, None)
+            .await
+            .unwrap();
+
+        assert_eq!(response.user.unwrap().status, UserStatus::Suspended);
+        assert!(response.revocation_failures.is_empty());
+        assert!(store.list_live_sessions(&user.id).await.unwrap().is_empty());
+        assert_eq!(audit_events(&store, AuditAction::EndSessions).await.len(), 1);
+    }
+
+    fn live_key(user: &User) -> AuthKey {
+        let now = Utc::now();
+        AuthKey {
+            id: "key".to_string(),
+            user_id: user.id.clone(),
+            tags: "tag:user".to_string(),
+            created_at: now,
+            expires_at: now + Duration::hours(2),
+            request_ip: None,
+            revoked_at: None,
+            api_token_id: None,
+            profile: None,
+            reusable: Some(true),
+            device: DeviceInfo::default(),
+        }
+    }
+
+    // The test configuration has no provider credentials, so every revocation and
+    // device listing fails; a failure shows the handler attempted it
+
+    #[tokio::test]
+    async fn suspending_a_user_revokes_their_keys_but_keeps_their_devices() {
+        let (state, store) = test_state();
+        let user = add_user(&store, "bob@example.com", UserStatus::Approved).await;
+        store.insert_auth_key(&live_key(&user)).await.unwrap();
+
+        let Json(response) = change_user_status(&state, admin(), AuditContext::default(), &user.id, UserStatus::Suspended, None)
+            .await
+            .unwrap();
+
+        assert_eq!(response.revocation_failures.len(), 1);
+        assert!(response.device_failures.is_empty());
+    }
+
+    #[tokio::test]
+    async fn expiring_a_user_revokes_their_keys_but_keeps_their_sessions_and_devices() {
+        let (state, store) = test_state();
+        let user = add_user(&store, "bob@example.com", UserStatus::Approved).await;
+        store.insert_auth_key(&live_key(&user)).await.unwrap();
+        store.insert_session(&live_session(&user)).await.unwrap();
+
+        let Json(response) = change_user_status(&state, admin(), AuditContext::default(), &user.id, UserStatus::Expired, None)
+            .await
+            .unwrap();
+
+        assert_eq!(response.user.unwrap().status, UserStatus::Expired);
+        assert_eq!(response.revocation_failures.len(), 1);
+        assert!(response.device_failures.is_empty());
+        assert_eq!(store.list_live_sessions(&user.id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn denying_a_user_revokes_keys_removes_devices_and_ends_sessions() {
+        let (state, store) = test_state();
+        let user = add_user(&store, "bob@example.com", UserStatus::Approved).await;
+        store.insert_auth_key(&live_key(&user)).await.unwrap();
+        store.insert_session(&live_session(&user)).await.unwrap();
+
+        let Json(response) = change_user_status(&state, admin(), AuditContext::default(), &user.id, UserStatus::Denied, None)
+            .await
+            .unwrap();
+
+        assert_eq!(response.revocation_failures.len(), 1);
+        assert!(!response.device_failures.is_empty());
+        assert!(store.list_live_sessions(&user.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn approving_a_user_leaves_their_keys_and_sessions_alone() {
+        let (state, store) = test_state();
+        let user = add_user(&store, "bob@example.com", UserStatus::Pending).await;
+        store.insert_auth_key(&live_key(&user)).await.unwrap();
+        store.insert_session(&live_session(&user)).await.unwrap();
+
+        let Json(response) = change_user_status(&state, admin(), AuditContext::default(), &user.id, UserStatus::Approved, None)
+            .await
+            .unwrap();
+
+        assert!(response.revocation_failures.is_empty());
+        assert!(response.device_failures.is_empty());
+        assert_eq!(store.list_live_auth_keys(&user.id).await.unwrap().len(), 1);
+        assert_eq!(store.list_live_sessions(&user.id).await.unwrap().len(), 1);
+    }
+
+    async fn admin_request(state: &AppState, store: &MemoryStore, status: UserStatus) -> Result<AdminUser, StatusCode> {
+        let user = add_user(store, &format!("{}-admin@example.com", status), status).await;
+        store.grant_permission(&user.id, PERMISSION_ADMIN).await.unwrap();
+        let tokens = session::issue(store, &user, &AuditContext::default()).await.unwrap();
+
+        let (mut parts, _) = axum::http::Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {}", tokens.token))
+            .body(())
+            .unwrap()
+            .into_parts();
+        AdminUser::from_request_parts(&mut parts, state).await
+    }
+
+    #[tokio::test]
+    async fn only_admins_in_good_standing_may_use_the_admin_api() {
+        let (state, store) = test_state();
+
+        for status in [UserStatus::Pending, UserStatus::Approved] {
+            assert!(admin_request(&state, &store, status).await.is_ok(), "{}", status);
+        }
+        for status in [UserStatus::Denied, UserStatus::Suspended, UserStatus::Expired] {
+            assert_eq!(admin_request(&state, &store, status).await.err(), Some(StatusCode::FORBIDDEN), "{}", status);
         }
+        assert_eq!(audit_events(&store, AuditAction::AdminAccess).await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_user_ends_their_sessions() {
+        let (state, store) = test_state();
+        let user = add_user(&store, "bob@example.com", UserStatus::Approved).await;
+        store.insert_session(&live_session(&user)).await.unwrap();
+
+        let Json(response) = delete_user(State(state), admin(), AuditContext::default(), Path(user.id.clone()))
+            .await
+            .unwrap();
+
+        assert!(response.success);
+        assert!(store.find_user_by_id(&user.id).await.unwrap().is_none());
+        assert_eq!(audit_events(&store, AuditAction::EndSessions).await.len(), 1);
+        assert_eq!(audit_events(&store, AuditAction::DeleteUser).await[0].outcome, AuditOutcome::Success);
+    }
+
+    #[tokio::test]
+    async fn user_whose_keys_cannot_be_revoked_is_not_deleted() {
+        let (state, store) = test_state();
+        let user = add_user(&store, "bob@example.com", UserStatus::Approved).await;
+        store.insert_auth_key(&live_key(&user)).await.unwrap();
+
+        let Json(response) = delete_user(State(state), admin(), AuditContext::default(), Path(user.id.clone()))
+            .await
+            .unwrap();
+
+        assert!(!response.success);
+        assert_eq!(response.revocation_failures.len(), 1);
+        assert!(store.find_user_by_id(&user.id).await.unwrap().is_some());
+        assert_eq!(store.list_live_auth_keys(&user.id).await.unwrap().len(), 1);
+        assert_eq!(audit_events(&store, AuditAction::DeleteUser).await[0].outcome, AuditOutcome::Failure);
     }
 }
@@ -0,0 +1,393 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use tracing::{info, error, debug};
+use crate::config::{get_config, DeviceRemoval};
+use crate::models::{
+    CreateAuthKeyResponse, CreatePreAuthKeyRequest, PreAuthKeyResponse, PreAuthKeyListResponse,
+    ExpirePreAuthKeyRequest, NodeListResponse, Device, DeviceInfo, KeyProfile,
+};
+use crate::network::NetworkProvider;
+
+/// Where and as whom the Headscale API is called
+struct Api {
+    url: String,
+    /// Unlike Tailscale there is no OAuth exchange: the secret file holds a
+    /// Headscale API key which is used as the bearer token directly
+    key: String,
+    /// The Headscale user that owns the pre-auth keys we issue
+    user: Option<String>,
+}
+
+impl Api {
+    fn from_config() -> Result<Self> {
+        let config = get_config();
+        let key = config.read_tailscale_secret()
+            .map_err(|e| anyhow!("Failed to read Headscale API key: {}", e))?;
+
+        Ok(Api {
+            url: config.tailscale.api_url.clone(),
+            key,
+            user: config.tailscale.headscale_user.clone(),
+        })
+    }
+
+    fn user(&self) -> Result<String> {
+        self.user.clone()
+            .ok_or_else(|| anyhow!("tailscale.headscale_user must be set when using the Headscale provider"))
+    }
+}
+
+/// Send a request to the Headscale API with the API key
+async fn send(api: &Api, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+    let response = request
+        .header("Authorization", format!("Bearer {}", api.key))
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to send request to Headscale API: {}", e))?;
+
+    let status = response.status();
+
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        error!("Headscale API error ({}): {}", status, error_text);
+        return Err(anyhow!("Headscale API returned error {}: {}", status, error_text));
+    }
+
+    Ok(response)
+}
+
+/// Generate a Headscale pre-auth key for a user
+///
+/// Headscale keys belong to a Headscale user rather than carrying a
+/// description, so every key is issued to the configured `headscale_user`
+/// and attributed to the signed-in user, and the device it is for, only
+/// through our auth_keys table.
+async fn generate_auth_key(api: &Api, user_email: &str, profile: &KeyProfile) -> Result<CreateAuthKeyResponse> {
+    let request_body = CreatePreAuthKeyRequest {
+        user: api.user()?,
+        reusable: profile.reusable,
+        ephemeral: profile.ephemeral,
+        expiration: Utc::now() + Duration::seconds(profile.expiry_seconds as i64),
+        acl_tags: profile.tags.clone(),
+    };
+
+    debug!("Creating pre-auth key with profile {} and tags: {:?}", profile.name, profile.tags);
+
+    let client = reqwest::Client::new();
+    let response = send(api, client.post(format!("{}/api/v1/preauthkey", api.url)).json(&request_body)).await?;
+
+    let PreAuthKeyResponse { pre_auth_key } = response
+        .json()
+        .await
+        .map_err(|e| anyhow!("Failed to parse Headscale API response: {}", e))?;
+
+    info!("Successfully generated Headscale pre-auth key {} for user: {}", pre_auth_key.id, user_email);
+
+    Ok(CreateAuthKeyResponse {
+        id: pre_auth_key.id,
+        key: pre_auth_key.key,
+        created: pre_auth_key.created_at,
+        expires: pre_auth_key.expiration,
+    })
+}
+
+/// Expire a Headscale pre-auth key by id
+///
+/// Headscale expires keys by their secret, which we never store, so the key
+/// is looked up among the configured user's keys first.
+async fn revoke_auth_key(api: &Api, key_id: &str) -> Result<()> {
+    let user = api.user()?;
+
+    let client = reqwest::Client::new();
+    let response = send(api, client.get(format!("{}/api/v1/preauthkey", api.url)).query(&[("user", user.as_str())])).await?;
+
+    let key_list: PreAuthKeyListResponse = response
+        .json()
+        .await
+        .map_err(|e| anyhow!("Failed to parse Headscale pre-auth key list: {}", e))?;
+
+    let Some(pre_auth_key) = key_list.pre_auth_keys.into_iter().find(|key| key.id == key_id) else {
+        debug!("Pre-auth key {} was already gone from Headscale", key_id);
+        return Ok(());
+    };
+
+    send(
+        api,
+        client
+            .post(format!("{}/api/v1/preauthkey/expire", api.url))
+            .json(&ExpirePreAuthKeyRequest { user, key: pre_auth_key.key })
+    ).await?;
+
+    info!("Expired Headscale pre-auth key {}", key_id);
+
+    Ok(())
+}
+
+/// List every node on the Headscale network
+async fn list_devices(api: &Api) -> Result<Vec<Device>> {
+    let client = reqwest::Client::new();
+    let response = send(api, client.get(format!("{}/api/v1/node", api.url))).await?;
+
+    let node_list: NodeListResponse = response
+        .json()
+        .await
+        .map_err(|e| anyhow!("Failed to parse Headscale node list: {}", e))?;
+
+    let now = Utc::now();
+
+    Ok(node_list.nodes
+        .into_iter()
+        .map(|node| {
+            let mut tags = node.forced_tags;
+            tags.extend(node.valid_tags);
+
+            // Headscale reports a zero timestamp for nodes that never expire
+            let expired = node.expiry.is_some_and(|expiry| expiry.timestamp() > 0 && expiry < now);
+
+            Device {
+                id: node.id,
+                name: if node.given_name.is_empty() { node.name.clone() } else { node.given_name },
+                hostname: node.name,
+                tags,
+                created: node.created_at,
+                authorized: !expired,
+                is_external: false,
+                auth_key_id: node.pre_auth_key.map(|key| key.id),
+            }
+        })
+        .collect())
+}
+
+/// Delete or expire a Headscale node
+///
+/// Headscale has no de-authorization, so expiring the node stands in for it.
+async fn remove_device(api: &Api, device_id: &str, removal: DeviceRemoval) -> Result<()> {
+    let client = reqwest::Client::new();
+    let request = match removal {
+        DeviceRemoval::Delete => client
+            .delete(format!("{}/api/v1/node/{}", api.url, device_id)),
+        DeviceRemoval::Deauthorize => client
+            .post(format!("{}/api/v1/node/{}/expire", api.url, device_id)),
+    };
+
+    send(api, request).await?;
+
+    info!("Removed node {} ({:?})", device_id, removal);
+
+    Ok(())
+}
+
+/// Network provider backed by a self-hosted Headscale control plane
+pub struct HeadscaleProvider;
+
+#[async_trait]
+impl NetworkProvider for HeadscaleProvider {
+    async fn generate_auth_key(&self, user_email: &str, profile: &KeyProfile, _device: &DeviceInfo) -> Result<CreateAuthKeyResponse> {
+        generate_auth_key(&Api::from_config()?, user_email, profile).await
+    }
+
+    async fn revoke_auth_key(&self, key_id: &str) -> Result<()> {
+        revoke_auth_key(&Api::from_config()?, key_id).await
+    }
+
+    async fn list_devices(&self) -> Result<Vec<Device>> {
+        list_devices(&Api::from_config()?).await
+    }
+
+    async fn remove_device(&self, device_id: &str, removal: DeviceRemoval) -> Result<()> {
+        remove_device(&Api::from_config()?, device_id, removal).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::extract::{Request, State};
+    use axum::http::StatusCode;
+    use axum::routing::any;
+    use serde_json::{Value, json};
+    use std::sync::{Arc, Mutex};
+
+    /// A request the fake Headscale server received
+    #[derive(Debug)]
+    struct Received {
+        method: String,
+        path_and_query: String,
+        authorization: Option<String>,
+        body: Value,
+    }
+
+    type Log = Arc<Mutex<Vec<Received>>>;
+
+    /// Method, path, status and body of a canned response
+    type Canned = (&'static str, &'static str, StatusCode, Value);
+
+    /// Serve canned responses by method and path, recording every request
+    async fn fake_headscale(responses: Vec<Canned>) -> (Api, Log) {
+        let log = Log::default();
+        let responses = Arc::new(responses);
+
+        let app = Router::new().fallback(any(|State((log, responses)): State<(Log, Arc<Vec<Canned>>)>, request: Request| async move {
+            let method = request.method().to_string();
+            let path = request.uri().path().to_string();
+            let path_and_query = request.uri().path_and_query().map(|value| value.to_string()).unwrap_or_default();
+            let authorization = request.headers().get("authorization").map(|value| value.to_str().unwrap().to_string());
+            let bytes = axum::body::to_bytes(request.into_body(), usize::MAX).await.unwrap();
+            let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+            log.lock().unwrap().push(Received { method: method.clone(), path_and_query, authorization, body });
+
+            responses.iter()
+                .find(|(m, p, _, _)| *m == method && *p == path)
+                .map(|(_, _, status, body)| (*status, axum::Json(body.clone())))
+                .unwrap_or((StatusCode::NOT_FOUND, axum::Json(json!({ "message": "not found" }))))
+        })).with_state((log.clone(), responses));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (Api { url, key: "api-key".to_string(), user: Some("access".to_string()) }, log)
+    }
+
+    fn pre_auth_key(id: &str, key: &str) -> Value {
+        json!({
+            "id": id,
+            "key": key,
+            "user": "access",
+            "reusable": true,
+            "ephemeral": false,
+            "used": false,
+            "expiration": "2026-01-01T14:00:00Z",
+            "createdAt": "2026-01-01T12:00:00Z",
+            "aclTags": ["tag:user"],
+        })
+    }
+
+    fn profile() -> KeyProfile {
+        KeyProfile {
+            name: "laptop".to_string(),
+            tags: vec!["tag:user".to_string()],
+            expiry_seconds: 7200,
+            reusable: false,
+            ephemeral: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn creating_a_key_sends_the_profile_and_reads_the_key() {
+        let (api, log) = fake_headscale(vec![
+            ("POST", "/api/v1/preauthkey", StatusCode::OK, json!({ "preAuthKey": pre_auth_key("7", "hskey-secret") })),
+        ]).await;
+
+        let created = generate_auth_key(&api, "alice@example.com", &profile()).await.unwrap();
+
+        assert_eq!(created.id, "7");
+        assert_eq!(created.key, "hskey-secret");
+        assert_eq!(created.expires.to_rfc3339(), "2026-01-01T14:00:00+00:00");
+
+        let log = log.lock().unwrap();
+        assert_eq!(log[0].authorization.as_deref(), Some("Bearer api-key"));
+        assert_eq!(log[0].body["user"], "access");
+        assert_eq!(log[0].body["reusable"], false);
+        assert_eq!(log[0].body["ephemeral"], true);
+        assert_eq!(log[0].body["aclTags"], json!(["tag:user"]));
+        let expiration = log[0].body["expiration"].as_str().unwrap().parse::<chrono::DateTime<Utc>>().unwrap();
+        assert!((expiration - Utc::now() - Duration::seconds(7200)).num_seconds().abs() < 60);
+    }
+
+    #[tokio::test]
+    async fn revoking_a_key_expires_it_by_its_secret() {
+        let (api, log) = fake_headscale(vec![
+            ("GET", "/api/v1/preauthkey", StatusCode::OK, json!({ "preAuthKeys": [pre_auth_key("6", "other"), pre_auth_key("7", "hskey-secret")] })),
+            ("POST", "/api/v1/preauthkey/expire", StatusCode::OK, json!({})),
+        ]).await;
+
+        revoke_auth_key(&api, "7").await.unwrap();
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].path_and_query, "/api/v1/preauthkey?user=access");
+        assert_eq!(log[1].method, "POST");
+        assert_eq!(log[1].body, json!({ "user": "access", "key": "hskey-secret" }));
+    }
+
+    #[tokio::test]
+    async fn revoking_a_key_headscale_no_longer_has_succeeds() {
+        let (api, log) = fake_headscale(vec![
+            ("GET", "/api/v1/preauthkey", StatusCode::OK, json!({})),
+        ]).await;
+
+        revoke_auth_key(&api, "7").await.unwrap();
+
+        assert_eq!(log.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_expiry_is_reported() {
+        let (api, _) = fake_headscale(vec![
+            ("GET", "/api/v1/preauthkey", StatusCode::OK, json!({ "preAuthKeys": [pre_auth_key("7", "hskey-secret")] })),
+            ("POST", "/api/v1/preauthkey/expire", StatusCode::INTERNAL_SERVER_ERROR, json!({ "message": "database is locked" })),
+        ]).await;
+
+        let error = revoke_auth_key(&api, "7").await.unwrap_err().to_string();
+
+        assert!(error.contains("500"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn keys_need_a_headscale_user() {
+        let (mut api, log) = fake_headscale(vec![]).await;
+        api.user = None;
+
+        assert!(generate_auth_key(&api, "alice@example.com", &profile()).await.is_err());
+        assert!(revoke_auth_key(&api, "7").await.is_err());
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn nodes_are_listed_with_their_key_and_expiry() {
+        let (api, _) = fake_headscale(vec![
+            ("GET", "/api/v1/node", StatusCode::OK, json!({ "nodes": [
+                {
+                    "id": "1",
+                    "name": "laptop",
+                    "givenName": "alices-laptop",
+                    "createdAt": "2026-01-01T12:30:00Z",
+                    "expiry": "0001-01-01T00:00:00Z",
+                    "forcedTags": ["tag:user"],
+                    "validTags": ["tag:laptop"],
+                    "preAuthKey": pre_auth_key("7", "hskey-secret"),
+                },
+                { "id": "2", "name": "old", "expiry": "2020-01-01T00:00:00Z" },
+            ] })),
+        ]).await;
+
+        let devices = list_devices(&api).await.unwrap();
+
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].name, "alices-laptop");
+        assert_eq!(devices[0].hostname, "laptop");
+        assert_eq!(devices[0].tags, vec!["tag:user".to_string(), "tag:laptop".to_string()]);
+        assert_eq!(devices[0].auth_key_id.as_deref(), Some("7"));
+        assert!(devices[0].authorized);
+        assert_eq!(devices[1].name, "old");
+        assert_eq!(devices[1].auth_key_id, None);
+        assert!(!devices[1].authorized);
+    }
+
+    #[tokio::test]
+    async fn removal_deletes_or_expires_the_node() {
+        let (api, log) = fake_headscale(vec![
+            ("DELETE", "/api/v1/node/1", StatusCode::OK, json!({})),
+            ("POST", "/api/v1/node/2/expire", StatusCode::OK, json!({})),
+        ]).await;
+
+        remove_device(&api, "1", DeviceRemoval::Delete).await.unwrap();
+        remove_device(&api, "2", DeviceRemoval::Deauthorize).await.unwrap();
+        assert!(remove_device(&api, "3", DeviceRemoval::Delete).await.is_err());
+
+        assert_eq!(log.lock().unwrap().len(), 3);
+    }
+}
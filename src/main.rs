@@ -1,20 +1,45 @@
 use axum::{
-    routing::{get, post},
+    middleware,
+    routing::{get, post, delete, MethodRouter},
     Router,
 };
-use tower_http::cors::CorsLayer;
 use tracing::{info, level_filters::LevelFilter};
 use std::net::SocketAddr;
 
 mod google;
+mod oidc;
+mod approval;
+mod audit;
+mod session;
+mod api_token;
 mod config;
 mod db;
+mod store;
+#[cfg(test)]
+mod memory_store;
+mod state;
 mod models;
 mod handlers;
+mod network;
 mod tailscale;
+mod headscale;
+mod devices;
+mod quota;
+mod proxy;
+mod rate_limit;
+mod cors;
+mod commands;
 
 use config::get_config;
-use handlers::{health_check, validate_token, generate_tailscale_token};
+use handlers::{
+    health_check, validate_token, generate_tailscale_token, refresh_session, logout, list_sessions, end_session,
+    list_key_profiles, list_api_tokens, create_api_token, revoke_api_token,
+    list_users, approve_user, deny_user, pend_user, suspend_user, expire_user, delete_user, revoke_user_keys,
+    list_user_devices, remove_user_devices,
+    list_user_permissions, grant_user_permission, revoke_user_permission, list_user_identities,
+    list_user_sessions, end_user_sessions, list_user_api_tokens, revoke_user_api_token,
+    get_user_quota, set_user_quota, clear_user_quota, list_audit_events,
+};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -34,23 +59,98 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
+    let command = config::get_command();
+
     // Initialize tracing with configured log level
-    tracing_subscriber::fmt()
-        .with_max_level(log_level)
-        .init();
+    // Subcommands print their results on stdout, so their logs go to stderr
+    let subscriber = tracing_subscriber::fmt().with_max_level(log_level);
+    if command.is_some() {
+        subscriber.with_writer(std::io::stderr).init();
+    } else {
+        subscriber.init();
+    }
+
+    // Administrative subcommands act on the database and exit
+    // They migrate the schema themselves, except the db commands that manage it
+    if let Some(command) = command {
+        let db = db::connect().await?;
+        return commands::run(&db, command).await;
+    }
 
     // Initialize database
     let db = db::init_db().await?;
-    
+
     info!("Database initialized successfully");
 
-    // Build our application with routes
-    let app = Router::new()
-        .route("/", get(health_check))
-        .route("/auth/validate", get(validate_token))
-        .route("/auth/generate-token", post(generate_tailscale_token))
-        .layer(CorsLayer::permissive()) // Allow CORS for frontend
-        .with_state(db);
+    let providers = oidc::get_providers();
+    info!(
+        "Accepting ID tokens from: {}",
+        providers.iter().map(|provider| provider.name()).collect::<Vec<_>>().join(", ")
+    );
+    info!("Loaded {} approval rule(s)", approval::load_rules());
+    info!("Offering {} key profile(s)", network::load_key_profiles());
+    session::load_secret();
+    let trusted_proxies = proxy::load_trusted_proxies();
+    if trusted_proxies > 0 {
+        info!("Trusting X-Forwarded-For from {} proxy range(s)", trusted_proxies);
+    }
+    let cors = cors::layer(&config.server.cors)
+        .map_err(|e| anyhow::anyhow!("Invalid [server.cors]: {}", e))?;
+    if config.server.cors.allowed_origins.is_empty() {
+        info!("Not allowing cross-origin requests; set [server.cors] allowed_origins for a frontend on another origin");
+    } else {
+        info!("Allowing cross-origin requests from: {}", config.server.cors.allowed_origins.join(", "));
+    }
+
+    // Every route the API serves, also checked against the rate limit configuration
+    let routes: Vec<(&str, MethodRouter<state::AppState>)> = vec![
+        ("/", get(health_check)),
+        ("/auth/validate", get(validate_token)),
+        ("/auth/generate-token", post(generate_tailscale_token)),
+        ("/auth/refresh", post(refresh_session)),
+        ("/auth/logout", post(logout)),
+        ("/auth/sessions", get(list_sessions)),
+        ("/auth/sessions/:id", delete(end_session)),
+        ("/auth/key-profiles", get(list_key_profiles)),
+        ("/auth/api-tokens", get(list_api_tokens).post(create_api_token)),
+        ("/auth/api-tokens/:id", delete(revoke_api_token)),
+        ("/admin/users", get(list_users)),
+        ("/admin/users/:id", delete(delete_user)),
+        ("/admin/users/:id/approve", post(approve_user)),
+        ("/admin/users/:id/deny", post(deny_user)),
+        ("/admin/users/:id/pend", post(pend_user)),
+        ("/admin/users/:id/suspend", post(suspend_user)),
+        ("/admin/users/:id/expire", post(expire_user)),
+        ("/admin/users/:id/revoke-keys", post(revoke_user_keys)),
+        ("/admin/users/:id/devices", get(list_user_devices).delete(remove_user_devices)),
+        ("/admin/users/:id/permissions", get(list_user_permissions).post(grant_user_permission)),
+        ("/admin/users/:id/permissions/:permission", delete(revoke_user_permission)),
+        ("/admin/users/:id/identities", get(list_user_identities)),
+        ("/admin/users/:id/sessions", get(list_user_sessions).delete(end_user_sessions)),
+        ("/admin/users/:id/api-tokens", get(list_user_api_tokens)),
+        ("/admin/users/:id/api-tokens/:token_id", delete(revoke_user_api_token)),
+        ("/admin/users/:id/quota", get(get_user_quota).put(set_user_quota).delete(clear_user_quota)),
+        ("/admin/audit", get(list_audit_events)),
+    ];
+    let paths = routes.iter().map(|(path, _)| *path).collect::<Vec<_>>();
+    let app = routes.into_iter().fold(Router::new(), |app, (path, handler)| app.route(path, handler));
+
+    if config.server.rate_limit.enabled {
+        let routes = rate_limit::load_rate_limits(&paths)
+            .map_err(|e| anyhow::anyhow!("Invalid [server.rate_limit]: {}", e))?;
+        info!("Rate limiting requests per client, {} route(s) with their own budget", routes);
+    }
+
+    // Applied per route, so each route's path keys its own budget and unknown paths are not counted
+    let app = if config.server.rate_limit.enabled {
+        app.route_layer(middleware::from_fn(rate_limit::limit))
+    } else {
+        app
+    };
+
+    let app = app
+        .layer(cors)
+        .with_state(state::AppState::new(db));
 
     // Run the server
     let addr: SocketAddr = config.server.bind_address.parse()
@@ -58,7 +158,7 @@ async fn main() -> anyhow::Result<()> {
     info!("Server starting on {}", addr);
     
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
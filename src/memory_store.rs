@@ -0,0 +1,435 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Mutex;
+use crate::db::AUDIT_QUERY_MAX_LIMIT;
+use crate::models::{
+    User, UserStatus, IllegalTransition, UserPermission, Identity, VerifiedIdentity, AuthKey, Session, ApiToken,
+    UserQuota, AuditEvent, AuditQuery,
+};
+use crate::store::{UserStore, AuthKeyStore, SessionStore, ApiTokenStore, QuotaStore, QuotaLock, AuditStore};
+
+#[derive(Default)]
+struct MemoryData {
+    users: Vec<User>,
+    identities: Vec<Identity>,
+    permissions: Vec<(String, UserPermission)>,
+    auth_keys: Vec<AuthKey>,
+    sessions: Vec<Session>,
+    api_tokens: Vec<(String, ApiToken)>,
+    quotas: Vec<UserQuota>,
+    audit_events: Vec<AuditEvent>,
+}
+
+/// Store that keeps everything in memory
+///
+/// Behaves like `SqlStore`, including the email uniqueness and status
+/// transition checks and the audit hash chain, so handler logic can be
+/// exercised without a database.
+#[derive(Default)]
+pub struct MemoryStore {
+    data: Mutex<MemoryData>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Change recorded audit events in place, as someone with access to the database could
+    pub fn tamper_with_audit_events(&self, tamper: impl FnOnce(&mut Vec<AuditEvent>)) {
+        tamper(&mut self.data.lock().unwrap().audit_events);
+    }
+}
+
+impl MemoryData {
+    fn upsert_identity(&mut self, user_id: &str, identity: &VerifiedIdentity) {
+        let now = Utc::now();
+
+        match self.identities.iter_mut().find(|linked| linked.issuer == identity.issuer && linked.subject == identity.subject) {
+            Some(linked) => {
+                linked.email = identity.email.clone();
+                linked.last_login = now;
+            }
+            None => self.identities.push(Identity {
+                issuer: identity.issuer.clone(),
+                subject: identity.subject.clone(),
+                user_id: user_id.to_string(),
+                email: identity.email.clone(),
+                created_at: now,
+                last_login: now,
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryStore {
+    async fn find_user_by_id(&self, id: &str) -> Result<Option<User>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.users.iter().find(|user| user.id == id).cloned())
+    }
+
+    async fn find_user_by_email(&self, email: &str) -> Result<Option<User>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.users.iter().find(|user| user.email == email).cloned())
+    }
+
+    async fn find_user_by_identity(&self, issuer: &str, subject: &str) -> Result<Option<User>> {
+        let data = self.data.lock().unwrap();
+        let Some(linked) = data.identities.iter().find(|linked| linked.issuer == issuer && linked.subject == subject) else {
+            return Ok(None);
+        };
+        Ok(data.users.iter().find(|user| user.id == linked.user_id).cloned())
+    }
+
+    async fn create_user(&self, user: &User, identity: &VerifiedIdentity) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+
+        if data.users.iter().any(|existing| existing.id == user.id || existing.email == user.email) {
+            return Err(anyhow!("User {} already exists", user.email));
+        }
+
+        data.users.push(user.clone());
+        data.upsert_identity(&user.id, identity);
+
+        Ok(())
+    }
+
+    async fn record_login(&self, user: &User, identity: &VerifiedIdentity) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+
+        let stored = data.users.iter_mut()
+            .find(|existing| existing.id == user.id)
+            .ok_or_else(|| anyhow!("User {} does not exist", user.email))?;
+        stored.email = user.email.clone();
+        stored.name = user.name.clone();
+        stored.last_login = user.last_login;
+
+        data.upsert_identity(&user.id, identity);
+
+        Ok(())
+    }
+
+    async fn link_identity(&self, user_id: &str, identity: &VerifiedIdentity) -> Result<()> {
+        self.data.lock().unwrap().upsert_identity(user_id, identity);
+        Ok(())
+    }
+
+    async fn list_users(&self, status: Option<UserStatus>) -> Result<Vec<User>> {
+        let data = self.data.lock().unwrap();
+        let mut users: Vec<User> = data.users.iter()
+            .filter(|user| status.is_none_or(|status| user.status == status))
+            .cloned()
+            .collect();
+        users.sort_by_key(|user| user.created_at);
+        Ok(users)
+    }
+
+    async fn set_user_status(&self, id: &str, status: UserStatus, reason: Option<&str>) -> Result<Option<User>> {
+        let mut data = self.data.lock().unwrap();
+
+        let Some(user) = data.users.iter_mut().find(|user| user.id == id) else {
+            return Ok(None);
+        };
+
+        if !user.status.can_transition_to(status) {
+            return Err(IllegalTransition { from: user.status, to: status }.into());
+        }
+
+        user.status = status;
+        user.status_reason = reason.map(str::to_string);
+        user.status_updated_at = Some(Utc::now());
+
+        Ok(Some(user.clone()))
+    }
+
+    async fn delete_user(&self, id: &str) -> Result<bool> {
+        let mut data = self.data.lock().unwrap();
+
+        data.permissions.retain(|(user_id, _)| user_id != id);
+        data.identities.retain(|linked| linked.user_id != id);
+        data.sessions.retain(|session| session.user_id != id);
+        data.api_tokens.retain(|(_, token)| token.user_id != id);
+        data.quotas.retain(|quota| quota.user_id != id);
+
+        let before = data.users.len();
+        data.users.retain(|user| user.id != id);
+
+        Ok(data.users.len() < before)
+    }
+
+    async fn list_identities(&self, user_id: &str) -> Result<Vec<Identity>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.identities.iter().filter(|linked| linked.user_id == user_id).cloned().collect())
+    }
+
+    async fn list_permissions(&self, user_id: &str) -> Result<Vec<UserPermission>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.permissions.iter()
+            .filter(|(holder, _)| holder == user_id)
+            .map(|(_, grant)| grant.clone())
+            .collect())
+    }
+
+    async fn grant_permission(&self, user_id: &str, permission: &str) -> Result<bool> {
+        let mut data = self.data.lock().unwrap();
+
+        if data.permissions.iter().any(|(holder, grant)| holder == user_id && grant.permission == permission) {
+            return Ok(false);
+        }
+
+        data.permissions.push((user_id.to_string(), UserPermission {
+            permission: permission.to_string(),
+            granted_at: Utc::now(),
+        }));
+
+        Ok(true)
+    }
+
+    async fn revoke_permission(&self, user_id: &str, permission: &str) -> Result<bool> {
+        let mut data = self.data.lock().unwrap();
+
+        let before = data.permissions.len();
+        data.permissions.retain(|(holder, grant)| !(holder == user_id && grant.permission == permission));
+
+        Ok(data.permissions.len() < before)
+    }
+}
+
+#[async_trait]
+impl AuthKeyStore for MemoryStore {
+    async fn insert_auth_key(&self, key: &AuthKey) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+
+        if data.auth_keys.iter().any(|existing| existing.id == key.id) {
+            return Err(anyhow!("Auth key {} already exists", key.id));
+        }
+
+        data.auth_keys.push(key.clone());
+        Ok(())
+    }
+
+    async fn list_auth_keys(&self) -> Result<Vec<AuthKey>> {
+        let data = self.data.lock().unwrap();
+        Ok(oldest_first(data.auth_keys.clone(), |key| key.created_at))
+    }
+
+    async fn list_live_auth_keys(&self, user_id: &str) -> Result<Vec<AuthKey>> {
+        let data = self.data.lock().unwrap();
+        let now = Utc::now();
+        let keys = data.auth_keys.iter()
+            .filter(|key| key.user_id == user_id && key.revoked_at.is_none() && key.expires_at > now)
+            .cloned()
+            .collect();
+        Ok(oldest_first(keys, |key| key.created_at))
+    }
+
+    async fn list_auth_keys_since(&self, user_id: &str, since: DateTime<Utc>) -> Result<Vec<AuthKey>> {
+        let data = self.data.lock().unwrap();
+        let keys = data.auth_keys.iter()
+            .filter(|key| key.user_id == user_id && key.created_at >= since)
+            .cloned()
+            .collect();
+        Ok(oldest_first(keys, |key| key.created_at))
+    }
+
+    async fn mark_auth_key_revoked(&self, id: &str) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(key) = data.auth_keys.iter_mut().find(|key| key.id == id) {
+            key.revoked_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn insert_session(&self, session: &Session) -> Result<()> {
+        self.data.lock().unwrap().sessions.push(session.clone());
+        Ok(())
+    }
+
+    async fn find_session(&self, id: &str) -> Result<Option<Session>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.sessions.iter().find(|session| session.id == id).cloned())
+    }
+
+    async fn touch_session(&self, id: &str) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(session) = data.sessions.iter_mut().find(|session| session.id == id) {
+            session.last_seen = Utc::now();
+        }
+        Ok(())
+    }
+
+    async fn advance_refresh_generation(&self, id: &str, generation: i64) -> Result<bool> {
+        let mut data = self.data.lock().unwrap();
+        let session = data.sessions.iter_mut()
+            .find(|session| session.id == id && session.ended_at.is_none() && session.refresh_generation == generation);
+
+        match session {
+            Some(session) => {
+                session.refresh_generation = generation + 1;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn list_live_sessions(&self, user_id: &str) -> Result<Vec<Session>> {
+        let data = self.data.lock().unwrap();
+        let now = Utc::now();
+        let sessions = data.sessions.iter()
+            .filter(|session| session.user_id == user_id && session.ended_at.is_none() && session.expires_at > now)
+            .cloned()
+            .collect();
+        Ok(oldest_first(sessions, |session| session.created_at))
+    }
+
+    async fn end_session(&self, id: &str) -> Result<bool> {
+        let mut data = self.data.lock().unwrap();
+        match data.sessions.iter_mut().find(|session| session.id == id && session.ended_at.is_none()) {
+            Some(session) => {
+                session.ended_at = Some(Utc::now());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn end_user_sessions(&self, user_id: &str) -> Result<Vec<String>> {
+        let mut ended = Vec::new();
+
+        for session in self.list_live_sessions(user_id).await? {
+            if self.end_session(&session.id).await? {
+                ended.push(session.id);
+            }
+        }
+
+        Ok(ended)
+    }
+}
+
+#[async_trait]
+impl ApiTokenStore for MemoryStore {
+    async fn insert_api_token(&self, token: &ApiToken, token_hash: &str) -> Result<()> {
+        self.data.lock().unwrap().api_tokens.push((token_hash.to_string(), token.clone()));
+        Ok(())
+    }
+
+    async fn find_api_token(&self, id: &str) -> Result<Option<ApiToken>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.api_tokens.iter().find(|(_, token)| token.id == id).map(|(_, token)| token.clone()))
+    }
+
+    async fn find_api_token_by_hash(&self, token_hash: &str) -> Result<Option<ApiToken>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.api_tokens.iter().find(|(hash, _)| hash == token_hash).map(|(_, token)| token.clone()))
+    }
+
+    async fn list_api_tokens(&self, user_id: &str) -> Result<Vec<ApiToken>> {
+        let data = self.data.lock().unwrap();
+        let tokens = data.api_tokens.iter()
+            .map(|(_, token)| token)
+            .filter(|token| token.user_id == user_id && token.revoked_at.is_none())
+            .cloned()
+            .collect();
+        Ok(oldest_first(tokens, |token| token.created_at))
+    }
+
+    async fn touch_api_token(&self, id: &str) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some((_, token)) = data.api_tokens.iter_mut().find(|(_, token)| token.id == id) {
+            token.last_used_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn revoke_api_token(&self, id: &str) -> Result<bool> {
+        let mut data = self.data.lock().unwrap();
+        match data.api_tokens.iter_mut().find(|(_, token)| token.id == id && token.revoked_at.is_none()) {
+            Some((_, token)) => {
+                token.revoked_at = Some(Utc::now());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl QuotaStore for MemoryStore {
+    async fn find_user_quota(&self, user_id: &str) -> Result<Option<UserQuota>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.quotas.iter().find(|quota| quota.user_id == user_id).cloned())
+    }
+
+    async fn set_user_quota(&self, quota: &UserQuota) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+        data.quotas.retain(|existing| existing.user_id != quota.user_id);
+        data.quotas.push(quota.clone());
+        Ok(())
+    }
+
+    async fn clear_user_quota(&self, user_id: &str) -> Result<bool> {
+        let mut data = self.data.lock().unwrap();
+        let before = data.quotas.len();
+        data.quotas.retain(|quota| quota.user_id != user_id);
+        Ok(data.quotas.len() < before)
+    }
+
+    async fn lock_user_quota(&self, _user_id: &str) -> Result<QuotaLock> {
+        // Nothing else shares the store, so the caller's own lock is enough
+        Ok(Box::new(()))
+    }
+}
+
+#[async_trait]
+impl AuditStore for MemoryStore {
+    async fn insert_audit_event(&self, event: &AuditEvent) -> Result<()> {
+        let mut data = self.data.lock().unwrap();
+
+        let prev_hash = data.audit_events.last().and_then(|last| last.hash.clone()).unwrap_or_default();
+        let hash = event.chain_hash(&prev_hash);
+        let id = data.audit_events.last().map_or(1, |last| last.id + 1);
+
+        data.audit_events.push(AuditEvent {
+            id,
+            prev_hash: Some(prev_hash),
+            hash: Some(hash),
+            ..event.clone()
+        });
+
+        Ok(())
+    }
+
+    async fn list_audit_chain(&self, after_id: i64, limit: u32) -> Result<Vec<AuditEvent>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.audit_events.iter()
+            .filter(|event| event.id > after_id)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_audit_events(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>> {
+        let data = self.data.lock().unwrap();
+        let limit = query.limit.unwrap_or(100).min(AUDIT_QUERY_MAX_LIMIT);
+
+        Ok(data.audit_events.iter()
+            .rev()
+            .filter(|event| query.user_id.is_none() || event.subject_user_id == query.user_id)
+            .filter(|event| query.action.is_none_or(|action| event.action == action))
+            .filter(|event| query.since.is_none_or(|since| event.occurred_at >= since))
+            .filter(|event| query.until.is_none_or(|until| event.occurred_at < until))
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+}
+
+fn oldest_first<T>(mut records: Vec<T>, created_at: impl Fn(&T) -> DateTime<Utc>) -> Vec<T> {
+    records.sort_by_key(|record| created_at(record));
+    records
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use super::user::{User, UserStatus, UserPermission, Identity};
+use super::tailscale::{KeyRevocationFailure, Device, DeviceRemovalFailure};
+
+#[derive(Deserialize)]
+pub struct ListUsersQuery {
+    pub status: Option<UserStatus>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct UserStatusRequest {
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AdminUsersResponse {
+    pub success: bool,
+    pub users: Vec<User>,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct AdminUserResponse {
+    pub success: bool,
+    pub user: Option<User>,
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub revocation_failures: Vec<KeyRevocationFailure>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub device_failures: Vec<DeviceRemovalFailure>,
+}
+
+#[derive(Serialize)]
+pub struct IdentitiesResponse {
+    pub success: bool,
+    pub identities: Vec<Identity>,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct RevokeKeysResponse {
+    pub success: bool,
+    pub revoked: Vec<String>,
+    pub failures: Vec<KeyRevocationFailure>,
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+pub struct PermissionRequest {
+    pub permission: String,
+}
+
+#[derive(Serialize)]
+pub struct PermissionsResponse {
+    pub success: bool,
+    pub permissions: Vec<UserPermission>,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct DevicesResponse {
+    pub success: bool,
+    pub devices: Vec<Device>,
+    pub ambiguous: Vec<Device>,  // Devices that may also belong to other users, which are never removed
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct RemoveDevicesResponse {
+    pub success: bool,
+    pub removed: Vec<Device>,
+    pub failures: Vec<DeviceRemovalFailure>,
+    pub message: String,
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// Scope letting an API token generate Tailscale auth keys for its owner
+pub const SCOPE_GENERATE_KEY: &str = "generate_key";
+
+/// Every scope an API token can be given
+pub const API_TOKEN_SCOPES: [&str; 1] = [SCOPE_GENERATE_KEY];
+
+// A personal API token as recorded in the api_tokens table (only its hash is stored)
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|granted| granted == scope)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiTokenRequest {
+    pub name: String,
+    pub scopes: Option<Vec<String>>,  // Defaults to every scope
+    pub expires_in_days: Option<u32>,  // Defaults to [api_tokens] default_ttl_days
+}
+
+#[derive(Serialize)]
+pub struct ApiTokenResponse {
+    pub success: bool,
+    pub api_token: Option<ApiToken>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,  // The token itself, returned only when it is created
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct ApiTokensResponse {
+    pub success: bool,
+    pub api_tokens: Vec<ApiToken>,
+    pub message: String,
+}
@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+/// Something that happened to or was done by a user, as recorded in the audit log
+///
+/// Stored in the `action` column as its snake_case name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// An ID token was presented to `/auth/validate`
+    SignIn,
+    /// A refresh token was exchanged for a new session
+    RefreshSession,
+    /// A user signed out
+    SignOut,
+    /// Sessions were ended before they expired, by their user or an administrator
+    EndSessions,
+    /// An auth key was requested from `/auth/generate-token`
+    GenerateKey,
+    /// A user created a personal API token
+    CreateApiToken,
+    /// A personal API token was revoked by its owner or an administrator
+    RevokeApiToken,
+    /// A caller without admin rights tried to use the admin API
+    AdminAccess,
+    /// An administrator changed a user's status
+    SetStatus,
+    /// An administrator deleted a user
+    DeleteUser,
+    /// A permission was granted to a user
+    GrantPermission,
+    /// A permission was revoked from a user
+    RevokePermission,
+    /// One of a user's auth keys was revoked
+    RevokeKey,
+    /// One of a user's devices was removed from the tailnet
+    RemoveDevice,
+    /// An administrator set or cleared a user's quota overrides
+    SetQuota,
+    /// An administrator linked an identity provider account to a user
+    LinkIdentity,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 16] = [
+        AuditAction::SignIn,
+        AuditAction::RefreshSession,
+        AuditAction::SignOut,
+        AuditAction::EndSessions,
+        AuditAction::GenerateKey,
+        AuditAction::CreateApiToken,
+        AuditAction::RevokeApiToken,
+        AuditAction::AdminAccess,
+        AuditAction::SetStatus,
+        AuditAction::DeleteUser,
+        AuditAction::GrantPermission,
+        AuditAction::RevokePermission,
+        AuditAction::RevokeKey,
+        AuditAction::RemoveDevice,
+        AuditAction::SetQuota,
+        AuditAction::LinkIdentity,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::SignIn => "sign_in",
+            AuditAction::RefreshSession => "refresh_session",
+            AuditAction::SignOut => "sign_out",
+            AuditAction::EndSessions => "end_sessions",
+            AuditAction::GenerateKey => "generate_key",
+            AuditAction::CreateApiToken => "create_api_token",
+            AuditAction::RevokeApiToken => "revoke_api_token",
+            AuditAction::AdminAccess => "admin_access",
+            AuditAction::SetStatus => "set_status",
+            AuditAction::DeleteUser => "delete_user",
+            AuditAction::GrantPermission => "grant_permission",
+            AuditAction::RevokePermission => "revoke_permission",
+            AuditAction::RevokeKey => "revoke_key",
+            AuditAction::RemoveDevice => "remove_device",
+            AuditAction::SetQuota => "set_quota",
+            AuditAction::LinkIdentity => "link_identity",
+        }
+    }
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AuditAction::ALL.into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!(
+                "Unknown audit action '{}', expected one of {}",
+                s,
+                AuditAction::ALL.map(AuditAction::as_str).join(", ")
+            ))
+    }
+}
+
+/// How an audited action ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    /// The action went through
+    Success,
+    /// The action was refused by policy, such as an untrusted token or a user who is not approved
+    Denied,
+    /// The action was allowed but could not be completed
+    Failure,
+}
+
+impl AuditOutcome {
+    pub const ALL: [AuditOutcome; 3] = [AuditOutcome::Success, AuditOutcome::Denied, AuditOutcome::Failure];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditOutcome::Success => "success",
+            AuditOutcome::Denied => "denied",
+            AuditOutcome::Failure => "failure",
+        }
+    }
+}
+
+impl fmt::Display for AuditOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AuditOutcome {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AuditOutcome::ALL.into_iter()
+            .find(|outcome| outcome.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!(
+                "Unknown audit outcome '{}', expected one of {}",
+                s,
+                AuditOutcome::ALL.map(AuditOutcome::as_str).join(", ")
+            ))
+    }
+}
+
+/// An entry in the append-only audit log
+///
+/// Events are hash-chained: each carries the hash of the event before it and
+/// a hash over its own content and that previous hash, so editing, removing or
+/// reordering a row breaks every link after it.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEvent {
+    pub id: i64,  // Assigned by the database; 0 until the event is recorded
+    pub occurred_at: DateTime<Utc>,
+    pub actor: Option<String>,  // Email of whoever acted, "cli" for subcommands, None if unknown
+    pub subject_user_id: Option<String>,
+    pub action: AuditAction,
+    pub outcome: AuditOutcome,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: serde_json::Value,
+    pub prev_hash: Option<String>,  // Empty for the first chained event; None for events recorded before chaining
+    pub hash: Option<String>,
+}
+
+impl AuditEvent {
+    /// Hash this event's content together with the hash of the event before it
+    ///
+    /// Covers every stored column except the id and the hashes themselves,
+    /// in the textual form they are stored in.
+    pub fn chain_hash(&self, prev_hash: &str) -> String {
+        let content = serde_json::json!([
+            prev_hash,
+            self.occurred_at.to_rfc3339(),
+            self.actor,
+            self.subject_user_id,
+            self.action.as_str(),
+            self.outcome.as_str(),
+            self.ip,
+            self.user_agent,
+            self.details.to_string(),
+        ]);
+
+        hex::encode(Sha256::digest(content.to_string().as_bytes()))
+    }
+}
+
+/// A signed statement of the audit chain's head at some point in time
+///
+/// Exported as an EdDSA-signed JWT. Kept outside the database, checkpoints
+/// show that the chain was not rewritten or truncated after they were taken.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditCheckpoint {
+    pub event_id: i64,
+    pub hash: String,
+    pub events: u64,  // Chained events up to and including event_id
+    pub iat: i64,
+}
+
+/// Who is acting and from where, stamped on every event they cause
+#[derive(Debug, Clone, Default)]
+pub struct AuditContext {
+    pub actor: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AuditContext {
+    /// Context for administrative subcommands run on the server itself
+    pub fn cli() -> Self {
+        AuditContext {
+            actor: Some("cli".to_string()),
+            ..AuditContext::default()
+        }
+    }
+
+    /// The same context attributed to a known actor
+    pub fn with_actor(&self, actor: &str) -> Self {
+        AuditContext {
+            actor: Some(actor.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// Start an event in this context, with no subject and empty details
+    pub fn event(&self, action: AuditAction, outcome: AuditOutcome) -> AuditEvent {
+        AuditEvent {
+            id: 0,
+            occurred_at: Utc::now(),
+            actor: self.actor.clone(),
+            subject_user_id: None,
+            action,
+            outcome,
+            ip: self.ip.clone(),
+            user_agent: self.user_agent.clone(),
+            details: serde_json::json!({}),
+            prev_hash: None,
+            hash: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub user_id: Option<String>,
+    pub action: Option<AuditAction>,
+    pub since: Option<DateTime<Utc>>,  // Inclusive
+    pub until: Option<DateTime<Utc>>,  // Exclusive
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct AuditEventsResponse {
+    pub success: bool,
+    pub events: Vec<AuditEvent>,
+    pub message: String,
+}
@@ -1,9 +1,16 @@
 use serde::{Deserialize, Serialize};
 use super::user::User;
+use super::session::SessionTokens;
+use super::tailscale::{KeyProfile, DeviceInfo};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct GenerateTokenRequest {
-    pub id_token: String,
+    pub id_token: Option<String>,  // Without one, the caller's session is used
+    pub profile: Option<String>,  // Without one, the default key profile is used
+    #[serde(default)]
+    pub single_use: bool,  // Issue a key that enrolls one device, even if the profile is reusable
+    #[serde(flatten)]
+    pub device: DeviceInfo,  // The device being enrolled, written into the key's description
 }
 
 #[derive(Serialize)]
@@ -11,6 +18,10 @@ pub struct ValidateTokenResponse {
     pub success: bool,
     pub user: Option<User>,
     pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,  // Machine-readable cause of a rejected token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<SessionTokens>,  // Issued when an ID token signs the user in
 }
 
 #[derive(Serialize)]
@@ -18,4 +29,24 @@ pub struct GenerateTokenResponse {
     pub success: bool,
     pub tailscale_token: Option<String>,
     pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,  // Machine-readable cause of a rejected token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,  // Seconds to wait before asking again, also sent as Retry-After
+}
+
+#[derive(Serialize)]
+pub struct RateLimitedResponse {
+    pub success: bool,
+    pub message: String,
+    pub reason: &'static str,
+    pub retry_after: u64,  // Seconds until the client may call the route again, also sent as Retry-After
+}
+
+#[derive(Serialize)]
+pub struct KeyProfilesResponse {
+    pub success: bool,
+    pub profiles: Vec<KeyProfile>,  // Only the profiles the caller may use
+    pub default_profile: Option<String>,
+    pub message: String,
 }
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Serialize)]
+pub struct CreatePreAuthKeyRequest {
+    pub user: String,
+    pub reusable: bool,
+    pub ephemeral: bool,
+    pub expiration: DateTime<Utc>,
+    #[serde(rename = "aclTags")]
+    pub acl_tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreAuthKeyResponse {
+    #[serde(rename = "preAuthKey")]
+    pub pre_auth_key: PreAuthKey,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreAuthKeyListResponse {
+    #[serde(rename = "preAuthKeys", default)]
+    pub pre_auth_keys: Vec<PreAuthKey>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreAuthKey {
+    pub id: String,
+    pub key: String,
+    pub expiration: DateTime<Utc>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    // Note: user, reusable, ephemeral, used and aclTags are also returned but not needed
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExpirePreAuthKeyRequest {
+    pub user: String,
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NodeListResponse {
+    #[serde(default)]
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Node {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "givenName", default)]
+    pub given_name: String,
+    #[serde(rename = "createdAt", default)]
+    pub created_at: String,
+    #[serde(default)]
+    pub expiry: Option<DateTime<Utc>>,
+    #[serde(rename = "forcedTags", default)]
+    pub forced_tags: Vec<String>,
+    #[serde(rename = "validTags", default)]
+    pub valid_tags: Vec<String>,
+    #[serde(rename = "preAuthKey", default)]
+    pub pre_auth_key: Option<NodePreAuthKey>,
+}
+
+// The pre-auth key a node registered with; only its id is needed
+#[derive(Debug, Deserialize)]
+pub struct NodePreAuthKey {
+    pub id: String,
+}
@@ -0,0 +1,10 @@
+/// An embedded schema migration and whether the database has it
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    /// When the migration was applied, or None if it is pending
+    pub installed_on: Option<String>,
+    /// False if the applied migration differs from the embedded one
+    pub checksum_matches: bool,
+}
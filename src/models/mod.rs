@@ -1,17 +1,42 @@
 // Domain models organized by module
 
 pub mod user;
-pub mod google;
+pub mod oidc;
 pub mod tailscale;
+pub mod headscale;
 pub mod handlers;
+pub mod admin;
+pub mod migration;
+pub mod audit;
+pub mod session;
+pub mod api_token;
+pub mod quota;
 
 // Re-export commonly used types at the models root
-pub use user::User;
-pub use google::{GoogleIdTokenClaims, GoogleJwks, GoogleJwk};
+pub use user::{User, UserStatus, IllegalTransition, UserPermission, Identity, PERMISSION_ADMIN};
+pub use oidc::{IdTokenClaims, UnverifiedClaims, OidcDiscovery, VerifiedIdentity};
 pub use tailscale::{
-    CreateAuthKeyRequest, CreateAuthKeyResponse, OAuthTokenResponse, CachedToken,
+    CreateAuthKeyRequest, CreateAuthKeyResponse, OAuthTokenResponse, CachedToken, AuthKey, KeyProfile, DeviceInfo,
+    KeyRevocationFailure, DeviceListResponse, Device, UserDevices, DeviceAuthorizedRequest, DeviceRemovalFailure,
     Capabilities, DeviceCapabilities, DeviceCreate,
 };
+pub use headscale::{
+    CreatePreAuthKeyRequest, PreAuthKeyResponse, PreAuthKeyListResponse, ExpirePreAuthKeyRequest,
+    NodeListResponse,
+};
 pub use handlers::{
-    GenerateTokenRequest, ValidateTokenResponse, GenerateTokenResponse,
+    GenerateTokenRequest, ValidateTokenResponse, GenerateTokenResponse, KeyProfilesResponse, RateLimitedResponse,
+};
+pub use admin::{
+    ListUsersQuery, UserStatusRequest, AdminUsersResponse, AdminUserResponse,
+    PermissionRequest, PermissionsResponse, IdentitiesResponse, RevokeKeysResponse, DevicesResponse, RemoveDevicesResponse,
+};
+pub use migration::MigrationStatus;
+pub use session::{
+    SessionTokenKind, SessionClaims, Session, SessionTokens, SessionResponse, SessionsResponse, EndSessionsResponse,
+};
+pub use api_token::{
+    ApiToken, CreateApiTokenRequest, ApiTokenResponse, ApiTokensResponse, SCOPE_GENERATE_KEY, API_TOKEN_SCOPES,
 };
+pub use quota::{QuotaLimits, UserQuota, QuotaUsage, QuotaRequest, QuotaResponse};
+pub use audit::{AuditAction, AuditOutcome, AuditEvent, AuditCheckpoint, AuditContext, AuditQuery, AuditEventsResponse};
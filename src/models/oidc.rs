@@ -0,0 +1,35 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
+    pub hd: Option<String>,  // Google Workspace hosted domain, absent for consumer accounts
+    // Note: iss, aud, exp and iat are checked by jsonwebtoken during validation
+}
+
+// Only the issuer is read from a token before its signature is checked,
+// to pick the provider whose keys verify it
+#[derive(Debug, Deserialize)]
+pub struct UnverifiedClaims {
+    pub iss: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OidcDiscovery {
+    pub issuer: String,
+    pub jwks_uri: String,
+    // Other metadata (endpoints, supported scopes, ...) is ignored by serde
+}
+
+// Who an ID token says the caller is, after the token has been verified
+#[derive(Debug, Clone)]
+pub struct VerifiedIdentity {
+    pub issuer: String,  // The provider's canonical issuer, even if the token used an alias
+    pub subject: String,
+    pub email: String,
+    pub name: Option<String>,
+    pub email_verified: bool,  // The provider requires `email_verified`, so the email is the caller's
+}
@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// The limits that apply to a user, each 0 when there is none
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaLimits {
+    pub keys_per_hour: u32,
+    pub live_keys: u32,
+    pub devices: u32,
+}
+
+// Limits an administrator set for one user, as stored in user_quotas; None falls back to the configured limit
+#[derive(Debug, Clone, Serialize)]
+pub struct UserQuota {
+    pub user_id: String,
+    pub keys_per_hour: Option<u32>,
+    pub live_keys: Option<u32>,
+    pub devices: Option<u32>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// How much of their quota a user has used
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaUsage {
+    pub keys_last_hour: u32,
+    pub live_keys: u32,
+}
+
+/// Replaces a user's overrides; omitted limits fall back to the configured ones
+#[derive(Deserialize)]
+pub struct QuotaRequest {
+    pub keys_per_hour: Option<u32>,
+    pub live_keys: Option<u32>,
+    pub devices: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct QuotaResponse {
+    pub success: bool,
+    pub limits: QuotaLimits,
+    pub overrides: Option<UserQuota>,
+    pub usage: QuotaUsage,
+    pub message: String,
+}
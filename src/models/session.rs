@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+/// What a token we issued may be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionTokenKind {
+    /// Authenticates API requests until it expires
+    Session,
+    /// Only exchanged at `/auth/refresh` for a new session token
+    Refresh,
+}
+
+/// Claims of the session and refresh tokens we sign
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionClaims {
+    pub iss: String,
+    pub sub: String,  // User id
+    pub sid: String,  // Row in the sessions table, shared by a session token and its refresh token
+    pub email: String,
+    pub typ: SessionTokenKind,
+    pub iat: i64,
+    pub exp: i64,
+    #[serde(rename = "gen", default)]
+    pub generation: i64,  // The session's refresh generation when the token was issued
+}
+
+/// A sign-in tracked on the server, which ends early when it is revoked
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,  // When the refresh token expires
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub ended_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub refresh_generation: i64,  // Bumped on every refresh; only a refresh token of this generation is accepted
+}
+
+/// Tokens handed to a signed-in user
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionTokens {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub refresh_token: String,
+    pub refresh_expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    pub success: bool,
+    pub session: Option<SessionTokens>,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct SessionsResponse {
+    pub success: bool,
+    pub sessions: Vec<Session>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<String>,  // Id of the session making the request, if any
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct EndSessionsResponse {
+    pub success: bool,
+    pub ended: Vec<String>,
+    pub message: String,
+}
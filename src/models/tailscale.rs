@@ -1,4 +1,6 @@
 use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use super::user::PERMISSION_KEY_PROFILE_PREFIX;
 
 #[derive(Debug, Serialize)]
 pub struct CreateAuthKeyRequest {
@@ -28,7 +30,160 @@ pub struct DeviceCreate {
 
 #[derive(Debug, Deserialize)]
 pub struct CreateAuthKeyResponse {
+    pub id: String,
     pub key: String,
+    pub created: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+    // Note: capabilities and description are echoed back but we already know them
+}
+
+// An auth key as recorded in the auth_keys table (the secret itself is never stored)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthKey {
+    pub id: String,
+    pub user_id: String,
+    pub tags: String,  // Comma-separated ACL tags the key was created with
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_token_id: Option<String>,  // The API token that requested the key, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,  // Key profile the key was issued under; None for keys issued before profiles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reusable: Option<bool>,  // None for keys issued before this was recorded
+    #[serde(flatten)]
+    pub device: DeviceInfo,
+}
+
+/// Longest hostname a caller may give, the length of a DNS label
+const MAX_HOSTNAME_LENGTH: usize = 63;
+const MAX_OS_LENGTH: usize = 32;
+const MAX_PURPOSE_LENGTH: usize = 100;
+
+// What a caller says about the device an auth key is meant to enroll
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub purpose: Option<String>,
+}
+
+impl DeviceInfo {
+    pub fn is_empty(&self) -> bool {
+        self.hostname.is_none() && self.os.is_none() && self.purpose.is_none()
+    }
+
+    /// Check the fields are of a sensible size and shape, describing the first that is not
+    ///
+    /// Hostnames must be a DNS label: letters, digits and inner hyphens.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(hostname) = &self.hostname {
+            let valid = (1..=MAX_HOSTNAME_LENGTH).contains(&hostname.len())
+                && hostname.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !hostname.starts_with('-')
+                && !hostname.ends_with('-');
+            if !valid {
+                return Err(format!(
+                    "Hostname must be 1 to {} letters, digits or hyphens, not starting or ending with a hyphen",
+                    MAX_HOSTNAME_LENGTH
+                ));
+            }
+        }
+
+        if let Some(os) = &self.os {
+            let valid = (1..=MAX_OS_LENGTH).contains(&os.chars().count())
+                && os.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '.' | '_'));
+            if !valid {
+                return Err(format!("OS must be 1 to {} letters, digits, spaces, '-', '.' or '_'", MAX_OS_LENGTH));
+            }
+        }
+
+        if let Some(purpose) = &self.purpose {
+            let valid = (1..=MAX_PURPOSE_LENGTH).contains(&purpose.chars().count())
+                && !purpose.chars().any(char::is_control);
+            if !valid {
+                return Err(format!("Purpose must be 1 to {} characters on a single line", MAX_PURPOSE_LENGTH));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The settings an auth key is issued with, resolved from a configured key profile
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyProfile {
+    pub name: String,
+    pub tags: Vec<String>,
+    pub expiry_seconds: u64,
+    pub reusable: bool,
+    pub ephemeral: bool,
+}
+
+impl KeyProfile {
+    /// Permission a user needs to request keys with this profile
+    pub fn permission(&self) -> String {
+        format!("{}{}", PERMISSION_KEY_PROFILE_PREFIX, self.name)
+    }
+}
+
+// An auth key that could not be revoked; it stays live in auth_keys so it can be retried
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyRevocationFailure {
+    pub key_id: String,
+    pub error: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceListResponse {
+    pub devices: Vec<Device>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Device {
+    pub id: String,
+    pub name: String,
+    pub hostname: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub created: String,  // RFC 3339, empty for some external devices
+    #[serde(default)]
+    pub authorized: bool,
+    #[serde(rename = "isExternal", default)]
+    pub is_external: bool,
+    // The key the device joined with, where the control plane reports it (Headscale does, Tailscale does not)
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub auth_key_id: Option<String>,
+}
+
+/// The devices attributed to one user
+#[derive(Debug, Default)]
+pub struct UserDevices {
+    /// Devices tied to the user and no one else
+    pub devices: Vec<Device>,
+    /// Devices that may be the user's but could be someone else's; never removed automatically
+    pub ambiguous: Vec<Device>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceAuthorizedRequest {
+    pub authorized: bool,
+}
+
+// A device that could not be removed from the tailnet
+// (device_id is empty when the device list itself could not be fetched)
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceRemovalFailure {
+    pub device_id: String,
+    pub error: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -1,12 +1,202 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+/// Where a user stands in the approval process
+///
+/// Stored in the `status` column as its lowercase name. A status only changes
+/// along the transitions allowed by `can_transition_to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    /// Waiting for an administrator
+    Pending,
+    /// Allowed to generate auth keys
+    Approved,
+    /// Refused access; their keys and devices are removed
+    Denied,
+    /// Temporarily blocked; their keys are revoked but devices stay enrolled
+    Suspended,
+    /// Approval has lapsed and must be renewed; their keys are revoked
+    Expired,
+}
+
+impl UserStatus {
+    pub const ALL: [UserStatus; 5] = [
+        UserStatus::Pending,
+        UserStatus::Approved,
+        UserStatus::Denied,
+        UserStatus::Suspended,
+        UserStatus::Expired,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UserStatus::Pending => "pending",
+            UserStatus::Approved => "approved",
+            UserStatus::Denied => "denied",
+            UserStatus::Suspended => "suspended",
+            UserStatus::Expired => "expired",
+        }
+    }
+
+    /// Check whether a user may move from this status to another
+    ///
+    /// Setting the current status again is always allowed, so a decision can
+    /// be repeated to update its reason or retry revocations. Suspension and
+    /// expiry only apply to approved users.
+    pub fn can_transition_to(self, to: UserStatus) -> bool {
+        use UserStatus::*;
+
+        self == to || matches!(
+            (self, to),
+            (Pending, Approved | Denied)
+                | (Approved, Pending | Denied | Suspended | Expired)
+                | (Denied, Pending | Approved)
+                | (Suspended, Approved | Denied)
+                | (Expired, Pending | Approved | Denied)
+        )
+    }
+
+    /// Whether a new user may be created with this status
+    pub fn is_initial(self) -> bool {
+        matches!(self, UserStatus::Pending | UserStatus::Approved | UserStatus::Denied)
+    }
+
+    /// Whether entering this status revokes the user's live auth keys
+    pub fn revokes_keys(self) -> bool {
+        matches!(self, UserStatus::Denied | UserStatus::Suspended | UserStatus::Expired)
+    }
+
+    /// Whether entering this status ends the user's sessions, signing them out everywhere
+    pub fn ends_sessions(self) -> bool {
+        matches!(self, UserStatus::Denied | UserStatus::Suspended)
+    }
+
+    /// Whether entering this status removes the user's devices from the tailnet
+    pub fn removes_devices(self) -> bool {
+        self == UserStatus::Denied
+    }
+}
+
+impl fmt::Display for UserStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for UserStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        UserStatus::ALL.into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!(
+                "Unknown user status '{}', expected one of {}",
+                s,
+                UserStatus::ALL.map(UserStatus::as_str).join(", ")
+            ))
+    }
+}
+
+/// A status change that the state machine does not allow
+#[derive(Debug)]
+pub struct IllegalTransition {
+    pub from: UserStatus,
+    pub to: UserStatus,
+}
+
+impl fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "User status cannot change from {} to {}", self.from, self.to)
+    }
+}
+
+impl std::error::Error for IllegalTransition {}
+
+/// Permission granting access to the admin API
+pub const PERMISSION_ADMIN: &str = "admin";
+
+/// Prefix of the permissions granting use of a key profile, followed by the profile name
+pub const PERMISSION_KEY_PROFILE_PREFIX: &str = "key_profile:";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: String,
     pub email: String,
     pub name: String,
-    pub status: String,  // 'pending', 'approved', 'denied'
+    pub status: UserStatus,
+    pub created_at: DateTime<Utc>,
+    pub last_login: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_reason: Option<String>,  // Reason given with the last admin decision or approval rule
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserPermission {
+    pub permission: String,
+    pub granted_at: DateTime<Utc>,
+}
+
+// A provider account linked to a user, keyed by issuer and subject
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identity {
+    pub issuer: String,
+    pub subject: String,
+    pub user_id: String,
+    pub email: String,
     pub created_at: DateTime<Utc>,
     pub last_login: DateTime<Utc>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use UserStatus::*;
+
+    #[test]
+    fn transitions_follow_the_state_machine() {
+        let allowed = [
+            (Pending, Approved),
+            (Pending, Denied),
+            (Approved, Pending),
+            (Approved, Denied),
+            (Approved, Suspended),
+            (Approved, Expired),
+            (Denied, Pending),
+            (Denied, Approved),
+            (Suspended, Approved),
+            (Suspended, Denied),
+            (Expired, Pending),
+            (Expired, Approved),
+            (Expired, Denied),
+        ];
+
+        for from in UserStatus::ALL {
+            for to in UserStatus::ALL {
+                let expected = from == to || allowed.contains(&(from, to));
+                assert_eq!(from.can_transition_to(to), expected, "{} -> {}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn every_status_that_ends_sessions_or_removes_devices_revokes_keys() {
+        for status in UserStatus::ALL {
+            if status.ends_sessions() || status.removes_devices() {
+                assert!(status.revokes_keys(), "{}", status);
+            }
+        }
+    }
+
+    #[test]
+    fn statuses_round_trip_through_their_names() {
+        for status in UserStatus::ALL {
+            assert_eq!(status.as_str().parse::<UserStatus>().unwrap(), status);
+        }
+        assert!("active".parse::<UserStatus>().is_err());
+    }
+}
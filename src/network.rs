@@ -0,0 +1,277 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use tracing::warn;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use crate::config::{get_config, DeviceRemoval, NetworkProviderKind, TailscaleConfig, KeyProfileConfig};
+use crate::audit;
+use crate::headscale::HeadscaleProvider;
+use crate::models::{
+    CreateAuthKeyResponse, Device, DeviceInfo, KeyProfile, KeyRevocationFailure, AuditContext, AuditEvent, AuditAction, AuditOutcome,
+};
+use crate::tailscale::TailscaleProvider;
+use crate::store::Store;
+
+/// Name of the single profile offered when no key profiles are configured
+pub const DEFAULT_KEY_PROFILE: &str = "default";
+
+/// Control plane that issues auth keys and manages devices on the network
+#[async_trait]
+pub trait NetworkProvider: Send + Sync {
+    /// Create a preauthorized auth key for a user with the settings of a key profile
+    ///
+    /// The device it is meant for is described on the key where the control plane allows it.
+    async fn generate_auth_key(&self, user_email: &str, profile: &KeyProfile, device: &DeviceInfo) -> Result<CreateAuthKeyResponse>;
+
+    /// Revoke an auth key by id; a key that no longer exists counts as revoked
+    async fn revoke_auth_key(&self, key_id: &str) -> Result<()>;
+
+    /// List every device on the network
+    async fn list_devices(&self) -> Result<Vec<Device>>;
+
+    /// Delete or de-authorize a device
+    async fn remove_device(&self, device_id: &str, removal: DeviceRemoval) -> Result<()>;
+}
+
+// Global provider instance, chosen once from configuration
+static PROVIDER: OnceLock<Box<dyn NetworkProvider>> = OnceLock::new();
+
+/// Get the configured network provider
+pub fn get_provider() -> &'static dyn NetworkProvider {
+    PROVIDER.get_or_init(|| match get_config().tailscale.provider {
+        NetworkProviderKind::Tailscale => Box::new(TailscaleProvider),
+        NetworkProviderKind::Headscale => Box::new(HeadscaleProvider),
+    }).as_ref()
+}
+
+/// The configured key profiles, and the one used when a request names none
+struct KeyProfiles {
+    profiles: BTreeMap<String, KeyProfile>,
+    default: Option<String>,
+}
+
+// Key profiles, resolved once from configuration
+static KEY_PROFILES: OnceLock<KeyProfiles> = OnceLock::new();
+
+fn build_key_profiles(config: &TailscaleConfig) -> Result<KeyProfiles> {
+    // Without configured profiles every key gets the global tags, as before profiles existed
+    if config.key_profiles.is_empty() {
+        if let Some(default) = &config.default_key_profile
+            && default != DEFAULT_KEY_PROFILE
+        {
+            return Err(anyhow!("Default key profile {} is not configured", default));
+        }
+        let profile = resolve_key_profile(config, DEFAULT_KEY_PROFILE, &KeyProfileConfig::default())?;
+        return Ok(KeyProfiles {
+            profiles: BTreeMap::from([(DEFAULT_KEY_PROFILE.to_string(), profile)]),
+            default: Some(DEFAULT_KEY_PROFILE.to_string()),
+        });
+    }
+
+    let profiles = config.key_profiles.iter()
+        .map(|(name, profile)| Ok((name.clone(), resolve_key_profile(config, name, profile)?)))
+        .collect::<Result<BTreeMap<_, _>>>()?;
+
+    if let Some(default) = &config.default_key_profile
+        && !profiles.contains_key(default)
+    {
+        return Err(anyhow!("Default key profile {} is not configured", default));
+    }
+
+    Ok(KeyProfiles {
+        profiles,
+        default: config.default_key_profile.clone(),
+    })
+}
+
+fn resolve_key_profile(config: &TailscaleConfig, name: &str, profile: &KeyProfileConfig) -> Result<KeyProfile> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(anyhow!("Key profile name '{}' may only contain letters, digits, '-' and '_'", name));
+    }
+    if profile.expiry_seconds == 0 {
+        return Err(anyhow!("Key profile {} must have a positive expiry_seconds", name));
+    }
+
+    Ok(KeyProfile {
+        name: name.to_string(),
+        tags: profile.tags.clone().unwrap_or_else(|| config.auth_key_tags.clone()),
+        expiry_seconds: profile.expiry_seconds,
+        reusable: profile.reusable,
+        ephemeral: profile.ephemeral,
+    })
+}
+
+fn get_key_profiles() -> &'static KeyProfiles {
+    KEY_PROFILES.get_or_init(|| {
+        build_key_profiles(&get_config().tailscale).unwrap_or_else(|e| {
+            eprintln!("Failed to set up key profiles: {}", e);
+            std::process::exit(1);
+        })
+    })
+}
+
+/// Resolve the configured key profiles, exiting on an invalid one
+///
+/// Called at startup so a bad profile is reported before anyone requests a
+/// key. Returns the number of profiles.
+pub fn load_key_profiles() -> usize {
+    get_key_profiles().profiles.len()
+}
+
+pub fn find_key_profile(name: &str) -> Option<&'static KeyProfile> {
+    get_key_profiles().profiles.get(name)
+}
+
+/// The profile used when a request names none, if one is configured
+pub fn default_key_profile() -> Option<&'static KeyProfile> {
+    get_key_profiles().default.as_deref().and_then(find_key_profile)
+}
+
+/// Whether a user holding the given permissions may request keys with a profile
+///
+/// The default profile is open to everyone; any other needs its
+/// `key_profile:<name>` permission.
+pub fn may_use_key_profile(profile: &KeyProfile, permissions: &[String]) -> bool {
+    get_key_profiles().may_use(profile, permissions)
+}
+
+impl KeyProfiles {
+    fn may_use(&self, profile: &KeyProfile, permissions: &[String]) -> bool {
+        self.default.as_deref() == Some(profile.name.as_str())
+            || permissions.contains(&profile.permission())
+    }
+}
+
+/// The profiles a user holding the given permissions may request keys with
+pub fn allowed_key_profiles(permissions: &[String]) -> Vec<KeyProfile> {
+    get_key_profiles().profiles.values()
+        .filter(|profile| may_use_key_profile(profile, permissions))
+        .cloned()
+        .collect()
+}
+
+/// Revoke every live auth key recorded for a user
+///
+/// Each key is attempted independently. Returns the ids of the keys that were
+/// revoked and the keys that could not be; failed keys stay live in the
+/// database so a later call retries them. Every attempt is audited in the
+/// given context.
+pub async fn revoke_user_keys(store: &dyn Store, user_id: &str, context: &AuditContext) -> Result<(Vec<String>, Vec<KeyRevocationFailure>)> {
+    let mut revoked = Vec::new();
+    let mut failures = Vec::new();
+
+    for key in store.list_live_auth_keys(user_id).await? {
+        let result = match get_provider().revoke_auth_key(&key.id).await {
+            Ok(()) => store.mark_auth_key_revoked(&key.id).await,
+            Err(e) => Err(e),
+        };
+
+        let event = AuditEvent {
+            subject_user_id: Some(user_id.to_string()),
+            ..context.event(AuditAction::RevokeKey, AuditOutcome::Success)
+        };
+
+        match result {
+            Ok(()) => {
+                audit::record(store, AuditEvent {
+                    details: serde_json::json!({ "key_id": key.id }),
+                    ..event
+                }).await;
+                revoked.push(key.id);
+            }
+            Err(e) => {
+                warn!("Failed to revoke auth key {} of user {}: {}", key.id, user_id, e);
+                audit::record(store, AuditEvent {
+                    outcome: AuditOutcome::Failure,
+                    details: serde_json::json!({ "key_id": key.id, "error": e.to_string() }),
+                    ..event
+                }).await;
+                failures.push(KeyRevocationFailure {
+                    key_id: key.id,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok((revoked, failures))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(key_profiles: serde_json::Value, default_key_profile: Option<&str>) -> TailscaleConfig {
+        serde_json::from_value(json!({
+            "oauth_secret_path": "/nonexistent",
+            "api_url": "https://api.tailscale.com/api/v2",
+            "auth_key_tags": ["tag:user"],
+            "key_profiles": key_profiles,
+            "default_key_profile": default_key_profile,
+        })).unwrap()
+    }
+
+    fn profiles() -> KeyProfiles {
+        build_key_profiles(&config(json!({
+            "laptop": {},
+            "server": { "tags": ["tag:server"], "reusable": false, "expiry_seconds": 600 },
+        }), Some("laptop"))).unwrap()
+    }
+
+    fn permissions(permissions: &[&str]) -> Vec<String> {
+        permissions.iter().map(|permission| permission.to_string()).collect()
+    }
+
+    #[test]
+    fn default_profile_is_open_to_everyone() {
+        let profiles = profiles();
+
+        assert!(profiles.may_use(&profiles.profiles["laptop"], &[]));
+    }
+
+    #[test]
+    fn other_profiles_need_their_permission() {
+        let profiles = profiles();
+        let server = &profiles.profiles["server"];
+
+        assert!(!profiles.may_use(server, &[]));
+        assert!(!profiles.may_use(server, &permissions(&["key_profile:laptop", "admin"])));
+        assert!(profiles.may_use(server, &permissions(&["key_profile:server"])));
+    }
+
+    #[test]
+    fn profiles_fall_back_to_the_global_settings() {
+        let profiles = profiles();
+
+        assert_eq!(profiles.profiles["laptop"].tags, vec!["tag:user".to_string()]);
+        assert_eq!(profiles.profiles["laptop"].expiry_seconds, 7200);
+        assert_eq!(profiles.profiles["server"].tags, vec!["tag:server".to_string()]);
+        assert!(!profiles.profiles["server"].reusable);
+    }
+
+    #[test]
+    fn without_profiles_there_is_one_open_default() {
+        let profiles = build_key_profiles(&config(json!({}), None)).unwrap();
+
+        assert_eq!(profiles.profiles.len(), 1);
+        assert_eq!(profiles.default.as_deref(), Some(DEFAULT_KEY_PROFILE));
+        assert!(profiles.may_use(&profiles.profiles[DEFAULT_KEY_PROFILE], &[]));
+    }
+
+    #[test]
+    fn without_a_default_every_profile_needs_its_permission() {
+        let profiles = build_key_profiles(&config(json!({ "laptop": {} }), None)).unwrap();
+
+        assert!(!profiles.may_use(&profiles.profiles["laptop"], &[]));
+    }
+
+    #[test]
+    fn unknown_or_invalid_profiles_are_rejected() {
+        assert!(build_key_profiles(&config(json!({ "laptop": {} }), Some("desktop"))).is_err());
+        assert!(build_key_profiles(&config(json!({}), Some("desktop"))).is_err());
+        assert!(build_key_profiles(&config(json!({ "lap top": {} }), None)).is_err());
+        assert!(build_key_profiles(&config(json!({ "laptop": { "expiry_seconds": 0 } }), None)).is_err());
+        assert!(!profiles().profiles.contains_key("desktop"));
+    }
+}
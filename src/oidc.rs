@@ -0,0 +1,321 @@
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{Utc, DateTime};
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+use tracing::debug;
+use crate::config::{get_config, IdentityProviderConfig};
+use crate::models::{IdTokenClaims, UnverifiedClaims, OidcDiscovery, VerifiedIdentity};
+use crate::google;
+
+const CACHE_DURATION_HOURS: i64 = 24;
+// Minimum age before an unknown key ID may trigger a refetch, so garbage tokens can't hammer the JWKS endpoint
+const REFRESH_COOLDOWN_MINUTES: i64 = 5;
+
+/// A validly signed token that fails one of the provider's account policies
+#[derive(Debug)]
+pub enum TokenRejection {
+    EmailNotVerified,
+    HostedDomainNotAllowed(Option<String>),
+}
+
+impl fmt::Display for TokenRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenRejection::EmailNotVerified => write!(f, "Email address is not verified"),
+            TokenRejection::HostedDomainNotAllowed(Some(domain)) => write!(f, "Accounts from {} are not allowed", domain),
+            TokenRejection::HostedDomainNotAllowed(None) => write!(f, "Only organization accounts are allowed"),
+        }
+    }
+}
+
+impl std::error::Error for TokenRejection {}
+
+/// An OpenID Connect provider together with its cached signing keys
+pub struct IdentityProvider {
+    config: IdentityProviderConfig,
+    jwks_uri: Mutex<Option<String>>,
+    jwks_cache: Mutex<Option<(DateTime<Utc>, JwkSet)>>,
+}
+
+impl IdentityProvider {
+    pub fn new(config: IdentityProviderConfig) -> Result<Self> {
+        if config.audiences.is_empty() {
+            return Err(anyhow!("Identity provider {} has no audiences", config.name));
+        }
+
+        // Symmetric keys have no place in a public JWKS
+        if let Some(algorithm) = config.algorithms.iter()
+            .find(|algorithm| matches!(algorithm, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512))
+        {
+            return Err(anyhow!("Identity provider {} allows {:?}; only asymmetric algorithms are supported", config.name, algorithm));
+        }
+
+        Ok(IdentityProvider {
+            jwks_uri: Mutex::new(config.jwks_uri.clone()),
+            jwks_cache: Mutex::new(None),
+            config,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// The issuer recorded with this provider's identities
+    pub fn issuer(&self) -> &str {
+        &self.config.issuer
+    }
+
+    /// Check whether this provider's tokens are refused unless the email is verified
+    pub fn verifies_email(&self) -> bool {
+        self.config.require_verified_email
+    }
+
+    /// Check whether a token's `iss` claim belongs to this provider
+    pub fn accepts_issuer(&self, issuer: &str) -> bool {
+        self.config.issuer == issuer || self.config.additional_issuers.iter().any(|other| other == issuer)
+    }
+
+    pub async fn validate_id_token(&self, id_token: &str) -> Result<VerifiedIdentity> {
+        // Decode the header to get the algorithm and key ID
+        let header = decode_header(id_token)?;
+        if !self.config.algorithms.contains(&header.alg) {
+            return Err(anyhow!("Token algorithm {:?} is not allowed", header.alg));
+        }
+        let kid = header.kid.ok_or_else(|| anyhow!("Token missing key ID"))?;
+
+        // Find the matching key, refetching once in case the provider rotated its keys
+        let jwk = match self.get_jwks(false).await?.find(&kid) {
+            Some(jwk) => jwk.clone(),
+            None => self.get_jwks(true).await?
+                .find(&kid)
+                .cloned()
+                .ok_or_else(|| anyhow!("Key ID not found in {} JWKS", self.config.name))?,
+        };
+
+        let decoding_key = DecodingKey::from_jwk(&jwk)
+            .map_err(|e| anyhow!("Failed to create decoding key: {}", e))?;
+
+        // Set up validation parameters
+        let mut issuers = vec![self.config.issuer.as_str()];
+        issuers.extend(self.config.additional_issuers.iter().map(String::as_str));
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&self.config.audiences);
+        validation.set_issuer(&issuers);
+
+        // Validate the token
+        let token_data = decode::<IdTokenClaims>(id_token, &decoding_key, &validation)?;
+
+        self.identity_from_claims(token_data.claims)
+    }
+
+    /// Apply the provider's account policies to a verified token's claims
+    ///
+    /// The email must be present and, if the provider requires it, verified;
+    /// with allowed hosted domains, the `hd` claim must be one of them.
+    fn identity_from_claims(&self, claims: IdTokenClaims) -> Result<VerifiedIdentity> {
+        let email = claims.email.ok_or_else(|| anyhow!("Token has no email claim"))?;
+
+        if self.config.require_verified_email && claims.email_verified != Some(true) {
+            return Err(TokenRejection::EmailNotVerified.into());
+        }
+
+        if !self.config.allowed_hosted_domains.is_empty() {
+            let allowed = claims.hd.as_deref().is_some_and(|hd| {
+                self.config.allowed_hosted_domains.iter().any(|domain| domain.eq_ignore_ascii_case(hd))
+            });
+            if !allowed {
+                return Err(TokenRejection::HostedDomainNotAllowed(claims.hd).into());
+            }
+        }
+
+        Ok(VerifiedIdentity {
+            issuer: self.config.issuer.clone(),
+            subject: claims.sub,
+            email,
+            name: claims.name,
+            email_verified: self.config.require_verified_email,
+        })
+    }
+
+    /// Get the provider's JWKS URL, from configuration or OIDC discovery
+    async fn get_jwks_uri(&self) -> Result<String> {
+        if let Some(jwks_uri) = self.jwks_uri.lock().unwrap().as_ref() {
+            return Ok(jwks_uri.clone());
+        }
+
+        let discovery_url = self.config.discovery_url.clone().unwrap_or_else(|| {
+            format!("{}/.well-known/openid-configuration", self.config.issuer.trim_end_matches('/'))
+        });
+
+        debug!("Fetching OIDC discovery document for {} from {}", self.config.name, discovery_url);
+
+        let response = reqwest::get(&discovery_url).await?.error_for_status()?;
+        let discovery: OidcDiscovery = response.json().await?;
+
+        if discovery.issuer != self.config.issuer {
+            return Err(anyhow!(
+                "Discovery document for {} names issuer {}, expected {}",
+                self.config.name, discovery.issuer, self.config.issuer
+            ));
+        }
+
+        *self.jwks_uri.lock().unwrap() = Some(discovery.jwks_uri.clone());
+
+        Ok(discovery.jwks_uri)
+    }
+
+    async fn get_jwks(&self, refresh: bool) -> Result<JwkSet> {
+        // Check cache first
+        {
+            let cache_guard = self.jwks_cache.lock().unwrap();
+            if let Some((cached_at, jwks)) = cache_guard.as_ref() {
+                let age = Utc::now().signed_duration_since(*cached_at);
+                let stale = age.num_hours() >= CACHE_DURATION_HOURS
+                    || (refresh && age.num_minutes() >= REFRESH_COOLDOWN_MINUTES);
+                if !stale {
+                    return Ok(jwks.clone());
+                }
+            }
+        } // Lock is released here
+
+        // Fetch fresh keys from the provider
+        let jwks_uri = self.get_jwks_uri().await?;
+        let response = reqwest::get(&jwks_uri).await?.error_for_status()?;
+        let jwks: JwkSet = response.json().await?;
+
+        // Update cache
+        {
+            let mut cache_guard = self.jwks_cache.lock().unwrap();
+            *cache_guard = Some((Utc::now(), jwks.clone()));
+        }
+
+        Ok(jwks)
+    }
+}
+
+// Configured identity providers, built once from configuration
+static PROVIDERS: OnceLock<Vec<IdentityProvider>> = OnceLock::new();
+
+fn build_providers() -> Result<Vec<IdentityProvider>> {
+    let config = get_config();
+
+    let configs = config.google.iter()
+        .map(google::provider_config)
+        .chain(config.identity_providers.iter().cloned());
+
+    let providers = configs.map(IdentityProvider::new).collect::<Result<Vec<_>>>()?;
+
+    if providers.is_empty() {
+        return Err(anyhow!("No identity providers configured; set google.client_id or add [[identity_providers]]"));
+    }
+
+    Ok(providers)
+}
+
+/// Get the configured identity providers
+pub fn get_providers() -> &'static [IdentityProvider] {
+    PROVIDERS.get_or_init(|| {
+        build_providers().unwrap_or_else(|e| {
+            eprintln!("Failed to set up identity providers: {}", e);
+            std::process::exit(1);
+        })
+    })
+}
+
+/// Find the provider that issues tokens with the given `iss` claim
+pub fn find_provider(issuer: &str) -> Option<&'static IdentityProvider> {
+    get_providers().iter().find(|provider| provider.accepts_issuer(issuer))
+}
+
+/// Read the `iss` claim of a token without verifying it
+///
+/// Only good for choosing which provider should verify the token.
+pub fn unverified_issuer(id_token: &str) -> Result<String> {
+    let payload = id_token.split('.').nth(1).ok_or_else(|| anyhow!("Malformed token"))?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| anyhow!("Malformed token payload"))?;
+    let claims: UnverifiedClaims = serde_json::from_slice(&payload).map_err(|_| anyhow!("Token missing issuer"))?;
+
+    Ok(claims.iss)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn provider(require_verified_email: bool, allowed_hosted_domains: &[&str]) -> IdentityProvider {
+        IdentityProvider::new(serde_json::from_value(json!({
+            "name": "test",
+            "issuer": "https://sso.example.com",
+            "audiences": ["client"],
+            "require_verified_email": require_verified_email,
+            "allowed_hosted_domains": allowed_hosted_domains,
+        })).unwrap()).unwrap()
+    }
+
+    fn claims(email_verified: Option<bool>, hd: Option<&str>) -> IdTokenClaims {
+        IdTokenClaims {
+            sub: "alice".to_string(),
+            email: Some("alice@example.com".to_string()),
+            email_verified,
+            name: Some("Alice".to_string()),
+            hd: hd.map(str::to_string),
+        }
+    }
+
+    fn rejection(result: Result<VerifiedIdentity>) -> TokenRejection {
+        result.unwrap_err().downcast::<TokenRejection>().unwrap()
+    }
+
+    #[test]
+    fn verified_email_signs_in() {
+        let identity = provider(true, &[]).identity_from_claims(claims(Some(true), None)).unwrap();
+
+        assert_eq!(identity.issuer, "https://sso.example.com");
+        assert_eq!(identity.subject, "alice");
+        assert_eq!(identity.email, "alice@example.com");
+        assert!(identity.email_verified);
+    }
+
+    #[test]
+    fn unverified_email_is_rejected_when_verification_is_required() {
+        let provider = provider(true, &[]);
+
+        assert!(matches!(rejection(provider.identity_from_claims(claims(Some(false), None))), TokenRejection::EmailNotVerified));
+        assert!(matches!(rejection(provider.identity_from_claims(claims(None, None))), TokenRejection::EmailNotVerified));
+    }
+
+    #[test]
+    fn unverified_email_is_accepted_but_marked_when_verification_is_not_required() {
+        let identity = provider(false, &[]).identity_from_claims(claims(Some(false), None)).unwrap();
+
+        assert!(!identity.email_verified);
+    }
+
+    #[test]
+    fn missing_email_is_rejected() {
+        let mut claims = claims(Some(true), None);
+        claims.email = None;
+
+        assert!(provider(true, &[]).identity_from_claims(claims).is_err());
+    }
+
+    #[test]
+    fn hosted_domain_must_be_allowed() {
+        let provider = provider(true, &["example.com"]);
+
+        assert!(provider.identity_from_claims(claims(Some(true), Some("Example.com"))).is_ok());
+        assert!(matches!(
+            rejection(provider.identity_from_claims(claims(Some(true), None))),
+            TokenRejection::HostedDomainNotAllowed(None)
+        ));
+        assert!(matches!(
+            rejection(provider.identity_from_claims(claims(Some(true), Some("evil.example")))),
+            TokenRejection::HostedDomainNotAllowed(Some(domain)) if domain == "evil.example"
+        ));
+    }
+}
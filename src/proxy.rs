@@ -0,0 +1,180 @@
+use anyhow::{Result, anyhow};
+use axum::http::HeaderMap;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use crate::config::get_config;
+
+/// Header through which reverse proxies pass on the addresses they forwarded for
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// An address range in CIDR notation; a bare address is a range of one
+struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    fn parse(range: &str) -> Result<Self> {
+        let (address, prefix) = match range.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (range, None),
+        };
+        let network: IpAddr = address.trim().parse()
+            .map_err(|_| anyhow!("Trusted proxy '{}' is not an IP address or CIDR range", range))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse::<u8>().ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| anyhow!("Trusted proxy '{}' has a prefix length outside 0 to {}", range, max_prefix))?,
+            None => max_prefix,
+        };
+
+        Ok(IpRange { network, prefix })
+    }
+
+    fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                prefix_matches(u32::from(network).into(), u32::from(address).into(), 32, self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                prefix_matches(network.into(), address.into(), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(network: u128, address: u128, bits: u8, prefix: u8) -> bool {
+    let shift = bits - prefix;
+    shift == bits || (network >> shift) == (address >> shift)
+}
+
+// Trusted proxy ranges, parsed once from configuration
+static TRUSTED_PROXIES: OnceLock<Vec<IpRange>> = OnceLock::new();
+
+fn trusted_proxies() -> &'static [IpRange] {
+    TRUSTED_PROXIES.get_or_init(|| {
+        get_config().server.trusted_proxies.iter()
+            .map(|range| IpRange::parse(range))
+            .collect::<Result<_>>()
+            .unwrap_or_else(|e| {
+                eprintln!("Invalid [server] trusted_proxies: {}", e);
+                std::process::exit(1);
+            })
+    })
+}
+
+/// Parse the trusted proxies, exiting on an invalid one
+///
+/// Called at startup so a typo is reported before the first request. Returns
+/// the number of ranges.
+pub fn load_trusted_proxies() -> usize {
+    trusted_proxies().len()
+}
+
+/// The address of the client behind a request
+///
+/// Starting from the connected peer, X-Forwarded-For is read from the right
+/// for as long as each hop is a trusted proxy, so a client cannot pose as
+/// another address by sending the header itself. Without trusted proxies
+/// the peer is always the client.
+pub fn client_ip(headers: &HeaderMap, peer: IpAddr) -> IpAddr {
+    forwarded_client(headers, peer, trusted_proxies())
+}
+
+fn forwarded_client(headers: &HeaderMap, peer: IpAddr, trusted: &[IpRange]) -> IpAddr {
+    let is_trusted = |address: IpAddr| trusted.iter().any(|range| range.contains(address));
+    let mut client = peer.to_canonical();
+
+    let forwarded = headers.get_all(FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+
+    for hop in forwarded.into_iter().rev() {
+        if !is_trusted(client) {
+            break;
+        }
+        match hop.trim().parse::<IpAddr>() {
+            Ok(address) => client = address.to_canonical(),
+            // A malformed hop was not written by a proxy we trust, so stop at the last one that was
+            Err(_) => break,
+        }
+    }
+
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn ranges(ranges: &[&str]) -> Vec<IpRange> {
+        ranges.iter().map(|range| IpRange::parse(range).unwrap()).collect()
+    }
+
+    fn forwarded_for(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(FORWARDED_FOR, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn header_is_ignored_without_trusted_proxies() {
+        let headers = forwarded_for(&["198.51.100.7"]);
+
+        assert_eq!(forwarded_client(&headers, ip("10.0.0.2"), &[]), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn header_is_ignored_from_untrusted_peers() {
+        let headers = forwarded_for(&["198.51.100.7"]);
+
+        assert_eq!(forwarded_client(&headers, ip("203.0.113.9"), &ranges(&["10.0.0.0/8"])), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn trusted_hops_are_skipped_from_the_right() {
+        let trusted = ranges(&["10.0.0.0/8", "192.0.2.1"]);
+        // The client forged the first entry; the proxies appended the rest
+        let headers = forwarded_for(&["1.2.3.4, 198.51.100.7, 10.1.2.3", "192.0.2.1"]);
+
+        assert_eq!(forwarded_client(&headers, ip("10.0.0.2"), &trusted), ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn malformed_hops_stop_at_the_last_trusted_address() {
+        let trusted = ranges(&["10.0.0.0/8"]);
+        let headers = forwarded_for(&["198.51.100.7, not-an-address, 10.1.2.3"]);
+
+        assert_eq!(forwarded_client(&headers, ip("10.0.0.2"), &trusted), ip("10.1.2.3"));
+    }
+
+    #[test]
+    fn ipv6_ranges_and_mapped_addresses_are_understood() {
+        let trusted = ranges(&["2001:db8::/32", "10.0.0.1"]);
+        let headers = forwarded_for(&["2001:db8:ffff::1, ::ffff:198.51.100.7"]);
+
+        assert_eq!(forwarded_client(&headers, ip("2001:db8::2"), &trusted), ip("198.51.100.7"));
+        assert_eq!(forwarded_client(&HeaderMap::new(), ip("::ffff:10.0.0.1"), &trusted), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn ranges_are_parsed_strictly() {
+        assert!(IpRange::parse("10.0.0.0/8").unwrap().contains(ip("10.255.0.1")));
+        assert!(!IpRange::parse("10.0.0.0/8").unwrap().contains(ip("11.0.0.1")));
+        assert!(IpRange::parse("0.0.0.0/0").unwrap().contains(ip("203.0.113.9")));
+        assert!(!IpRange::parse("0.0.0.0/0").unwrap().contains(ip("::1")));
+        assert!(IpRange::parse("10.0.0.0/33").is_err());
+        assert!(IpRange::parse("proxy.internal").is_err());
+    }
+}
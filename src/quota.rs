@@ -0,0 +1,266 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::OwnedMutexGuard;
+use crate::config::get_config;
+use crate::models::{QuotaLimits, QuotaUsage, QuotaRequest, UserQuota, AuditContext, AuditEvent, AuditAction, AuditOutcome};
+use crate::{audit, devices};
+use crate::store::{QuotaLock, Store};
+
+/// Window over which `keys_per_hour` is counted
+const KEY_RATE_WINDOW_SECONDS: i64 = 3600;
+
+/// A request for an auth key that would take a user over one of their limits
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    /// Which limit was reached: `keys_per_hour`, `live_keys` or `devices`
+    pub limit: &'static str,
+    pub message: String,
+    /// Seconds until a key can be issued again; None when waiting will not help
+    pub retry_after: Option<u64>,
+}
+
+/// Why a user may not be issued an auth key right now
+#[derive(Debug)]
+pub enum QuotaError {
+    Exceeded(QuotaExceeded),
+    /// The limits or usage could not be read
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for QuotaError {
+    fn from(e: anyhow::Error) -> Self {
+        QuotaError::Failed(e)
+    }
+}
+
+/// A user's place in their quotas, held from the check until their new key is recorded
+///
+/// Only one reservation per user is held at a time, across every server
+/// sharing the store, so concurrent requests cannot all pass the check before
+/// any of their keys is counted.
+pub struct Reservation {
+    _lock: QuotaLock,
+    _guard: OwnedMutexGuard<()>,
+}
+
+// One lock per user with a reservation held or awaited
+static USER_LOCKS: OnceLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> = OnceLock::new();
+
+async fn lock_user(user_id: &str) -> OwnedMutexGuard<()> {
+    let lock = {
+        let mut locks = USER_LOCKS.get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Drop the locks nobody holds or waits for, so the map only grows with concurrent users
+        locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+        locks.entry(user_id.to_string()).or_default().clone()
+    };
+    lock.lock_owned().await
+}
+
+/// Combine the configured limits with a user's overrides
+pub fn effective_limits(overrides: Option<&UserQuota>) -> QuotaLimits {
+    let config = &get_config().quotas;
+
+    QuotaLimits {
+        keys_per_hour: overrides.and_then(|quota| quota.keys_per_hour).unwrap_or(config.keys_per_hour),
+        live_keys: overrides.and_then(|quota| quota.live_keys).unwrap_or(config.live_keys),
+        devices: overrides.and_then(|quota| quota.devices).unwrap_or(config.devices),
+    }
+}
+
+/// Look up the limits that apply to a user
+pub async fn limits(store: &dyn Store, user_id: &str) -> Result<QuotaLimits> {
+    Ok(effective_limits(store.find_user_quota(user_id).await?.as_ref()))
+}
+
+/// Count the auth keys a user was issued in the last hour and holds live
+pub async fn usage(store: &dyn Store, user_id: &str) -> Result<QuotaUsage> {
+    let since = Utc::now() - Duration::seconds(KEY_RATE_WINDOW_SECONDS);
+
+    Ok(QuotaUsage {
+        keys_last_hour: store.list_auth_keys_since(user_id, since).await?.len() as u32,
+        live_keys: store.list_live_auth_keys(user_id).await?.len() as u32,
+    })
+}
+
+/// Replace a user's quota overrides, recording who set them
+pub async fn set(store: &dyn Store, user_id: &str, request: &QuotaRequest, context: &AuditContext) -> Result<UserQuota> {
+    let quota = UserQuota {
+        user_id: user_id.to_string(),
+        keys_per_hour: request.keys_per_hour,
+        live_keys: request.live_keys,
+        devices: request.devices,
+        updated_at: Utc::now(),
+    };
+
+    store.set_user_quota(&quota).await?;
+    audit::record(store, AuditEvent {
+        subject_user_id: Some(user_id.to_string()),
+        details: json!({ "keys_per_hour": quota.keys_per_hour, "live_keys": quota.live_keys, "devices": quota.devices }),
+        ..context.event(AuditAction::SetQuota, AuditOutcome::Success)
+    }).await;
+
+    Ok(quota)
+}
+
+/// Remove a user's quota overrides so the configured limits apply again
+///
+/// Returns false, recording nothing, if the user had no overrides.
+pub async fn clear(store: &dyn Store, user_id: &str, context: &AuditContext) -> Result<bool> {
+    let cleared = store.clear_user_quota(user_id).await?;
+
+    if cleared {
+        audit::record(store, AuditEvent {
+            subject_user_id: Some(user_id.to_string()),
+            details: json!({ "cleared": true }),
+            ..context.event(AuditAction::SetQuota, AuditOutcome::Success)
+        }).await;
+    }
+
+    Ok(cleared)
+}
+
+/// Reserve a user's place for another auth key, checking their quotas
+///
+/// Waits for any other reservation of the user to be dropped first. Hold the
+/// reservation until the new key is recorded, or until issuing it failed.
+pub async fn reserve(store: &dyn Store, user_id: &str) -> Result<Reservation, QuotaError> {
+    // Requests in this process queue here, so each holds a database connection only once it is its turn
+    let guard = lock_user(user_id).await;
+    let lock = store.lock_user_quota(user_id).await?;
+    check(store, user_id).await?;
+    Ok(Reservation { _lock: lock, _guard: guard })
+}
+
+/// Check whether a user may be issued another auth key
+///
+/// Counts the keys recorded for the user, so keys revoked early stop counting
+/// against `live_keys` straight away. The device limit is checked last, as it
+/// needs the tailnet's device list.
+pub async fn check(store: &dyn Store, user_id: &str) -> Result<(), QuotaError> {
+    let limits = limits(store, user_id).await?;
+    let now = Utc::now();
+
+    if limits.keys_per_hour > 0 {
+        let recent = store.list_auth_keys_since(user_id, now - Duration::seconds(KEY_RATE_WINDOW_SECONDS)).await?;
+        if let Some(count) = exceeded(recent.len(), limits.keys_per_hour) {
+            // Oldest first, so this is the key whose ageing out brings the user back under the limit
+            let frees_at = recent[count].created_at + Duration::seconds(KEY_RATE_WINDOW_SECONDS);
+            return Err(QuotaError::Exceeded(QuotaExceeded {
+                limit: "keys_per_hour",
+                message: format!("You can generate at most {} auth keys per hour", limits.keys_per_hour),
+                retry_after: Some(seconds_until(frees_at, now)),
+            }));
+        }
+    }
+
+    if limits.live_keys > 0 {
+        let mut live = store.list_live_auth_keys(user_id).await?;
+        if let Some(count) = exceeded(live.len(), limits.live_keys) {
+            live.sort_by_key(|key| key.expires_at);
+            return Err(QuotaError::Exceeded(QuotaExceeded {
+                limit: "live_keys",
+                message: format!(
+                    "You can hold at most {} unexpired auth keys; revoke one or wait for one to expire",
+                    limits.live_keys
+                ),
+                retry_after: Some(seconds_until(live[count].expires_at, now)),
+            }));
+        }
+    }
+
+    if limits.devices > 0 {
+        let enrolled = devices::list_user_devices(store, user_id).await?.devices;
+        if exceeded(enrolled.len(), limits.devices).is_some() {
+            return Err(QuotaError::Exceeded(QuotaExceeded {
+                limit: "devices",
+                message: format!(
+                    "You can have at most {} devices on the network; remove one before adding another",
+                    limits.devices
+                ),
+                retry_after: None,
+            }));
+        }
+    }
+
+    Ok(())
+}
+
+/// How many items over the limit a count is, counting reaching it as 0 over
+fn exceeded(count: usize, limit: u32) -> Option<usize> {
+    count.checked_sub(limit as usize)
+}
+
+fn seconds_until(time: DateTime<Utc>, now: DateTime<Utc>) -> u64 {
+    (time - now).num_seconds().max(1) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+    use crate::memory_store::MemoryStore;
+    use crate::models::{AuthKey, DeviceInfo};
+    use crate::store::{AuthKeyStore, QuotaStore};
+
+    async fn store_with_one_live_key_allowed(user_id: &str) -> MemoryStore {
+        init_test_config();
+        let store = MemoryStore::new();
+        store.set_user_quota(&UserQuota {
+            user_id: user_id.to_string(),
+            keys_per_hour: None,
+            live_keys: Some(1),
+            devices: None,
+            updated_at: Utc::now(),
+        }).await.unwrap();
+        store
+    }
+
+    fn key(id: &str, user_id: &str) -> AuthKey {
+        let now = Utc::now();
+        AuthKey {
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            tags: "tag:user".to_string(),
+            created_at: now,
+            expires_at: now + Duration::hours(1),
+            request_ip: None,
+            revoked_at: None,
+            api_token_id: None,
+            profile: None,
+            reusable: Some(true),
+            device: DeviceInfo::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn reservation_holds_off_other_requests_until_the_key_is_recorded() {
+        let store = store_with_one_live_key_allowed("alice").await;
+
+        let reservation = reserve(&store, "alice").await.unwrap();
+        let waiting = tokio::time::timeout(std::time::Duration::from_millis(50), reserve(&store, "alice")).await;
+        assert!(waiting.is_err(), "a second reservation must wait for the first");
+
+        store.insert_auth_key(&key("k1", "alice")).await.unwrap();
+        drop(reservation);
+
+        match reserve(&store, "alice").await {
+            Err(QuotaError::Exceeded(exceeded)) => assert_eq!(exceeded.limit, "live_keys"),
+            _ => panic!("the recorded key must count against the next reservation"),
+        }
+    }
+
+    #[tokio::test]
+    async fn reservations_of_different_users_do_not_wait_for_each_other() {
+        let store = store_with_one_live_key_allowed("alice").await;
+
+        let _alice = reserve(&store, "alice").await.unwrap();
+        let bob = tokio::time::timeout(std::time::Duration::from_millis(50), reserve(&store, "bob")).await;
+
+        assert!(matches!(bob, Ok(Ok(_))));
+    }
+}
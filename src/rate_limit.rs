@@ -0,0 +1,336 @@
+use anyhow::{Result, anyhow};
+use axum::extract::{ConnectInfo, MatchedPath, Request};
+use axum::http::{HeaderValue, StatusCode, header::RETRY_AFTER};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Json, Response};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+use crate::config::{get_config, RateLimitConfig};
+use crate::models::RateLimitedResponse;
+use crate::proxy;
+
+/// How often buckets that have refilled completely are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Share of the buckets dropped at once when all are in use, as one in this many
+const EVICT_FRACTION: usize = 10;
+
+/// Prefix length IPv6 clients are grouped by, the size of a single subnet
+const IPV6_CLIENT_PREFIX: u32 = 64;
+
+/// How many requests a client may make to a route, and how fast that refills
+#[derive(Debug, Clone, Copy)]
+struct Budget {
+    burst: f64,
+    per_second: f64,
+}
+
+/// A client's remaining requests on one route
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When the client last made a request, unlike `updated` which pruning also moves
+    seen: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, budget: Budget, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * budget.per_second).min(budget.burst);
+        self.updated = now;
+    }
+}
+
+/// Token buckets for every client address on every route
+struct RateLimiter {
+    default: Budget,
+    routes: HashMap<String, Budget>,
+    max_clients: usize,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    by_client: HashMap<(String, IpAddr), Bucket>,
+    pruned: Instant,
+}
+
+impl RateLimiter {
+    fn budget(&self, route: &str) -> Budget {
+        self.routes.get(route).copied().unwrap_or(self.default)
+    }
+
+    /// Take a request from a client's bucket, or say how many seconds until one is available
+    ///
+    /// IPv6 clients share a bucket with their /64, which a single host can
+    /// usually pick addresses from at will.
+    fn acquire(&self, route: &str, client: IpAddr, now: Instant) -> Result<(), u64> {
+        let budget = self.budget(route);
+        let client = client_key(client);
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let key = (route.to_string(), client);
+
+        if now.duration_since(buckets.pruned) >= PRUNE_INTERVAL {
+            self.prune(&mut buckets, now);
+        }
+
+        if !buckets.by_client.contains_key(&key) && buckets.by_client.len() >= self.max_clients {
+            self.prune(&mut buckets, now);
+            if buckets.by_client.len() >= self.max_clients {
+                let evicted = evict_least_recent(&mut buckets.by_client, self.max_clients / EVICT_FRACTION);
+                warn!("Rate limiter is tracking {} clients, forgot the {} least recently seen", self.max_clients, evicted);
+            }
+        }
+
+        let bucket = buckets.by_client.entry(key).or_insert(Bucket {
+            tokens: budget.burst,
+            updated: now,
+            seen: now,
+        });
+        bucket.refill(budget, now);
+        bucket.seen = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / budget.per_second).ceil().max(1.0) as u64)
+        }
+    }
+
+    /// Drop the buckets that have refilled completely, as a new one would be the same
+    fn prune(&self, buckets: &mut Buckets, now: Instant) {
+        buckets.by_client.retain(|(route, _), bucket| {
+            let budget = self.budget(route);
+            bucket.refill(budget, now);
+            bucket.tokens < budget.burst
+        });
+        buckets.pruned = now;
+    }
+}
+
+/// Drop the buckets that were used longest ago, at least one, returning how many were dropped
+///
+/// Evicting in batches keeps the cost of a full map to one scan per batch.
+/// Clients sending requests keep their buckets fresh, so a flood of new
+/// addresses mostly pushes out the flood's own earlier buckets and idle clients.
+fn evict_least_recent(by_client: &mut HashMap<(String, IpAddr), Bucket>, count: usize) -> usize {
+    let count = count.clamp(1, by_client.len());
+    let mut seen = by_client.values().map(|bucket| bucket.seen).collect::<Vec<_>>();
+    let (_, cutoff, _) = seen.select_nth_unstable(count - 1);
+    let cutoff = *cutoff;
+
+    let before = by_client.len();
+    by_client.retain(|_, bucket| bucket.seen > cutoff);
+    before - by_client.len()
+}
+
+/// The address a client's requests are counted against
+fn client_key(client: IpAddr) -> IpAddr {
+    match client {
+        IpAddr::V4(_) => client,
+        IpAddr::V6(address) => {
+            let mask = u128::MAX << (128 - IPV6_CLIENT_PREFIX);
+            IpAddr::V6(Ipv6Addr::from(u128::from(address) & mask))
+        }
+    }
+}
+
+fn budget(route: &str, burst: u32, per_minute: u32) -> Result<Budget> {
+    if burst == 0 || per_minute == 0 {
+        return Err(anyhow!("Rate limit of {} needs a positive burst and per_minute", route));
+    }
+
+    Ok(Budget {
+        burst: burst as f64,
+        per_second: per_minute as f64 / 60.0,
+    })
+}
+
+/// Build the rate limiter, checking every budgeted route is one of the served paths
+fn build_rate_limiter(config: &RateLimitConfig, paths: &[&str]) -> Result<RateLimiter> {
+    let default = budget("every route", config.burst, config.per_minute)?;
+    if config.max_clients == 0 {
+        return Err(anyhow!("max_clients must be positive"));
+    }
+
+    let routes = config.routes.iter()
+        .map(|(route, limit)| {
+            if !paths.contains(&route.as_str()) {
+                return Err(anyhow!(
+                    "Rate limited route '{}' is not served; use a path such as \"/auth/validate\", with :name for each parameter",
+                    route
+                ));
+            }
+            let budget = budget(
+                route,
+                limit.burst.unwrap_or(config.burst),
+                limit.per_minute.unwrap_or(config.per_minute),
+            )?;
+            Ok((route.clone(), budget))
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(RateLimiter {
+        default,
+        routes,
+        max_clients: config.max_clients,
+        buckets: Mutex::new(Buckets {
+            by_client: HashMap::new(),
+            pruned: Instant::now(),
+        }),
+    })
+}
+
+// Rate limiter, built once at startup from configuration
+static RATE_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+fn rate_limiter() -> &'static RateLimiter {
+    RATE_LIMITER.get().expect("rate limits are loaded at startup")
+}
+
+/// Build the rate limiter for the paths the server serves
+///
+/// Called at startup, before the first request, so a bad budget or a budget
+/// for a path that is not served stops the server. Returns the number of
+/// routes with their own budget.
+pub fn load_rate_limits(paths: &[&str]) -> Result<usize> {
+    let limiter = build_rate_limiter(&get_config().server.rate_limit, paths)?;
+    let routes = limiter.routes.len();
+    // Startup runs once, so the limiter is never already set
+    let _ = RATE_LIMITER.set(limiter);
+    Ok(routes)
+}
+
+/// Middleware refusing requests once their client has spent the route's budget
+///
+/// Each route has its own buckets, so exhausting one does not block the
+/// others. Refused requests get 429 with a Retry-After header.
+pub async fn limit(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    route: MatchedPath,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client = proxy::client_ip(request.headers(), peer.ip());
+    match rate_limiter().acquire(route.as_str(), client, Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            debug!("Rate limited {} on {}", client, route.as_str());
+            let mut response = (StatusCode::TOO_MANY_REQUESTS, Json(RateLimitedResponse {
+                success: false,
+                message: format!("Too many requests; try again in {} seconds", retry_after),
+                reason: "rate_limited",
+                retry_after,
+            })).into_response();
+            response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after));
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATHS: &[&str] = &["/a", "/b", "/auth/validate"];
+
+    fn limiter(burst: u32, per_minute: u32, max_clients: usize) -> RateLimiter {
+        build_rate_limiter(&RateLimitConfig {
+            burst,
+            per_minute,
+            max_clients,
+            ..RateLimitConfig::default()
+        }, PATHS).unwrap()
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn burst_is_spent_then_refilled_over_time() {
+        let limiter = limiter(2, 60, 100);
+        let start = Instant::now();
+
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.1"), start), Ok(()));
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.1"), start), Ok(()));
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.1"), start), Err(1));
+
+        // One request a second comes back, and never more than the burst
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.1"), start + Duration::from_secs(1)), Ok(()));
+        assert!(limiter.acquire("/a", ip("192.0.2.1"), start + Duration::from_secs(1)).is_err());
+        let later = start + Duration::from_secs(600);
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.1"), later), Ok(()));
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.1"), later), Ok(()));
+        assert!(limiter.acquire("/a", ip("192.0.2.1"), later).is_err());
+    }
+
+    #[test]
+    fn retry_after_counts_the_seconds_until_a_request_refills() {
+        let limiter = limiter(1, 6, 100);
+        let start = Instant::now();
+
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.1"), start), Ok(()));
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.1"), start), Err(10));
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.1"), start + Duration::from_secs(4)), Err(6));
+    }
+
+    #[test]
+    fn routes_and_ipv4_clients_have_their_own_buckets() {
+        let limiter = limiter(1, 1, 100);
+        let now = Instant::now();
+
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.1"), now), Ok(()));
+        assert_eq!(limiter.acquire("/b", ip("192.0.2.1"), now), Ok(()));
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.2"), now), Ok(()));
+        assert!(limiter.acquire("/a", ip("192.0.2.1"), now).is_err());
+    }
+
+    #[test]
+    fn ipv6_clients_share_a_bucket_with_their_64() {
+        let limiter = limiter(1, 1, 100);
+        let now = Instant::now();
+
+        assert_eq!(limiter.acquire("/a", ip("2001:db8:1:2::1"), now), Ok(()));
+        assert!(limiter.acquire("/a", ip("2001:db8:1:2:ffff:ffff:ffff:ffff"), now).is_err());
+        assert_eq!(limiter.acquire("/a", ip("2001:db8:1:3::1"), now), Ok(()));
+    }
+
+    #[test]
+    fn least_recently_seen_clients_make_room_for_new_ones() {
+        let limiter = limiter(2, 60, 2);
+        let start = Instant::now();
+
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.1"), start), Ok(()));
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.2"), start + Duration::from_millis(10)), Ok(()));
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.2"), start + Duration::from_millis(20)), Ok(()));
+        // Every bucket is partly spent, so the oldest goes and the new client is served
+        assert_eq!(limiter.acquire("/a", ip("192.0.2.3"), start + Duration::from_millis(30)), Ok(()));
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_client.len(), 2);
+        assert!(!buckets.by_client.contains_key(&("/a".to_string(), ip("192.0.2.1"))));
+        // The busy client kept its spent budget
+        assert!(buckets.by_client[&("/a".to_string(), ip("192.0.2.2"))].tokens < 1.0);
+    }
+
+    #[test]
+    fn empty_budgets_are_refused() {
+        assert!(build_rate_limiter(&RateLimitConfig { burst: 0, ..RateLimitConfig::default() }, PATHS).is_err());
+        assert!(build_rate_limiter(&RateLimitConfig { max_clients: 0, ..RateLimitConfig::default() }, PATHS).is_err());
+    }
+
+    #[test]
+    fn budgets_for_paths_not_served_are_refused() {
+        let config = |route: &str| -> RateLimitConfig {
+            serde_json::from_value(serde_json::json!({ "routes": { route: { "burst": 5 } } })).unwrap()
+        };
+
+        assert_eq!(build_rate_limiter(&config("/auth/validate"), PATHS).unwrap().budget("/auth/validate").burst, 5.0);
+        assert!(build_rate_limiter(&config("/auth/validat"), PATHS).is_err());
+        assert!(build_rate_limiter(&config("auth/validate"), PATHS).is_err());
+    }
+}
@@ -0,0 +1,369 @@
+use anyhow::{Result, anyhow};
+use axum::http::{HeaderMap, HeaderValue, header::{COOKIE, SET_COOKIE}};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde_json::json;
+use std::fmt;
+use std::sync::OnceLock;
+use tracing::warn;
+use crate::config::get_config;
+use crate::models::{
+    User, Session, SessionClaims, SessionTokenKind, SessionTokens,
+    AuditContext, AuditEvent, AuditAction, AuditOutcome,
+};
+use crate::{audit, db, oidc};
+use crate::store::Store;
+
+/// Issuer of the tokens we sign, which tells them apart from ID tokens
+pub const SESSION_ISSUER: &str = "low-access-api";
+
+/// Cookie carrying the session token
+pub const SESSION_COOKIE: &str = "low_access_session";
+
+/// Cookie carrying the refresh token, only sent to the /auth endpoints
+pub const REFRESH_COOKIE: &str = "low_access_refresh";
+
+/// How long a session may go unused before its last-seen time is updated again
+const TOUCH_INTERVAL_SECONDS: i64 = 60;
+
+/// Shortest secret accepted for signing, the output size of HS256
+const MIN_SECRET_LENGTH: usize = 32;
+
+// Secret signing session tokens, read once from configuration
+static SECRET: OnceLock<Vec<u8>> = OnceLock::new();
+
+fn secret() -> &'static [u8] {
+    SECRET.get_or_init(|| match &get_config().session.secret_path {
+        Some(path) => read_secret(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        // Replicas sharing a PostgreSQL database must accept each other's sessions
+        None if db::is_postgres().unwrap_or(false) => {
+            eprintln!("Set [session] secret_path when using PostgreSQL, so every replica signs sessions with the same secret");
+            std::process::exit(1);
+        }
+        None => {
+            warn!("No [session] secret_path set; sessions will end when the server restarts");
+            rand::random::<[u8; 32]>().to_vec()
+        }
+    })
+}
+
+/// Read the signing secret from a file, refusing one short enough to guess
+fn read_secret(path: &str) -> Result<Vec<u8>> {
+    let secret = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read session secret {}: {}", path, e))?;
+    let secret = secret.trim();
+
+    if secret.len() < MIN_SECRET_LENGTH {
+        return Err(anyhow!(
+            "Session secret {} is {} bytes; it must be at least {}, e.g. from `openssl rand -base64 32`",
+            path, secret.len(), MIN_SECRET_LENGTH
+        ));
+    }
+
+    Ok(secret.as_bytes().to_vec())
+}
+
+/// Load the signing secret, so a missing or short secret file stops the server at startup
+pub fn load_secret() {
+    secret();
+}
+
+/// Check whether a token claims to be one of ours rather than an ID token
+///
+/// Only good for choosing how to verify the token.
+pub fn is_session_token(token: &str) -> bool {
+    oidc::unverified_issuer(token).is_ok_and(|issuer| issuer == SESSION_ISSUER)
+}
+
+/// A token that verified but whose session was ended on the server
+#[derive(Debug)]
+pub struct SessionEnded;
+
+impl fmt::Display for SessionEnded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Session has been ended")
+    }
+}
+
+impl std::error::Error for SessionEnded {}
+
+/// A refresh token that was already exchanged, presented again
+///
+/// Only one of the two holders can be the user, so the session is ended.
+#[derive(Debug)]
+pub struct RefreshTokenReused {
+    pub session_id: String,
+}
+
+impl fmt::Display for RefreshTokenReused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Refresh token of session {} was already used; the session has been ended", self.session_id)
+    }
+}
+
+impl std::error::Error for RefreshTokenReused {}
+
+/// Start a session for a user who has just signed in, recording it in the sessions table
+pub async fn issue(store: &dyn Store, user: &User, context: &AuditContext) -> Result<SessionTokens> {
+    let now = Utc::now();
+    let refresh_expires_at = now.timestamp() + get_config().session.refresh_ttl_seconds as i64;
+    let session = Session {
+        id: format!("{:032x}", rand::random::<u128>()),
+        user_id: user.id.clone(),
+        created_at: now,
+        last_seen: now,
+        expires_at: timestamp(refresh_expires_at)?,
+        ip: context.ip.clone(),
+        user_agent: context.user_agent.clone(),
+        ended_at: None,
+        refresh_generation: 0,
+    };
+
+    store.insert_session(&session).await?;
+    reissue(user, &session.id, refresh_expires_at, session.refresh_generation)
+}
+
+/// Exchange a refresh token for new tokens, rotating the refresh token
+///
+/// The session moves on to its next refresh generation, so the refresh token
+/// presented stops working. Presenting it again fails with
+/// `RefreshTokenReused` and ends the session, as the token has been copied.
+pub async fn refresh(store: &dyn Store, user: &User, claims: &SessionClaims) -> Result<SessionTokens> {
+    if !store.advance_refresh_generation(&claims.sid, claims.generation).await? {
+        // Either the session ended just now, or this token was already exchanged
+        if store.end_session(&claims.sid).await? {
+            return Err(RefreshTokenReused { session_id: claims.sid.clone() }.into());
+        }
+        return Err(SessionEnded.into());
+    }
+
+    reissue(user, &claims.sid, claims.exp, claims.generation + 1)
+}
+
+/// Issue a new session token together with a refresh token of the given generation
+///
+/// The refresh token keeps the given expiry, so refreshing never extends a
+/// session beyond the lifetime of the sign-in that started it.
+fn reissue(user: &User, session_id: &str, refresh_expires_at: i64, generation: i64) -> Result<SessionTokens> {
+    let now = Utc::now().timestamp();
+    let expires_at = (now + get_config().session.ttl_seconds as i64).min(refresh_expires_at);
+
+    Ok(SessionTokens {
+        token: sign(user, session_id, SessionTokenKind::Session, now, expires_at, generation)?,
+        expires_at: timestamp(expires_at)?,
+        refresh_token: sign(user, session_id, SessionTokenKind::Refresh, now, refresh_expires_at, generation)?,
+        refresh_expires_at: timestamp(refresh_expires_at)?,
+    })
+}
+
+fn sign(user: &User, session_id: &str, kind: SessionTokenKind, iat: i64, exp: i64, generation: i64) -> Result<String> {
+    let claims = SessionClaims {
+        iss: SESSION_ISSUER.to_string(),
+        sub: user.id.clone(),
+        sid: session_id.to_string(),
+        email: user.email.clone(),
+        typ: kind,
+        iat,
+        exp,
+        generation,
+    };
+
+    Ok(jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(secret()))?)
+}
+
+fn timestamp(seconds: i64) -> Result<DateTime<Utc>> {
+    DateTime::from_timestamp(seconds, 0).ok_or_else(|| anyhow!("Session expiry {} is out of range", seconds))
+}
+
+/// Verify a token we issued and check it is of the expected kind
+pub fn verify(token: &str, kind: SessionTokenKind) -> Result<SessionClaims> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[SESSION_ISSUER]);
+    validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+
+    let claims = jsonwebtoken::decode::<SessionClaims>(token, &DecodingKey::from_secret(secret()), &validation)?.claims;
+    if claims.typ != kind {
+        return Err(anyhow!("Token is not a {} token", match kind {
+            SessionTokenKind::Session => "session",
+            SessionTokenKind::Refresh => "refresh",
+        }));
+    }
+
+    Ok(claims)
+}
+
+/// Verify a token we issued and check its session is still live on the server
+///
+/// Updates the session's last-seen time, at most once a minute.
+pub async fn authenticate(store: &dyn Store, token: &str, kind: SessionTokenKind) -> Result<SessionClaims> {
+    let claims = verify(token, kind)?;
+
+    let session = store.find_session(&claims.sid).await?
+        .filter(|session| session.user_id == claims.sub && session.ended_at.is_none())
+        .ok_or(SessionEnded)?;
+
+    if (Utc::now() - session.last_seen).num_seconds() >= TOUCH_INTERVAL_SECONDS
+        && let Err(e) = store.touch_session(&session.id).await
+    {
+        warn!("Failed to update last use of session {}: {}", session.id, e);
+    }
+
+    Ok(claims)
+}
+
+/// End every live session of a user, recording one audit event for them all
+pub async fn end_user_sessions(store: &dyn Store, user_id: &str, context: &AuditContext) -> Result<Vec<String>> {
+    let ended = store.end_user_sessions(user_id).await?;
+
+    if !ended.is_empty() {
+        audit::record(store, AuditEvent {
+            subject_user_id: Some(user_id.to_string()),
+            details: json!({ "sessions": ended }),
+            ..context.event(AuditAction::EndSessions, AuditOutcome::Success)
+        }).await;
+    }
+
+    Ok(ended)
+}
+
+/// Whether a token was rejected only because it expired
+pub fn is_expired(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<jsonwebtoken::errors::Error>().map(|e| e.kind()),
+        Some(jsonwebtoken::errors::ErrorKind::ExpiredSignature)
+    )
+}
+
+/// Set-Cookie headers handing a session to a browser
+pub fn cookies(tokens: &SessionTokens) -> HeaderMap {
+    let config = &get_config().session;
+    let now = Utc::now();
+    let mut headers = HeaderMap::new();
+
+    for (name, value, path, expires_at) in [
+        (SESSION_COOKIE, tokens.token.as_str(), "/", tokens.expires_at),
+        (REFRESH_COOKIE, tokens.refresh_token.as_str(), "/auth", tokens.refresh_expires_at),
+    ] {
+        let max_age = (expires_at - now).num_seconds().max(0);
+        headers.append(SET_COOKIE, cookie_header(name, value, path, max_age, config.cookie_secure));
+    }
+
+    headers
+}
+
+/// Set-Cookie headers removing a session from a browser
+pub fn clear_cookies() -> HeaderMap {
+    let secure = get_config().session.cookie_secure;
+    let mut headers = HeaderMap::new();
+    headers.append(SET_COOKIE, cookie_header(SESSION_COOKIE, "", "/", 0, secure));
+    headers.append(SET_COOKIE, cookie_header(REFRESH_COOKIE, "", "/auth", 0, secure));
+    headers
+}
+
+fn cookie_header(name: &str, value: &str, path: &str, max_age: i64, secure: bool) -> HeaderValue {
+    let secure = if secure { "; Secure" } else { "" };
+    // Tokens are base64url and dots only, so the header is always valid
+    HeaderValue::from_str(&format!("{}={}; Path={}; Max-Age={}; HttpOnly; SameSite=Strict{}", name, value, path, max_age, secure))
+        .expect("cookie header is valid")
+}
+
+/// Read a cookie sent with a request
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+    use crate::memory_store::MemoryStore;
+    use crate::models::UserStatus;
+    use crate::store::SessionStore;
+
+    fn user() -> User {
+        let now = Utc::now();
+        User {
+            id: "user".to_string(),
+            email: "alice@example.com".to_string(),
+            name: "Alice".to_string(),
+            status: UserStatus::Approved,
+            created_at: now,
+            last_login: now,
+            status_reason: None,
+            status_updated_at: None,
+        }
+    }
+
+    async fn exchange(store: &MemoryStore, refresh_token: &str) -> Result<SessionTokens> {
+        let claims = authenticate(store, refresh_token, SessionTokenKind::Refresh).await?;
+        refresh(store, &user(), &claims).await
+    }
+
+    #[tokio::test]
+    async fn refresh_rotates_the_refresh_token() {
+        init_test_config();
+        let store = MemoryStore::new();
+        let first = issue(&store, &user(), &AuditContext::default()).await.unwrap();
+
+        let second = exchange(&store, &first.refresh_token).await.unwrap();
+        let third = exchange(&store, &second.refresh_token).await.unwrap();
+
+        assert_eq!(verify(&third.refresh_token, SessionTokenKind::Refresh).unwrap().generation, 2);
+        assert_eq!(third.refresh_expires_at, first.refresh_expires_at);
+    }
+
+    #[tokio::test]
+    async fn reused_refresh_token_ends_the_session() {
+        init_test_config();
+        let store = MemoryStore::new();
+        let first = issue(&store, &user(), &AuditContext::default()).await.unwrap();
+        let second = exchange(&store, &first.refresh_token).await.unwrap();
+        let session_id = verify(&first.refresh_token, SessionTokenKind::Refresh).unwrap().sid;
+
+        let reused = exchange(&store, &first.refresh_token).await.unwrap_err();
+
+        assert!(reused.is::<RefreshTokenReused>());
+        assert!(store.find_session(&session_id).await.unwrap().unwrap().ended_at.is_some());
+        // The holder of the newer tokens is signed out too, as either could be the thief
+        assert!(exchange(&store, &second.refresh_token).await.unwrap_err().is::<SessionEnded>());
+        assert!(authenticate(&store, &second.token, SessionTokenKind::Session).await.unwrap_err().is::<SessionEnded>());
+    }
+
+    #[test]
+    fn short_secrets_are_refused() {
+        let path = std::env::temp_dir().join(format!("low-access-secret-{}", rand::random::<u64>()));
+        let path = path.to_str().unwrap();
+
+        std::fs::write(path, "short\n").unwrap();
+        let short = read_secret(path);
+        std::fs::write(path, "  \n").unwrap();
+        let empty = read_secret(path);
+        std::fs::write(path, format!("{}\n", "s".repeat(MIN_SECRET_LENGTH))).unwrap();
+        let long_enough = read_secret(path);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(short.is_err());
+        assert!(empty.is_err());
+        assert_eq!(long_enough.unwrap().len(), MIN_SECRET_LENGTH);
+        assert!(read_secret(path).is_err());
+    }
+
+        #[tokio::test]
+    async fn session_token_cannot_be_used_to_refresh() {
+        init_test_config();
+        let store = MemoryStore::new();
+        let tokens = issue(&store, &user(), &AuditContext::default()).await.unwrap();
+
+        assert!(exchange(&store, &tokens.token).await.is_err());
+    }
+}
@@ -0,0 +1,22 @@
+use sqlx::AnyPool;
+use std::sync::Arc;
+use crate::store::{SqlStore, Store};
+
+/// Shared state handed to every request handler
+#[derive(Clone)]
+pub struct AppState {
+    /// Users, their sessions, auth keys and everything else handlers record
+    pub store: Arc<dyn Store>,
+}
+
+impl AppState {
+    /// Build the state for a database, storing everything in it
+    pub fn new(pool: AnyPool) -> Self {
+        Self::with_store(Arc::new(SqlStore::new(pool)))
+    }
+
+    /// Build the state around any store, such as `MemoryStore` in tests
+    pub fn with_store(store: Arc<dyn Store>) -> Self {
+        AppState { store }
+    }
+}
@@ -0,0 +1,344 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::AnyPool;
+use crate::db;
+use chrono::{DateTime, Utc};
+use crate::models::{
+    User, UserStatus, UserPermission, Identity, VerifiedIdentity, AuthKey, Session, ApiToken, UserQuota,
+    AuditEvent, AuditQuery,
+};
+
+/// Storage for users, their linked identities and their permissions
+///
+/// Handlers go through this trait rather than the database so their logic can
+/// run against `MemoryStore` as well as the real database.
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    async fn find_user_by_id(&self, id: &str) -> Result<Option<User>>;
+
+    async fn find_user_by_email(&self, email: &str) -> Result<Option<User>>;
+
+    /// Find the user an identity provider account is linked to
+    async fn find_user_by_identity(&self, issuer: &str, subject: &str) -> Result<Option<User>>;
+
+    /// Store a new user and link the identity they signed in with
+    async fn create_user(&self, user: &User, identity: &VerifiedIdentity) -> Result<()>;
+
+    /// Record a sign-in of an existing user, linking the identity if it is new
+    ///
+    /// Updates the user's email, name and last login; never their status.
+    async fn record_login(&self, user: &User, identity: &VerifiedIdentity) -> Result<()>;
+
+    /// Link an identity provider account to a user without signing them in
+    ///
+    /// An identity already linked stays with the user it was first linked to.
+    async fn link_identity(&self, user_id: &str, identity: &VerifiedIdentity) -> Result<()>;
+
+    /// List users, optionally restricted to a single status, oldest first
+    async fn list_users(&self, status: Option<UserStatus>) -> Result<Vec<User>>;
+
+    /// Change a user's status, failing with `IllegalTransition` if the state machine forbids it
+    ///
+    /// Returns the updated user, or `None` if no user has the given id.
+    async fn set_user_status(&self, id: &str, status: UserStatus, reason: Option<&str>) -> Result<Option<User>>;
+
+    /// Delete a user together with their permissions, identities, sessions, API tokens and quota
+    ///
+    /// Returns false if no user has the given id.
+    async fn delete_user(&self, id: &str) -> Result<bool>;
+
+    /// List the identity provider accounts linked to a user, oldest first
+    async fn list_identities(&self, user_id: &str) -> Result<Vec<Identity>>;
+
+    /// List a user's permissions, in the order they were granted
+    async fn list_permissions(&self, user_id: &str) -> Result<Vec<UserPermission>>;
+
+    /// Grant a permission, returning false if the user already held it
+    async fn grant_permission(&self, user_id: &str, permission: &str) -> Result<bool>;
+
+    /// Revoke a permission, returning false if the user did not hold it
+    async fn revoke_permission(&self, user_id: &str, permission: &str) -> Result<bool>;
+}
+
+/// Storage for the auth keys issued to users
+#[async_trait]
+pub trait AuthKeyStore: Send + Sync {
+    async fn insert_auth_key(&self, key: &AuthKey) -> Result<()>;
+
+    /// List every recorded auth key, oldest first
+    async fn list_auth_keys(&self) -> Result<Vec<AuthKey>>;
+
+    /// List a user's auth keys that have neither expired nor been revoked, oldest first
+    async fn list_live_auth_keys(&self, user_id: &str) -> Result<Vec<AuthKey>>;
+
+    /// List the auth keys issued to a user at or after a time, oldest first
+    async fn list_auth_keys_since(&self, user_id: &str, since: DateTime<Utc>) -> Result<Vec<AuthKey>>;
+
+    async fn mark_auth_key_revoked(&self, id: &str) -> Result<()>;
+}
+
+/// Storage for the sessions started by sign-ins
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn insert_session(&self, session: &Session) -> Result<()>;
+
+    async fn find_session(&self, id: &str) -> Result<Option<Session>>;
+
+    /// Record that a session was just used
+    async fn touch_session(&self, id: &str) -> Result<()>;
+
+    /// Move a live session from the given refresh generation to the next
+    ///
+    /// Returns false, changing nothing, if the session has ended or is at
+    /// another generation.
+    async fn advance_refresh_generation(&self, id: &str, generation: i64) -> Result<bool>;
+
+    /// List a user's sessions that have neither expired nor ended, oldest first
+    async fn list_live_sessions(&self, user_id: &str) -> Result<Vec<Session>>;
+
+    /// End a session, returning false if it had already ended
+    async fn end_session(&self, id: &str) -> Result<bool>;
+
+    /// End every live session of a user, returning the ids of the sessions ended
+    async fn end_user_sessions(&self, user_id: &str) -> Result<Vec<String>>;
+}
+
+/// Storage for personal API tokens, kept by the hash of their secret
+#[async_trait]
+pub trait ApiTokenStore: Send + Sync {
+    async fn insert_api_token(&self, token: &ApiToken, token_hash: &str) -> Result<()>;
+
+    async fn find_api_token(&self, id: &str) -> Result<Option<ApiToken>>;
+
+    async fn find_api_token_by_hash(&self, token_hash: &str) -> Result<Option<ApiToken>>;
+
+    /// List a user's API tokens that have not been revoked, expired ones included, oldest first
+    async fn list_api_tokens(&self, user_id: &str) -> Result<Vec<ApiToken>>;
+
+    /// Record that an API token was just used
+    async fn touch_api_token(&self, id: &str) -> Result<()>;
+
+    /// Revoke an API token, returning false if it was already revoked
+    async fn revoke_api_token(&self, id: &str) -> Result<bool>;
+}
+
+/// Storage for the quota overrides administrators set for users
+#[async_trait]
+pub trait QuotaStore: Send + Sync {
+    async fn find_user_quota(&self, user_id: &str) -> Result<Option<UserQuota>>;
+
+    /// Store a user's quota overrides, replacing any they had
+    async fn set_user_quota(&self, quota: &UserQuota) -> Result<()>;
+
+    /// Remove a user's quota overrides, returning false if they had none
+    async fn clear_user_quota(&self, user_id: &str) -> Result<bool>;
+
+    /// Keep other servers sharing the store from checking a user's quota until the lock is dropped
+    async fn lock_user_quota(&self, user_id: &str) -> Result<QuotaLock>;
+}
+
+/// A lock on one user's quota, released when dropped
+pub type QuotaLock = Box<dyn Send>;
+
+/// Storage for the hash-chained audit log
+#[async_trait]
+pub trait AuditStore: Send + Sync {
+    /// Append an event, chained to the last event recorded
+    async fn insert_audit_event(&self, event: &AuditEvent) -> Result<()>;
+
+    /// List events in chain order, starting after the given id
+    async fn list_audit_chain(&self, after_id: i64, limit: u32) -> Result<Vec<AuditEvent>>;
+
+    /// List events matching a query, newest first
+    async fn list_audit_events(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>>;
+}
+
+/// Everything the server stores
+///
+/// Implemented by every type that implements all of the store traits.
+pub trait Store: UserStore + AuthKeyStore + SessionStore + ApiTokenStore + QuotaStore + AuditStore {}
+
+impl<T> Store for T where T: UserStore + AuthKeyStore + SessionStore + ApiTokenStore + QuotaStore + AuditStore {}
+
+/// Store backed by the configured SQL database
+pub struct SqlStore {
+    pool: AnyPool,
+}
+
+impl SqlStore {
+    pub fn new(pool: AnyPool) -> Self {
+        SqlStore { pool }
+    }
+}
+
+#[async_trait]
+impl UserStore for SqlStore {
+    async fn find_user_by_id(&self, id: &str) -> Result<Option<User>> {
+        db::find_user_by_id(&self.pool, id).await
+    }
+
+    async fn find_user_by_email(&self, email: &str) -> Result<Option<User>> {
+        db::find_user_by_email(&self.pool, email).await
+    }
+
+    async fn find_user_by_identity(&self, issuer: &str, subject: &str) -> Result<Option<User>> {
+        db::find_user_by_identity(&self.pool, issuer, subject).await
+    }
+
+    async fn create_user(&self, user: &User, identity: &VerifiedIdentity) -> Result<()> {
+        db::upsert_user(&self.pool, user).await?;
+        db::upsert_identity(&self.pool, &user.id, identity).await
+    }
+
+    async fn record_login(&self, user: &User, identity: &VerifiedIdentity) -> Result<()> {
+        db::upsert_user(&self.pool, user).await?;
+        db::upsert_identity(&self.pool, &user.id, identity).await
+    }
+
+    async fn link_identity(&self, user_id: &str, identity: &VerifiedIdentity) -> Result<()> {
+        db::upsert_identity(&self.pool, user_id, identity).await
+    }
+
+    async fn list_users(&self, status: Option<UserStatus>) -> Result<Vec<User>> {
+        db::list_users(&self.pool, status).await
+    }
+
+    async fn set_user_status(&self, id: &str, status: UserStatus, reason: Option<&str>) -> Result<Option<User>> {
+        db::set_user_status(&self.pool, id, status, reason).await
+    }
+
+    async fn delete_user(&self, id: &str) -> Result<bool> {
+        db::delete_user(&self.pool, id).await
+    }
+
+    async fn list_identities(&self, user_id: &str) -> Result<Vec<Identity>> {
+        db::list_identities(&self.pool, user_id).await
+    }
+
+    async fn list_permissions(&self, user_id: &str) -> Result<Vec<UserPermission>> {
+        db::list_permissions(&self.pool, user_id).await
+    }
+
+    async fn grant_permission(&self, user_id: &str, permission: &str) -> Result<bool> {
+        db::grant_permission(&self.pool, user_id, permission).await
+    }
+
+    async fn revoke_permission(&self, user_id: &str, permission: &str) -> Result<bool> {
+        db::revoke_permission(&self.pool, user_id, permission).await
+    }
+}
+
+#[async_trait]
+impl AuthKeyStore for SqlStore {
+    async fn insert_auth_key(&self, key: &AuthKey) -> Result<()> {
+        db::insert_auth_key(&self.pool, key).await
+    }
+
+    async fn list_auth_keys(&self) -> Result<Vec<AuthKey>> {
+        db::list_auth_keys(&self.pool).await
+    }
+
+    async fn list_live_auth_keys(&self, user_id: &str) -> Result<Vec<AuthKey>> {
+        db::list_live_auth_keys(&self.pool, user_id).await
+    }
+
+    async fn list_auth_keys_since(&self, user_id: &str, since: DateTime<Utc>) -> Result<Vec<AuthKey>> {
+        db::list_auth_keys_since(&self.pool, user_id, since).await
+    }
+
+    async fn mark_auth_key_revoked(&self, id: &str) -> Result<()> {
+        db::mark_auth_key_revoked(&self.pool, id).await
+    }
+}
+
+#[async_trait]
+impl SessionStore for SqlStore {
+    async fn insert_session(&self, session: &Session) -> Result<()> {
+        db::insert_session(&self.pool, session).await
+    }
+
+    async fn find_session(&self, id: &str) -> Result<Option<Session>> {
+        db::find_session(&self.pool, id).await
+    }
+
+    async fn touch_session(&self, id: &str) -> Result<()> {
+        db::touch_session(&self.pool, id).await
+    }
+
+    async fn advance_refresh_generation(&self, id: &str, generation: i64) -> Result<bool> {
+        db::advance_refresh_generation(&self.pool, id, generation).await
+    }
+
+    async fn list_live_sessions(&self, user_id: &str) -> Result<Vec<Session>> {
+        db::list_live_sessions(&self.pool, user_id).await
+    }
+
+    async fn end_session(&self, id: &str) -> Result<bool> {
+        db::end_session(&self.pool, id).await
+    }
+
+    async fn end_user_sessions(&self, user_id: &str) -> Result<Vec<String>> {
+        db::end_user_sessions(&self.pool, user_id).await
+    }
+}
+
+#[async_trait]
+impl ApiTokenStore for SqlStore {
+    async fn insert_api_token(&self, token: &ApiToken, token_hash: &str) -> Result<()> {
+        db::insert_api_token(&self.pool, token, token_hash).await
+    }
+
+    async fn find_api_token(&self, id: &str) -> Result<Option<ApiToken>> {
+        db::find_api_token(&self.pool, id).await
+    }
+
+    async fn find_api_token_by_hash(&self, token_hash: &str) -> Result<Option<ApiToken>> {
+        db::find_api_token_by_hash(&self.pool, token_hash).await
+    }
+
+    async fn list_api_tokens(&self, user_id: &str) -> Result<Vec<ApiToken>> {
+        db::list_api_tokens(&self.pool, user_id).await
+    }
+
+    async fn touch_api_token(&self, id: &str) -> Result<()> {
+        db::touch_api_token(&self.pool, id).await
+    }
+
+    async fn revoke_api_token(&self, id: &str) -> Result<bool> {
+        db::revoke_api_token(&self.pool, id).await
+    }
+}
+
+#[async_trait]
+impl QuotaStore for SqlStore {
+    async fn find_user_quota(&self, user_id: &str) -> Result<Option<UserQuota>> {
+        db::find_user_quota(&self.pool, user_id).await
+    }
+
+    async fn set_user_quota(&self, quota: &UserQuota) -> Result<()> {
+        db::set_user_quota(&self.pool, quota).await
+    }
+
+    async fn clear_user_quota(&self, user_id: &str) -> Result<bool> {
+        db::clear_user_quota(&self.pool, user_id).await
+    }
+
+    async fn lock_user_quota(&self, user_id: &str) -> Result<QuotaLock> {
+        Ok(Box::new(db::lock_user_quota(&self.pool, user_id).await?))
+    }
+}
+
+#[async_trait]
+impl AuditStore for SqlStore {
+    async fn insert_audit_event(&self, event: &AuditEvent) -> Result<()> {
+        db::insert_audit_event(&self.pool, event).await
+    }
+
+    async fn list_audit_chain(&self, after_id: i64, limit: u32) -> Result<Vec<AuditEvent>> {
+        db::list_audit_chain(&self.pool, after_id, limit).await
+    }
+
+    async fn list_audit_events(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>> {
+        db::list_audit_events(&self.pool, query).await
+    }
+}
@@ -1,11 +1,14 @@
-use reqwest;
 use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use async_trait::async_trait;
 use tracing::{info, error, debug};
-use crate::config::get_config;
+use crate::config::{get_config, DeviceRemoval};
 use crate::models::{
     CreateAuthKeyRequest, CreateAuthKeyResponse, OAuthTokenResponse, CachedToken,
-    Capabilities, DeviceCapabilities, DeviceCreate,
+    Capabilities, DeviceCapabilities, DeviceCreate, Device, DeviceListResponse, DeviceAuthorizedRequest,
+    KeyProfile, DeviceInfo,
 };
+use crate::network::NetworkProvider;
 use std::sync::OnceLock;
 use tokio::sync::RwLock;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -94,9 +97,10 @@ async fn get_oauth_access_token() -> Result<String> {
 
 /// Generate a Tailscale auth key for a user
 ///
-/// This creates a reusable, preauthorized auth key that expires in 2 hours.
-/// The key allows the user to register their device on the tailnet as a non-ephemeral device.
-pub async fn generate_auth_key(user_email: &str) -> Result<String> {
+/// This creates a preauthorized auth key with the tags, expiry, reusability
+/// and ephemerality of the given key profile, described by the user and device it is for.
+/// The whole API response is returned so the caller can record the key's id and expiry.
+async fn generate_auth_key(user_email: &str, profile: &KeyProfile, device: &DeviceInfo) -> Result<CreateAuthKeyResponse> {
     let config = get_config();
 
     // Step 1: Exchange OAuth client credentials for access token
@@ -109,23 +113,18 @@ pub async fn generate_auth_key(user_email: &str) -> Result<String> {
         capabilities: Capabilities {
             devices: DeviceCapabilities {
                 create: DeviceCreate {
-                    reusable: true,
-                    ephemeral: false,
+                    reusable: profile.reusable,
+                    ephemeral: profile.ephemeral,
                     preauthorized: true,
-                    tags: config.tailscale.auth_key_tags.clone(),
+                    tags: profile.tags.clone(),
                 },
             },
         },
-        expiry_seconds: 7200,
-        // Tailscale requires alphanumeric + hyphen/space only in descriptions
-        description: Some(format!("Auth key for user {}",
-            user_email.chars()
-                .map(|c| if c.is_alphanumeric() { c } else { '-' })
-                .collect::<String>()
-        )),
+        expiry_seconds: profile.expiry_seconds,
+        description: Some(key_description(user_email, device)),
     };
 
-    debug!("Creating auth key with tags: {:?}", config.tailscale.auth_key_tags);
+    debug!("Creating auth key with profile {} and tags: {:?}", profile.name, profile.tags);
 
     // Make API request to create auth key using the access token
     let client = reqwest::Client::new();
@@ -151,7 +150,219 @@ pub async fn generate_auth_key(user_email: &str) -> Result<String> {
         .await
         .map_err(|e| anyhow!("Failed to parse Tailscale API response: {}", e))?;
 
-    info!("Successfully generated Tailscale auth key for user: {}", user_email);
+    info!("Successfully generated Tailscale auth key {} for user: {}", auth_key_response.id, user_email);
 
-    Ok(auth_key_response.key)
+    Ok(auth_key_response)
+}
+
+/// Longest description Tailscale accepts on an auth key
+const MAX_DESCRIPTION_LENGTH: usize = 50;
+
+/// Describe an auth key by the user and, when given, the device it is for
+///
+/// The user's email is never cut, so every key stays attributable: an email
+/// too long to fit is replaced by a stable identifier derived from it. The
+/// device details then share what room is left, each cut on its own.
+fn key_description(user_email: &str, device: &DeviceInfo) -> String {
+    let user = user_label(user_email);
+
+    if device.is_empty() {
+        let description = format!("Auth key for user {}", user);
+        return if description.len() <= MAX_DESCRIPTION_LENGTH { description } else { user };
+    }
+
+    let mut description = user;
+    for field in [device.hostname.as_deref(), device.os.as_deref(), device.purpose.as_deref()].into_iter().flatten() {
+        let room = MAX_DESCRIPTION_LENGTH.saturating_sub(description.len() + 1);
+        let field = sanitize_description(field).chars().take(room).collect::<String>();
+        let field = field.trim_matches('-');
+        if !field.is_empty() {
+            description.push(' ');
+            description.push_str(field);
+        }
+    }
+
+    description
+}
+
+/// The sanitized email, or `user-` and a hash of it when too long for a description
+fn user_label(user_email: &str) -> String {
+    let email = sanitize_description(user_email);
+    if email.len() <= MAX_DESCRIPTION_LENGTH {
+        email
+    } else {
+        format!("user-{}", &hex::encode(Sha256::digest(user_email.to_lowercase().as_bytes()))[..16])
+    }
+}
+
+// Tailscale requires alphanumeric + hyphen/space only in descriptions
+fn sanitize_description(value: &str) -> String {
+    value.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+/// Revoke a Tailscale auth key by id
+///
+/// A key Tailscale no longer knows about (already deleted or expired) counts
+/// as revoked.
+async fn revoke_auth_key(key_id: &str) -> Result<()> {
+    let config = get_config();
+    let access_token = get_oauth_access_token().await?;
+
+    let api_url = format!("{}/tailnet/-/keys/{}", config.tailscale.api_url, key_id);
+
+    let client = reqwest::Client::new();
+    let response = client
+        .delete(&api_url)
+        .header("Authorization", format!("Bearer {}", access_token))
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to send request to Tailscale API: {}", e))?;
+
+    let status = response.status();
+
+    if status == reqwest::StatusCode::NOT_FOUND {
+        debug!("Auth key {} was already gone from Tailscale", key_id);
+        return Ok(());
+    }
+
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        error!("Tailscale API error revoking key {} ({}): {}", key_id, status, error_text);
+        return Err(anyhow!("Tailscale API returned error {}: {}", status, error_text));
+    }
+
+    info!("Revoked Tailscale auth key {}", key_id);
+
+    Ok(())
+}
+
+/// List every device on the tailnet
+async fn list_devices() -> Result<Vec<Device>> {
+    let config = get_config();
+    let access_token = get_oauth_access_token().await?;
+
+    let api_url = format!("{}/tailnet/-/devices", config.tailscale.api_url);
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(&api_url)
+        .header("Authorization", format!("Bearer {}", access_token))
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to send request to Tailscale API: {}", e))?;
+
+    let status = response.status();
+
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        error!("Tailscale API error listing devices ({}): {}", status, error_text);
+        return Err(anyhow!("Tailscale API returned error {}: {}", status, error_text));
+    }
+
+    let device_list: DeviceListResponse = response
+        .json()
+        .await
+        .map_err(|e| anyhow!("Failed to parse Tailscale device list: {}", e))?;
+
+    Ok(device_list.devices)
+}
+
+/// Delete or de-authorize a device on the tailnet
+async fn remove_device(device_id: &str, removal: DeviceRemoval) -> Result<()> {
+    let config = get_config();
+    let access_token = get_oauth_access_token().await?;
+
+    let client = reqwest::Client::new();
+    let request = match removal {
+        DeviceRemoval::Delete => client
+            .delete(format!("{}/device/{}", config.tailscale.api_url, device_id)),
+        DeviceRemoval::Deauthorize => client
+            .post(format!("{}/device/{}/authorized", config.tailscale.api_url, device_id))
+            .json(&DeviceAuthorizedRequest { authorized: false }),
+    };
+
+    let response = request
+        .header("Authorization", format!("Bearer {}", access_token))
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to send request to Tailscale API: {}", e))?;
+
+    let status = response.status();
+
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        error!("Tailscale API error removing device {} ({}): {}", device_id, status, error_text);
+        return Err(anyhow!("Tailscale API returned error {}: {}", status, error_text));
+    }
+
+    info!("Removed device {} ({:?})", device_id, removal);
+
+    Ok(())
+}
+
+/// Network provider backed by the Tailscale API
+pub struct TailscaleProvider;
+
+#[async_trait]
+impl NetworkProvider for TailscaleProvider {
+    async fn generate_auth_key(&self, user_email: &str, profile: &KeyProfile, device: &DeviceInfo) -> Result<CreateAuthKeyResponse> {
+        generate_auth_key(user_email, profile, device).await
+    }
+
+    async fn revoke_auth_key(&self, key_id: &str) -> Result<()> {
+        revoke_auth_key(key_id).await
+    }
+
+    async fn list_devices(&self) -> Result<Vec<Device>> {
+        list_devices().await
+    }
+
+    async fn remove_device(&self, device_id: &str, removal: DeviceRemoval) -> Result<()> {
+        remove_device(device_id, removal).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(hostname: &str, os: &str, purpose: &str) -> DeviceInfo {
+        DeviceInfo {
+            hostname: Some(hostname.to_string()),
+            os: Some(os.to_string()),
+            purpose: Some(purpose.to_string()),
+        }
+    }
+
+    #[test]
+    fn description_names_the_user_and_device() {
+        assert_eq!(
+            key_description("alice@example.com", &device("build-01", "Ubuntu 24.04", "CI")),
+            "alice-example-com build-01 Ubuntu-24-04 CI"
+        );
+        assert_eq!(key_description("alice@example.com", &DeviceInfo::default()), "Auth key for user alice-example-com");
+    }
+
+    #[test]
+    fn device_details_are_cut_but_the_email_is_not() {
+        let email = "a.very.long.name@engineering.example.com";
+        let description = key_description(email, &device("build-runner-01", "Ubuntu 24.04", "CI runner for the web app"));
+
+        assert!(description.len() <= MAX_DESCRIPTION_LENGTH);
+        assert_eq!(description, "a-very-long-name-engineering-example-com build-run");
+        assert_eq!(key_description(email, &DeviceInfo::default()), "a-very-long-name-engineering-example-com");
+    }
+
+    #[test]
+    fn overlong_emails_become_a_stable_identifier() {
+        let email = format!("{}@example.com", "x".repeat(60));
+        let description = key_description(&email, &device("build-01", "Ubuntu 24.04", "CI"));
+
+        assert!(description.starts_with("user-"));
+        assert!(description.len() <= MAX_DESCRIPTION_LENGTH);
+        assert!(description.ends_with(" build-01 Ubuntu-24-04 CI"));
+        assert_eq!(user_label(&email), user_label(&email.to_uppercase()));
+    }
 }